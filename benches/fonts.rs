@@ -0,0 +1,89 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use lopdf::{dictionary, Document, Object, Stream};
+
+const FONTS: usize = 60;
+
+// A ToUnicode CMap that maps every byte, as subset fonts often carry
+fn cmap(font: usize) -> Vec<u8> {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+        1 begincodespacerange <00> <FF> endcodespacerange ",
+    );
+    for chunk in (0..256usize).collect::<Vec<_>>().chunks(100) {
+        cmap += &format!("{} beginbfchar ", chunk.len());
+        for code in chunk {
+            cmap += &format!("<{:02X}> <{:04X}> ", code, 0x4e00 + font * 256 + code);
+        }
+        cmap += "endbfchar ";
+    }
+    cmap += "endcmap end end";
+    cmap.into_bytes()
+}
+
+// A page that selects each of FONTS fonts and shows `text` with it
+fn many_fonts_pdf(text: &str) -> Document {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let mut fonts = lopdf::Dictionary::new();
+    let mut content = String::from("BT ");
+    for font in 0..FONTS {
+        let widths: Vec<Object> = vec![500.into(); 256];
+        let to_unicode = doc.add_object(Stream::new(dictionary! {}, cmap(font)));
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => format!("Subset{}", font),
+            "FirstChar" => 0,
+            "LastChar" => 255,
+            "Widths" => widths,
+            "ToUnicode" => to_unicode,
+        });
+        fonts.set(format!("F{}", font), font_id);
+        content += &format!("/F{} 10 Tf 50 {} Td ({}) Tj ", font, 700 - font * 10, text);
+    }
+    content += "ET";
+    let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content_id,
+        "Resources" => dictionary! { "Font" => fonts },
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => 1,
+            "Kids" => vec![page_id.into()],
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc
+}
+
+fn many_fonts(c: &mut Criterion) {
+    // fonts that only draw a couple of glyphs, and ones that are selected but draw nothing,
+    // whose ToUnicode maps never need parsing
+    for (name, text) in [("used", "AB"), ("unused", "")] {
+        let doc = many_fonts_pdf(text);
+        c.bench_function(
+            &format!("page with {} briefly {} fonts", FONTS, name),
+            |b| {
+                b.iter(|| {
+                    let mut text = String::new();
+                    let mut output = ara_yaaaay::output::PlainTextOutput::new(&mut text);
+                    ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+                    text
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, many_fonts);
+criterion_main!(benches);
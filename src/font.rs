@@ -5,11 +5,12 @@ use crate::utils::{maybe_deref, maybe_get_obj, pdf_to_utf8, to_utf8, PDFDocEncod
 use crate::zapfglyphnames;
 use crate::{
     as_num, get, get_contents, get_name_string, maybe_get, maybe_get_array, maybe_get_name,
-    maybe_get_name_string, ByteMapping, CIDRange, CodeRange,
+    maybe_get_name_string, ByteMapping, CIDRange, CodeRange, Transform, Transform2D,
 };
-use lopdf::{Dictionary, Document, Object};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::cell::OnceCell;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
 use std::rc::Rc;
@@ -26,30 +27,160 @@ macro_rules! dlog {
 struct PdfSimpleFont<'a> {
     font: &'a Dictionary,
     doc: &'a Document,
-    encoding: Option<Vec<u16>>,
-    unicode_map: Option<HashMap<u32, String>>,
+    encoding: Option<Vec<String>>,
+    unicode_map: OnceCell<Option<HashMap<u32, String>>>,
+    // glyph names from /Differences that fill in gaps of the ToUnicode map
+    unicode_overrides: Vec<(CharCode, String, String)>,
     widths: HashMap<CharCode, f64>, // should probably just use i32 here
     missing_width: f64,
+    fallback: GlyphFallback<'a>,
 }
 
 #[derive(Clone)]
 struct PdfType3Font<'a> {
     font: &'a Dictionary,
     doc: &'a Document,
-    encoding: Option<Vec<u16>>,
-    unicode_map: Option<HashMap<u32, String>>,
+    encoding: Option<Vec<String>>,
+    unicode_map: OnceCell<Option<HashMap<u32, String>>>,
     widths: HashMap<CharCode, f64>, // should probably just use i32 here
+    font_matrix: Transform,
+    /// codes whose names in /Differences aren't glyph names we know, like /g3
+    unknown_names: HashSet<CharCode>,
+    fallback: GlyphFallback<'a>,
 }
 
-pub fn make_font<'a>(doc: &'a Document, font: &'a Dictionary) -> Rc<dyn PdfFont + 'a> {
+/// Recovers the text of glyphs that a font gives us no way to decode, e.g. subsetted fonts
+/// that have neither a ToUnicode map nor a usable encoding.
+///
+/// `decode_char` only consults the resolver as a last resort. The intended use is a database of
+/// glyph hashes: parse the outline of `code` out of `font_file`, hash it and look the hash up in
+/// a table built from fonts where the mapping to unicode is known.
+pub trait GlyphResolver {
+    fn resolve(&self, glyph: &Glyph) -> Option<String>;
+}
+
+/// A glyph that couldn't be decoded
+#[derive(Debug)]
+pub struct Glyph<'a> {
+    pub base_font: &'a str,
+    pub code: u32,
+    /// The decompressed embedded font program (FontFile, FontFile2 or FontFile3) if there is one,
+    /// or for Type3 fonts the decompressed glyph procedure that draws `code`
+    pub font_file: Option<&'a [u8]>,
+}
+
+/// A resolver that never recovers anything, which is the same as not having a resolver
+pub struct NoGlyphResolver;
+
+impl GlyphResolver for NoGlyphResolver {
+    fn resolve(&self, _glyph: &Glyph) -> Option<String> {
+        None
+    }
+}
+
+// What a font needs to hand an undecodable glyph to a GlyphResolver
+#[derive(Clone)]
+struct GlyphFallback<'a> {
+    doc: &'a Document,
+    // the dictionary holding the FontDescriptor
+    font: &'a Dictionary,
+    resolver: Option<&'a dyn GlyphResolver>,
+    font_file: OnceCell<Option<Vec<u8>>>,
+}
+
+impl<'a> GlyphFallback<'a> {
+    fn new(
+        doc: &'a Document,
+        font: &'a Dictionary,
+        resolver: Option<&'a dyn GlyphResolver>,
+    ) -> GlyphFallback<'a> {
+        GlyphFallback {
+            doc,
+            font,
+            resolver,
+            font_file: OnceCell::new(),
+        }
+    }
+
+    fn resolve(&self, code: CharCode) -> Option<String> {
+        self.resolver?;
+        let font_file = self
+            .font_file
+            .get_or_init(|| {
+                let descriptor: &Dictionary = maybe_get(self.doc, self.font, b"FontDescriptor")?;
+                [&b"FontFile"[..], &b"FontFile2"[..], &b"FontFile3"[..]]
+                    .iter()
+                    .find_map(|k| maybe_get::<&Stream>(self.doc, descriptor, k))
+                    .map(get_contents)
+            })
+            .as_deref();
+        self.resolve_with(code, font_file)
+    }
+
+    // Type3 fonts have no font program, so they hand over the glyph procedure instead
+    fn resolve_with(&self, code: CharCode, font_file: Option<&[u8]>) -> Option<String> {
+        let resolver = self.resolver?;
+        let base_font = maybe_get_name_string(self.doc, self.font, b"BaseFont").unwrap_or_default();
+        resolver.resolve(&Glyph {
+            base_font: &base_font,
+            code,
+            font_file,
+        })
+    }
+}
+
+/// Whether a font is bold or italic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FontStyle {
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// Guess the style of `font` from its /BaseFont, like "Arial-BoldItalicMT", and its
+/// FontDescriptor's /Flags, /FontWeight, /StemV and /ItalicAngle
+pub fn font_style(doc: &Document, font: &Dictionary) -> FontStyle {
+    let name = maybe_get_name_string(doc, font, b"BaseFont")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let mut style = FontStyle {
+        bold: ["bold", "black", "heavy", "demi"]
+            .iter()
+            .any(|w| name.contains(w)),
+        italic: name.contains("italic") || name.contains("oblique"),
+    };
+    // the descriptor of a composite font is on its descendant
+    let descendant = maybe_get_array(doc, font, b"DescendantFonts")
+        .and_then(|d| d.first())
+        .and_then(|d| maybe_deref(doc, d).as_dict().ok());
+    let descriptor: Option<&Dictionary> =
+        maybe_get(doc, descendant.unwrap_or(font), b"FontDescriptor");
+    if let Some(descriptor) = descriptor {
+        let flags = maybe_get::<i64>(doc, descriptor, b"Flags").unwrap_or(0);
+        let weight = maybe_get::<f64>(doc, descriptor, b"FontWeight").unwrap_or(0.);
+        // regular weights have stems around 80 and bold ones from around 120
+        let stem_v = maybe_get::<f64>(doc, descriptor, b"StemV").unwrap_or(0.);
+        let italic_angle = maybe_get::<f64>(doc, descriptor, b"ItalicAngle").unwrap_or(0.);
+        // bit 7 is Italic and bit 19 is ForceBold
+        style.bold |= flags & (1 << 18) != 0 || weight >= 600. || stem_v >= 120.;
+        style.italic |= flags & (1 << 6) != 0 || italic_angle != 0.;
+    }
+    style
+}
+
+pub fn make_font<'a>(
+    doc: &'a Document,
+    font: &'a Dictionary,
+    resolver: Option<&'a dyn GlyphResolver>,
+    substitutes: Option<&'a FontSubstitutes>,
+) -> Rc<dyn PdfFont + 'a> {
     let subtype = get_name_string(doc, font, b"Subtype");
     dlog!("MakeFont({})", subtype);
     if subtype == "Type0" {
-        Rc::new(PdfCIDFont::new(doc, font))
+        Rc::new(PdfCIDFont::new(doc, font, resolver))
     } else if subtype == "Type3" {
-        Rc::new(PdfType3Font::new(doc, font))
+        Rc::new(PdfType3Font::new(doc, font, resolver))
     } else {
-        Rc::new(PdfSimpleFont::new(doc, font))
+        Rc::new(PdfSimpleFont::new(doc, font, resolver, substitutes))
     }
 }
 
@@ -73,26 +204,165 @@ pub fn is_core_font(name: &str) -> bool {
     }
 }
 
-pub fn encoding_to_unicode_table(name: &[u8]) -> Vec<u16> {
+/// Standard fonts whose metrics stand in for fonts that have neither widths nor an embedded font
+/// program, given to processing with `OutputOptions::font_substitutes`. These take priority over
+/// the built in substitutes.
+#[derive(Debug, Clone, Default)]
+pub struct FontSubstitutes(HashMap<String, String>);
+
+impl FontSubstitutes {
+    pub fn new() -> FontSubstitutes {
+        FontSubstitutes::default()
+    }
+
+    /// Use the metrics of the standard font `core_font` (e.g. "Helvetica") for fonts named
+    /// `font_name`. Returns false, without adding anything, if `core_font` isn't one of the 14
+    /// standard fonts.
+    pub fn insert(&mut self, font_name: &str, core_font: &str) -> bool {
+        if !is_core_font(core_font) {
+            return false;
+        }
+        self.0.insert(font_name.to_owned(), core_font.to_owned());
+        true
+    }
+}
+
+// The name of a font without the tag of a subset, which are named like ABCDEF+Arial
+pub(crate) fn strip_subset_tag(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((tag, rest)) if tag.len() == 6 && tag.chars().all(|c| c.is_ascii_uppercase()) => rest,
+        _ => name,
+    }
+}
+
+// The standard font whose metrics stand in for the non-standard font `name`. Common metric
+// compatible fonts map to their equivalents and anything else gets Helvetica.
+fn substitute_font(name: &str, substitutes: Option<&FontSubstitutes>) -> String {
+    let name = strip_subset_tag(name);
+    if let Some(core_font) = substitutes.and_then(|s| s.0.get(name)) {
+        return core_font.clone();
+    }
+    let lower = name.to_ascii_lowercase().replace(' ', "");
+    let (family, style) = lower.split_once([',', '-']).unwrap_or((&lower, ""));
+    let family = family.trim_end_matches("psmt").trim_end_matches("mt");
+    let bold = style.contains("bold");
+    let italic = style.contains("italic") || style.contains("oblique");
+    let (regular, bold_name, italic_name, bold_italic) = match family {
+        "timesnewroman" | "times" | "liberationserif" | "nimbusroman" => (
+            "Times-Roman",
+            "Times-Bold",
+            "Times-Italic",
+            "Times-BoldItalic",
+        ),
+        "couriernew" | "courier" | "liberationmono" | "nimbusmono" => (
+            "Courier",
+            "Courier-Bold",
+            "Courier-Oblique",
+            "Courier-BoldOblique",
+        ),
+        _ => {
+            if !matches!(
+                family,
+                "arial" | "helvetica" | "liberationsans" | "nimbussans"
+            ) {
+                log::warn!("using Helvetica metrics for {}", name);
+            }
+            (
+                "Helvetica",
+                "Helvetica-Bold",
+                "Helvetica-Oblique",
+                "Helvetica-BoldOblique",
+            )
+        }
+    };
+    match (bold, italic) {
+        (false, false) => regular,
+        (true, false) => bold_name,
+        (false, true) => italic_name,
+        (true, true) => bold_italic,
+    }
+    .to_owned()
+}
+
+// The text of a UTF-16 code unit from a glyph list or encoding table
+fn unicode_string(unicode: u16) -> String {
+    String::from_utf16_lossy(&[unicode])
+}
+
+fn pdf_doc_encoding_table() -> Vec<String> {
+    PDFDocEncoding.iter().map(|&u| unicode_string(u)).collect()
+}
+
+pub fn encoding_to_unicode_table(name: &[u8]) -> Vec<String> {
     let encoding = match &name[..] {
         b"MacRomanEncoding" => encodings::MAC_ROMAN_ENCODING,
         b"MacExpertEncoding" => encodings::MAC_EXPERT_ENCODING,
         b"WinAnsiEncoding" => encodings::WIN_ANSI_ENCODING,
         _ => panic!("unexpected encoding {:?}", pdf_to_utf8(name)),
     };
-    let encoding_table = encoding
+    let mut encoding_table: Vec<String> = encoding
         .iter()
         .map(|x| {
             if let &Some(x) = x {
-                glyphnames::name_to_unicode(x).unwrap()
+                unicode_string(glyphnames::name_to_unicode(x).unwrap())
             } else {
-                0
+                unicode_string(0)
             }
         })
         .collect();
+    if name == b"MacRomanEncoding" {
+        // the glyph list maps /Omega to the ohm sign but Mac OS Roman's 0xBD is the Greek letter
+        encoding_table[0xBD] = unicode_string(0x03A9);
+    }
     encoding_table
 }
 
+// The glyph names of a named simple encoding
+fn encoding_glyph_names(name: &[u8]) -> Option<&'static [Option<&'static str>; 256]> {
+    match name {
+        b"MacRomanEncoding" => Some(&encodings::MAC_ROMAN_ENCODING),
+        b"MacExpertEncoding" => Some(&encodings::MAC_EXPERT_ENCODING),
+        b"WinAnsiEncoding" => Some(&encodings::WIN_ANSI_ENCODING),
+        b"StandardEncoding" => Some(&encodings::STANDARD_ENCODING),
+        _ => None,
+    }
+}
+
+/// The glyph procedure from /CharProcs that draws `code` in the Type3 font `font`
+pub(crate) fn type3_char_proc<'a>(
+    doc: &'a Document,
+    font: &'a Dictionary,
+    code: CharCode,
+) -> Option<&'a Stream> {
+    let (base_encoding, differences) = match maybe_get_obj(doc, font, b"Encoding")? {
+        Object::Name(name) => (Some(&name[..]), None),
+        Object::Dictionary(encoding) => (
+            maybe_get_name(doc, encoding, b"BaseEncoding"),
+            maybe_get_array(doc, encoding, b"Differences"),
+        ),
+        _ => return None,
+    };
+    let mut name = base_encoding
+        .and_then(encoding_glyph_names)
+        .and_then(|names| *names.get(code as usize)?)
+        .map(|name| name.as_bytes());
+    let mut next_code = 0;
+    for o in differences.into_iter().flatten() {
+        match o {
+            &Object::Integer(i) => next_code = i,
+            Object::Name(n) => {
+                if next_code == code as i64 {
+                    name = Some(n);
+                }
+                next_code += 1;
+            }
+            _ => {}
+        }
+    }
+    let char_procs: &Dictionary = maybe_get(doc, font, b"CharProcs")?;
+    maybe_get(doc, char_procs, name?)
+}
+
 /* "Glyphs in the font are selected by single-byte character codes obtained from a string that
     is shown by the text-showing operators. Logically, these codes index into a table of 256
     glyphs; the mapping from codes to glyphs is called the font’s encoding. Each font program
@@ -100,7 +370,12 @@ pub fn encoding_to_unicode_table(name: &[u8]) -> Vec<u16> {
     described in Section 5.5.5, “Character Encoding.”
 */
 impl<'a> PdfSimpleFont<'a> {
-    pub fn new(doc: &'a Document, font: &'a Dictionary) -> PdfSimpleFont<'a> {
+    pub fn new(
+        doc: &'a Document,
+        font: &'a Dictionary,
+        resolver: Option<&'a dyn GlyphResolver>,
+        substitutes: Option<&FontSubstitutes>,
+    ) -> PdfSimpleFont<'a> {
         let base_name = get_name_string(doc, font, b"BaseFont");
         let subtype = get_name_string(doc, font, b"Subtype");
 
@@ -162,7 +437,9 @@ impl<'a> PdfSimpleFont<'a> {
             //dlog!("charset {:?}", charset);
         }
 
-        let mut unicode_map = get_unicode_map(doc, font);
+        // The ToUnicode CMap is only parsed once we need to decode a character. Until then we
+        // remember the entries that the glyph names in /Differences would add to it.
+        let mut unicode_overrides = Vec::new();
 
         let mut encoding_table = None;
         match encoding {
@@ -177,7 +454,7 @@ impl<'a> PdfSimpleFont<'a> {
                         dlog!("BaseEncoding {:?}", base_encoding);
                         encoding_to_unicode_table(base_encoding)
                     } else {
-                        Vec::from(PDFDocEncoding)
+                        pdf_doc_encoding_table()
                     };
                 let differences = maybe_get_array(doc, encoding, b"Differences");
                 if let Some(differences) = differences {
@@ -191,68 +468,40 @@ impl<'a> PdfSimpleFont<'a> {
                             }
                             &Object::Name(ref n) => {
                                 let name = pdf_to_utf8(&n);
-                                // XXX: names of Type1 fonts can map to arbitrary strings instead of real
-                                // unicode names, so we should probably handle this differently
-                                let unicode = glyphnames::name_to_unicode(&name);
-                                if let Some(unicode) = unicode {
-                                    table[code as usize] = unicode;
-                                    if let Some(ref mut unicode_map) = unicode_map {
-                                        let be = [unicode];
-                                        match unicode_map.entry(code as u32) {
-                                            // If there's a unicode table entry missing use one based on the name
-                                            Entry::Vacant(v) => {
-                                                v.insert(String::from_utf16(&be).unwrap());
-                                            }
-                                            Entry::Occupied(e) => {
-                                                if e.get() != &String::from_utf16(&be).unwrap() {
-                                                    let normal_match =
-                                                        e.get().nfkc().eq(String::from_utf16(&be)
-                                                            .unwrap()
-                                                            .nfkc());
-                                                    println!(
-                                                        "Unicode mismatch {} {} {:?} {:?} {:?}",
-                                                        normal_match,
-                                                        name,
-                                                        e.get(),
-                                                        String::from_utf16(&be),
-                                                        be
-                                                    );
-                                                }
-                                            }
-                                        }
-                                    }
+                                // malformed /Differences can use codes that don't fit in a single byte
+                                if code < 0 || code as usize >= table.len() {
+                                    log::warn!(
+                                        "ignoring out of range code {} for glyph '{}' in /Differences",
+                                        code, name
+                                    );
+                                    code += 1;
+                                    continue;
+                                }
+                                let unicode = glyph_name_to_string(&name);
+                                if let Some(unicode) = &unicode {
+                                    table[code as usize] = unicode.clone();
+                                    // If there's a unicode table entry missing use one based on the name
+                                    unicode_overrides.push((
+                                        code as CharCode,
+                                        name.clone(),
+                                        unicode.clone(),
+                                    ));
+                                } else if base_name.contains("FontAwesome") {
+                                    // the fontawesome tex package will use glyph names that don't have a corresponding unicode
+                                    // code point, so we'll use an empty string instead. See issue #76
+                                    unicode_overrides.push((
+                                        code as CharCode,
+                                        name.clone(),
+                                        "".to_owned(),
+                                    ));
                                 } else {
-                                    match unicode_map {
-                                        Some(ref mut unicode_map)
-                                            if base_name.contains("FontAwesome") =>
-                                        {
-                                            // the fontawesome tex package will use glyph names that don't have a corresponding unicode
-                                            // code point, so we'll use an empty string instead. See issue #76
-                                            match unicode_map.entry(code as u32) {
-                                                Entry::Vacant(v) => {
-                                                    v.insert("".to_owned());
-                                                }
-                                                Entry::Occupied(e) => {
-                                                    panic!("unexpected entry in unicode map")
-                                                }
-                                            }
-                                        }
-                                        _ => {
-                                            println!(
-                                                "unknown glyph name '{}' for font {}",
-                                                name, base_name
-                                            );
-                                        }
-                                    }
+                                    log::warn!(
+                                        "unknown glyph name '{}' for font {}",
+                                        name,
+                                        base_name
+                                    );
                                 }
                                 dlog!("{} = {} ({:?})", code, name, unicode);
-                                if let Some(ref mut unicode_map) = unicode_map {
-                                    // The unicode map might not have the code in it, but the code might
-                                    // not be used so we don't want to panic here.
-                                    // An example of this is the 'suppress' character in the TeX Latin Modern font.
-                                    // This shows up in https://arxiv.org/pdf/2405.01295v1.pdf
-                                    dlog!("{} {:?}", code, unicode_map.get(&(code as u32)));
-                                }
                                 code += 1;
                             }
                             _ => {
@@ -272,30 +521,19 @@ impl<'a> PdfSimpleFont<'a> {
             }
             None => {
                 if let Some(type1_encoding) = type1_encoding {
-                    let mut table = Vec::from(PDFDocEncoding);
+                    let mut table = pdf_doc_encoding_table();
                     dlog!("type1encoding");
                     for (code, name) in type1_encoding {
-                        let unicode = glyphnames::name_to_unicode(&pdf_to_utf8(&name));
-                        if let Some(unicode) = unicode {
+                        let name = pdf_to_utf8(&name);
+                        if let Some(unicode) = glyph_name_to_string(&name) {
                             table[code as usize] = unicode;
                         } else {
-                            dlog!("unknown character {}", pdf_to_utf8(&name));
+                            dlog!("unknown character {}", name);
                         }
                     }
                     encoding_table = Some(table)
                 } else if subtype == "TrueType" {
-                    encoding_table = Some(
-                        encodings::WIN_ANSI_ENCODING
-                            .iter()
-                            .map(|x| {
-                                if let &Some(x) = x {
-                                    glyphnames::name_to_unicode(x).unwrap()
-                                } else {
-                                    0
-                                }
-                            })
-                            .collect(),
-                    );
+                    encoding_table = Some(encoding_to_unicode_table(b"WinAnsiEncoding"));
                 }
             }
             _ => {
@@ -332,13 +570,20 @@ impl<'a> PdfSimpleFont<'a> {
                 i += 1;
             }
             assert_eq!(first_char + i - 1, last_char);
-        } else if is_core_font(&base_name) {
+        } else {
+            // fonts that aren't one of the standard 14 and don't tell us their widths borrow
+            // the metrics of a similar standard font
+            let metrics_name = if is_core_font(&base_name) {
+                base_name.clone()
+            } else {
+                substitute_font(&base_name, substitutes)
+            };
             for font_metrics in core_fonts::metrics().iter() {
-                if font_metrics.0 == base_name {
+                if font_metrics.0 == metrics_name {
                     if let Some(ref encoding) = encoding_table {
                         dlog!("has encoding");
                         for w in font_metrics.2 {
-                            let c = glyphnames::name_to_unicode(w.2).unwrap();
+                            let c = unicode_string(glyphnames::name_to_unicode(w.2).unwrap());
                             for i in 0..encoding.len() {
                                 if encoding[i] == c {
                                     width_map.insert(i as CharCode, w.1 as f64);
@@ -350,17 +595,18 @@ impl<'a> PdfSimpleFont<'a> {
                         // of the character names. We should probably verify that this produces the
                         // same result.
 
-                        let mut table = vec![0; 256];
+                        let mut table = vec![unicode_string(0); 256];
                         for w in font_metrics.2 {
                             dlog!("{} {}", w.0, w.2);
                             // -1 is "not encoded"
                             if w.0 != -1 {
-                                table[w.0 as usize] = if base_name == "ZapfDingbats" {
-                                    zapfglyphnames::zapfdigbats_names_to_unicode(w.2)
-                                        .unwrap_or_else(|| panic!("bad name {:?}", w))
-                                } else {
-                                    glyphnames::name_to_unicode(w.2).unwrap()
-                                }
+                                table[w.0 as usize] =
+                                    unicode_string(if metrics_name == "ZapfDingbats" {
+                                        zapfglyphnames::zapfdigbats_names_to_unicode(w.2)
+                                            .unwrap_or_else(|| panic!("bad name {:?}", w))
+                                    } else {
+                                        glyphnames::name_to_unicode(w.2).unwrap()
+                                    })
                             }
                         }
 
@@ -382,8 +628,6 @@ impl<'a> PdfSimpleFont<'a> {
                     // assert!(maybe_get_obj(doc, font, b"Widths").is_none());
                 }
             }
-        } else {
-            panic!("no widths");
         }
 
         let missing_width = get::<Option<f64>>(doc, font, b"MissingWidth").unwrap_or(0.);
@@ -393,10 +637,42 @@ impl<'a> PdfSimpleFont<'a> {
             widths: width_map,
             encoding: encoding_table,
             missing_width,
-            unicode_map,
+            unicode_map: OnceCell::new(),
+            unicode_overrides,
+            fallback: GlyphFallback::new(doc, font, resolver),
         }
     }
 
+    fn unicode_map(&self) -> Option<&HashMap<u32, String>> {
+        self.unicode_map
+            .get_or_init(|| {
+                let mut unicode_map = get_unicode_map(self.doc, self.font);
+                if let Some(ref mut unicode_map) = unicode_map {
+                    for (code, name, unicode) in &self.unicode_overrides {
+                        match unicode_map.entry(*code) {
+                            Entry::Vacant(v) => {
+                                v.insert(unicode.clone());
+                            }
+                            Entry::Occupied(e) => {
+                                if e.get() != unicode {
+                                    let normal_match = e.get().nfkc().eq(unicode.nfkc());
+                                    log::debug!(
+                                        "Unicode mismatch {} {} {:?} {:?}",
+                                        normal_match,
+                                        name,
+                                        e.get(),
+                                        unicode
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+                unicode_map
+            })
+            .as_ref()
+    }
+
     #[allow(dead_code)]
     fn get_type(&self) -> String {
         get_name_string(self.doc, self.font, b"Type")
@@ -433,11 +709,15 @@ impl<'a> PdfSimpleFont<'a> {
 }
 
 impl<'a> PdfType3Font<'a> {
-    pub fn new(doc: &'a Document, font: &'a Dictionary) -> PdfType3Font<'a> {
-        let unicode_map = get_unicode_map(doc, font);
+    pub fn new(
+        doc: &'a Document,
+        font: &'a Dictionary,
+        resolver: Option<&'a dyn GlyphResolver>,
+    ) -> PdfType3Font<'a> {
         let encoding: Option<&Object> = get(doc, font, b"Encoding");
 
         let encoding_table;
+        let mut unknown_names = HashSet::new();
         match encoding {
             Some(&Object::Name(ref encoding_name)) => {
                 dlog!("encoding {:?}", pdf_to_utf8(encoding_name));
@@ -450,7 +730,7 @@ impl<'a> PdfType3Font<'a> {
                         dlog!("BaseEncoding {:?}", base_encoding);
                         encoding_to_unicode_table(base_encoding)
                     } else {
-                        Vec::from(PDFDocEncoding)
+                        pdf_doc_encoding_table()
                     };
                 let differences = maybe_get_array(doc, encoding, b"Differences");
                 if let Some(differences) = differences {
@@ -463,16 +743,23 @@ impl<'a> PdfType3Font<'a> {
                             }
                             &Object::Name(ref n) => {
                                 let name = pdf_to_utf8(&n);
-                                // XXX: names of Type1 fonts can map to arbitrary strings instead of real
-                                // unicode names, so we should probably handle this differently
-                                let unicode = glyphnames::name_to_unicode(&name);
-                                if let Some(unicode) = unicode {
-                                    table[code as usize] = unicode;
+                                // malformed /Differences can use codes that don't fit in a single byte
+                                if code < 0 || code as usize >= table.len() {
+                                    log::warn!(
+                                        "ignoring out of range code {} for glyph '{}' in /Differences",
+                                        code, name
+                                    );
+                                    code += 1;
+                                    continue;
                                 }
-                                dlog!("{} = {} ({:?})", code, name, unicode);
-                                if let Some(ref unicode_map) = unicode_map {
-                                    dlog!("{} {:?}", code, unicode_map.get(&(code as u32)));
+                                let unicode = glyph_name_to_string(&name);
+                                match &unicode {
+                                    Some(unicode) => table[code as usize] = unicode.clone(),
+                                    None => {
+                                        unknown_names.insert(code as CharCode);
+                                    }
                                 }
+                                dlog!("{} = {} ({:?})", code, name, unicode);
                                 code += 1;
                             }
                             _ => {
@@ -515,17 +802,33 @@ impl<'a> PdfType3Font<'a> {
             i += 1;
         }
         assert_eq!(first_char + i - 1, last_char);
+        let font_matrix = match get::<Option<Vec<f64>>>(doc, font, b"FontMatrix").as_deref() {
+            Some(&[a, b, c, d, e, f]) => Transform2D::row_major(a, b, c, d, e, f),
+            m => {
+                log::warn!("bad FontMatrix {:?} in Type3 font, using the default", m);
+                Transform2D::create_scale(0.001, 0.001)
+            }
+        };
         PdfType3Font {
             doc,
             font,
             widths: width_map,
             encoding: encoding_table,
-            unicode_map,
+            unicode_map: OnceCell::new(),
+            font_matrix,
+            unknown_names,
+            fallback: GlyphFallback::new(doc, font, resolver),
         }
     }
+
+    fn unicode_map(&self) -> Option<&HashMap<u32, String>> {
+        self.unicode_map
+            .get_or_init(|| get_unicode_map(self.doc, self.font))
+            .as_ref()
+    }
 }
 
-type CharCode = u32;
+pub(crate) type CharCode = u32;
 
 pub struct PdfFontIter<'a> {
     i: Iter<'a, u8>,
@@ -543,6 +846,19 @@ pub trait PdfFont: Debug {
     fn get_width(&self, id: CharCode) -> f64;
     fn next_char(&self, iter: &mut Iter<u8>) -> Option<(CharCode, u8)>;
     fn decode_char(&self, char: CharCode) -> String;
+    /// The matrix that maps glyph space to text space. Only Type3 fonts have one,
+    /// every other font scales glyph space by 1/1000.
+    fn font_matrix(&self) -> Option<Transform> {
+        None
+    }
+    /// Every code that `decode_char` knows how to decode along with what it decodes to,
+    /// or None if the font has nothing to decode with
+    fn unicode_table(&self) -> Option<HashMap<CharCode, String>>;
+    /// The default vertical metrics of a CID font, from its /DW2 as [vy w1y]. Other fonts
+    /// can't be written vertically and have none.
+    fn vertical_default(&self) -> Option<[f64; 2]> {
+        None
+    }
 
     /*fn char_codes<'a>(&'a self, chars: &'a [u8]) -> PdfFontIter {
         let p = self;
@@ -594,37 +910,54 @@ impl<'a> PdfFont for PdfSimpleFont<'a> {
     }
     fn decode_char(&self, char: CharCode) -> String {
         let slice = [char as u8];
-        if let Some(ref unicode_map) = self.unicode_map {
+        if let Some(unicode_map) = self.unicode_map() {
             let s = unicode_map.get(&char);
             let s = match s {
                 None => {
-                    println!(
+                    log::warn!(
                         "missing char {:?} in unicode map {:?} for {:?}",
-                        char, unicode_map, self.font
+                        char,
+                        unicode_map,
+                        self.font
                     );
                     // some pdf's like http://arxiv.org/pdf/2312.00064v1 are missing entries in their unicode map but do have
                     // entries in the encoding.
-                    let encoding = self
-                        .encoding
-                        .as_ref()
-                        .map(|x| &x[..])
-                        .expect("missing unicode map and encoding");
-                    let s = to_utf8(encoding, &slice);
-                    println!("falling back to encoding {} -> {:?}", char, s);
-                    s
+                    match self.encoding {
+                        Some(ref encoding) => {
+                            let s = encoding[char as usize].clone();
+                            log::warn!("falling back to encoding {} -> {:?}", char, s);
+                            s
+                        }
+                        None => match self.fallback.resolve(char) {
+                            Some(s) => s,
+                            None => to_utf8(PDFDocEncoding, &slice),
+                        },
+                    }
                 }
                 Some(s) => s.clone(),
             };
             return s;
         }
-        let encoding = self
-            .encoding
-            .as_ref()
-            .map(|x| &x[..])
-            .unwrap_or(&PDFDocEncoding);
-        //dlog!("char_code {:?} {:?}", char, self.encoding);
-        let s = to_utf8(encoding, &slice);
-        s
+        match self.encoding {
+            Some(ref encoding) => encoding[char as usize].clone(),
+            // there's nothing telling us what this glyph is so see if the resolver knows
+            None => match self.fallback.resolve(char) {
+                Some(s) => s,
+                None => to_utf8(PDFDocEncoding, &slice),
+            },
+        }
+    }
+    fn unicode_table(&self) -> Option<HashMap<CharCode, String>> {
+        // the encoding only fills in the codes the ToUnicode map is missing
+        let mut table = match (&self.encoding, self.unicode_map()) {
+            (Some(encoding), _) => encoding_code_map(encoding),
+            (None, Some(_)) => HashMap::new(),
+            (None, None) => encoding_code_map(&pdf_doc_encoding_table()),
+        };
+        if let Some(unicode_map) = self.unicode_map() {
+            table.extend(unicode_map.iter().map(|(k, v)| (*k, v.clone())));
+        }
+        Some(table)
     }
 }
 
@@ -653,24 +986,42 @@ impl<'a> PdfFont for PdfType3Font<'a> {
     }
     fn decode_char(&self, char: CharCode) -> String {
         let slice = [char as u8];
-        if let Some(ref unicode_map) = self.unicode_map {
+        if let Some(unicode_map) = self.unicode_map() {
             let s = unicode_map.get(&char);
-            let s = match s {
-                None => {
-                    panic!("missing char {:?} in map {:?}", char, unicode_map)
-                }
-                Some(s) => s.clone(),
-            };
-            return s;
+            match s {
+                Some(s) => return s.clone(),
+                None => log::warn!(
+                    "missing char {:?} in unicode map {:?} for {:?}",
+                    char,
+                    unicode_map,
+                    self.font
+                ),
+            }
         }
-        let encoding = self
-            .encoding
-            .as_ref()
-            .map(|x| &x[..])
-            .unwrap_or(&PDFDocEncoding);
-        //dlog!("char_code {:?} {:?}", char, self.encoding);
-        let s = to_utf8(encoding, &slice);
-        s
+        // glyph procedures are often named like /g3, which tells us nothing, so see if the
+        // resolver knows what they draw
+        if self.encoding.is_none() || self.unknown_names.contains(&char) {
+            let char_proc = type3_char_proc(self.doc, self.font, char).map(get_contents);
+            if let Some(s) = self.fallback.resolve_with(char, char_proc.as_deref()) {
+                return s;
+            }
+        }
+        match self.encoding {
+            Some(ref encoding) => encoding[char as usize].clone(),
+            None => to_utf8(PDFDocEncoding, &slice),
+        }
+    }
+    fn font_matrix(&self) -> Option<Transform> {
+        Some(self.font_matrix)
+    }
+    fn unicode_table(&self) -> Option<HashMap<CharCode, String>> {
+        if let Some(unicode_map) = self.unicode_map() {
+            return Some(unicode_map.clone());
+        }
+        Some(match self.encoding {
+            Some(ref encoding) => encoding_code_map(encoding),
+            None => encoding_code_map(&pdf_doc_encoding_table()),
+        })
     }
 }
 
@@ -682,13 +1033,81 @@ impl<'a> fmt::Debug for PdfType3Font<'a> {
 
 struct PdfCIDFont<'a> {
     font: &'a Dictionary,
-    #[allow(dead_code)]
     doc: &'a Document,
     #[allow(dead_code)]
     encoding: ByteMapping,
-    to_unicode: Option<HashMap<u32, String>>,
+    to_unicode: OnceCell<Option<HashMap<u32, String>>>,
     widths: HashMap<CharCode, f64>, // should probably just use i32 here
-    default_width: Option<f64>, // only used for CID fonts and we should probably brake out the different font types
+    // the width of glyphs missing from /W, from /DW
+    default_width: f64,
+    // the default vertical metrics from DW2 as [vy w1y], only needed for vertical writing
+    vertical_default: [f64; 2],
+    fallback: GlyphFallback<'a>,
+}
+
+// The text of a glyph name following the Adobe Glyph List specification: the name of a
+// character, uniXXXX or uXXXX[XX] code points, or several of those joined by underscores like
+// f_f_i. Names joined by underscores are spelled out even when the ligature has a code point of
+// its own. Anything after a period is a variant suffix.
+fn glyph_name_to_string(name: &str) -> Option<String> {
+    fn component(name: &str) -> Option<String> {
+        if let Some(unicode) = glyphnames::name_to_unicode(name) {
+            return Some(unicode_string(unicode));
+        }
+        let is_hex = |s: &str| {
+            s.chars()
+                .all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c))
+        };
+        let code_point = |hex: &str| char::from_u32(u32::from_str_radix(hex, 16).ok()?);
+        if let Some(hex) = name.strip_prefix("uni") {
+            if !hex.is_empty() && hex.len() % 4 == 0 && is_hex(hex) {
+                return (0..hex.len())
+                    .step_by(4)
+                    .map(|i| code_point(&hex[i..i + 4]))
+                    .collect();
+            }
+        }
+        match name.strip_prefix('u') {
+            Some(hex) if (4..=6).contains(&hex.len()) && is_hex(hex) => {
+                code_point(hex).map(String::from)
+            }
+            _ => None,
+        }
+    }
+    if !name.contains('_') {
+        if let Some(unicode) = glyphnames::name_to_unicode(name) {
+            return Some(unicode_string(unicode));
+        }
+    }
+    let name = name.split('.').next()?;
+    if name.is_empty() {
+        return None;
+    }
+    name.split('_').map(component).collect()
+}
+
+// the codes of a single byte encoding that map to something
+fn encoding_code_map(encoding: &[String]) -> HashMap<CharCode, String> {
+    (0..=255u8)
+        .filter(|&code| encoding[code as usize] != "\0")
+        .map(|code| (code as CharCode, encoding[code as usize].clone()))
+        .collect()
+}
+
+// The Identity-H encoding: 2-byte codes that map directly to CIDs
+fn identity_h() -> ByteMapping {
+    ByteMapping {
+        codespace: vec![CodeRange {
+            width: 2,
+            start: 0,
+            end: 0xffff,
+        }],
+        cid: vec![CIDRange {
+            src_code_lo: 0,
+            src_code_hi: 0xffff,
+            dst_CID_lo: 0,
+        }],
+    }
 }
 
 fn get_unicode_map<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<HashMap<u32, String>> {
@@ -698,18 +1117,29 @@ fn get_unicode_map<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<HashMa
     match to_unicode {
         Some(&Object::Stream(ref stream)) => {
             let contents = get_contents(stream);
-            dlog!("Stream: {}", String::from_utf8(contents.clone()).unwrap());
+            dlog!("Stream: {}", String::from_utf8_lossy(&contents));
 
-            let cmap = adobe_cmap_parser::get_unicode_map(&contents).unwrap();
+            // fall back to the encoding rather than give up on the whole document
+            let cmap = match adobe_cmap_parser::get_unicode_map(&contents) {
+                Ok(cmap) => cmap,
+                Err(e) => {
+                    log::warn!("ignoring malformed ToUnicode map: {}", e);
+                    return None;
+                }
+            };
             let mut unicode = HashMap::new();
             // "It must use the beginbfchar, endbfchar, beginbfrange, and endbfrange operators to
             // define the mapping from character codes to Unicode character sequences expressed in
             // UTF-16BE encoding."
+            // A destination can be a sequence of several code points (e.g. a ligature expanding
+            // to "ffi", or a base character followed by combining marks) so we keep the whole string.
             for (&k, v) in cmap.iter() {
                 let mut be: Vec<u16> = Vec::new();
                 let mut i = 0;
-                assert!(v.len() % 2 == 0);
-                while i < v.len() {
+                if v.len() % 2 != 0 {
+                    dlog!("odd length destination {:?} for {}", v, k);
+                }
+                while i + 1 < v.len() {
                     be.push(((v[i] as u16) << 8) | v[i + 1] as u16);
                     i += 2;
                 }
@@ -721,7 +1151,8 @@ fn get_unicode_map<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<HashMa
                     }
                     _ => {}
                 }
-                let s = String::from_utf16(&be).unwrap();
+                // unpaired surrogates inside of a longer sequence shouldn't take down the whole map
+                let s = String::from_utf16(&be).unwrap_or_else(|_| String::from_utf16_lossy(&be));
 
                 unicode.insert(k, s);
             }
@@ -743,14 +1174,48 @@ fn get_unicode_map<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<HashMa
     unicode_map
 }
 
+/// Find the CIDFont of a Type0 font. Malformed files can have DescendantFonts that point back
+/// at the Type0 font or at a loop of references so we keep track of what we've visited and give up
+/// instead of spinning.
+fn descendant_font<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<&'a Dictionary> {
+    let descendants = maybe_get_array(doc, font, b"DescendantFonts")?;
+    let mut visited: HashSet<ObjectId> = HashSet::new();
+    let mut o = descendants.first()?;
+    while let &Object::Reference(id) = o {
+        if !visited.insert(id) {
+            log::warn!("reference cycle at {:?} in DescendantFonts", id);
+            return None;
+        }
+        o = doc.get_object(id).ok()?;
+    }
+    let ciddict = o.as_dict().ok()?;
+    // a CIDFont can't be a Type0 font so this also catches a font that descends from itself
+    if std::ptr::eq(ciddict, font)
+        || maybe_get_name_string(doc, ciddict, b"Subtype").as_deref() == Some("Type0")
+    {
+        log::warn!(
+            "DescendantFonts of {:?} loops back to a Type0 font",
+            font.get(b"BaseFont")
+        );
+        return None;
+    }
+    Some(ciddict)
+}
+
 impl<'a> PdfCIDFont<'a> {
-    fn new(doc: &'a Document, font: &'a Dictionary) -> PdfCIDFont<'a> {
+    fn new(
+        doc: &'a Document,
+        font: &'a Dictionary,
+        resolver: Option<&'a dyn GlyphResolver>,
+    ) -> PdfCIDFont<'a> {
         let base_name = get_name_string(doc, font, b"BaseFont");
-        let descendants =
-            maybe_get_array(doc, font, b"DescendantFonts").expect("Descendant fonts required");
-        let ciddict = maybe_deref(doc, &descendants[0])
-            .as_dict()
-            .expect("should be CID dict");
+        let descendant = descendant_font(doc, font);
+        if descendant.is_none() {
+            log::warn!(
+                "no usable descendant font for {}, falling back to default widths",
+                base_name
+            );
+        }
         let encoding =
             maybe_get_obj(doc, font, b"Encoding").expect("Encoding required in type0 fonts");
         dlog!("base_name {} {:?}", base_name, font);
@@ -760,23 +1225,19 @@ impl<'a> PdfCIDFont<'a> {
                 let name = pdf_to_utf8(name);
                 dlog!("encoding {:?}", name);
                 assert!(name == "Identity-H");
-                ByteMapping {
-                    codespace: vec![CodeRange {
-                        width: 2,
-                        start: 0,
-                        end: 0xffff,
-                    }],
-                    cid: vec![CIDRange {
-                        src_code_lo: 0,
-                        src_code_hi: 0xffff,
-                        dst_CID_lo: 0,
-                    }],
-                }
+                identity_h()
             }
             &Object::Stream(ref stream) => {
                 let contents = get_contents(stream);
-                dlog!("Stream: {}", String::from_utf8(contents.clone()).unwrap());
-                adobe_cmap_parser::get_byte_mapping(&contents).unwrap()
+                dlog!("Stream: {}", String::from_utf8_lossy(&contents));
+                // 2-byte codes that are their own CIDs are by far the most common encoding
+                adobe_cmap_parser::get_byte_mapping(&contents).unwrap_or_else(|e| {
+                    log::warn!(
+                        "using Identity-H in place of a malformed encoding CMap: {}",
+                        e
+                    );
+                    identity_h()
+                })
             }
             _ => {
                 panic!("unsupported encoding {:?}", encoding)
@@ -788,37 +1249,50 @@ impl<'a> PdfCIDFont<'a> {
         // We should also look inside the truetype data to see if there's a cmap table. It will help us convert as well.
         // This won't work if the cmap has been subsetted. A better approach might be to hash glyph contents and use that against
         // a global library of glyph hashes
-        let unicode_map = get_unicode_map(doc, font);
 
-        dlog!("descendents {:?} {:?}", descendants, ciddict);
+        dlog!("descendent {:?}", descendant);
 
-        let font_dict = maybe_get_obj(doc, ciddict, b"FontDescriptor").expect("required");
-        dlog!("{:?}", font_dict);
-        let _f = font_dict.as_dict().expect("must be dict");
-        let default_width = get::<Option<i64>>(doc, ciddict, b"DW").unwrap_or(1000);
+        // without a descendant the font itself stands in for it, which just gives us the defaults
+        let ciddict = descendant.unwrap_or(font);
+        if descendant.is_some() {
+            let font_dict = maybe_get_obj(doc, ciddict, b"FontDescriptor").expect("required");
+            dlog!("{:?}", font_dict);
+            let _f = font_dict.as_dict().expect("must be dict");
+        }
+        let default_width = get::<Option<f64>>(doc, ciddict, b"DW").unwrap_or(1000.);
+        // "The default value shall be [880 −1000]"
+        let vertical_default = match get::<Option<Vec<f64>>>(doc, ciddict, b"DW2").as_deref() {
+            Some(&[vy, w1y]) => [vy, w1y],
+            _ => [880., -1000.],
+        };
         let w: Option<Vec<&Object>> = get(doc, ciddict, b"W");
         dlog!("widths {:?}", w);
         let mut widths = HashMap::new();
         let mut i = 0;
         if let Some(w) = w {
-            while i < w.len() {
-                if let &Object::Array(ref wa) = w[i + 1] {
+            // the W array is made of `c [w1 w2 ... wn]` and `c_first c_last w` entries
+            while i + 1 < w.len() {
+                // the inner arrays and their elements can be indirect references
+                if let &Object::Array(ref wa) = maybe_deref(doc, w[i + 1]) {
                     let cid = w[i].as_i64().expect("id should be num");
                     let mut j = 0;
                     dlog!("wa: {:?} -> {:?}", cid, wa);
                     for w in wa {
-                        widths.insert((cid + j) as CharCode, as_num(w));
+                        widths.insert((cid + j) as CharCode, as_num(maybe_deref(doc, w)));
                         j += 1;
                     }
                     i += 2;
-                } else {
+                } else if i + 2 < w.len() {
                     let c_first = w[i].as_i64().expect("first should be num");
-                    let c_last = w[i].as_i64().expect("last should be num");
-                    let c_width = as_num(&w[i]);
-                    for id in c_first..c_last {
+                    let c_last = w[i + 1].as_i64().expect("last should be num");
+                    let c_width = as_num(w[i + 2]);
+                    for id in c_first..=c_last {
                         widths.insert(id as CharCode, c_width);
                     }
                     i += 3;
+                } else {
+                    dlog!("truncated W array {:?}", w);
+                    break;
                 }
             }
         }
@@ -826,13 +1300,23 @@ impl<'a> PdfCIDFont<'a> {
             doc,
             font,
             widths,
-            to_unicode: unicode_map,
+            to_unicode: OnceCell::new(),
             encoding,
-            default_width: Some(default_width as f64),
+            default_width,
+            vertical_default,
+            fallback: GlyphFallback::new(doc, ciddict, resolver),
         }
     }
 }
 
+impl<'a> PdfCIDFont<'a> {
+    fn to_unicode(&self) -> Option<&HashMap<u32, String>> {
+        self.to_unicode
+            .get_or_init(|| get_unicode_map(self.doc, self.font))
+            .as_ref()
+    }
+}
+
 impl<'a> PdfFont for PdfCIDFont<'a> {
     fn get_width(&self, id: CharCode) -> f64 {
         let width = self.widths.get(&id);
@@ -841,7 +1325,7 @@ impl<'a> PdfFont for PdfCIDFont<'a> {
             return *width;
         } else {
             dlog!("missing width for {} falling back to default_width", id);
-            return self.default_width.unwrap();
+            return self.default_width;
         }
     } /*
       fn decode(&self, chars: &[u8]) -> String {
@@ -875,9 +1359,11 @@ impl<'a> PdfFont for PdfCIDFont<'a> {
         None
     }
     fn decode_char(&self, char: CharCode) -> String {
-        let s = self.to_unicode.as_ref().and_then(|x| x.get(&char));
+        let s = self.to_unicode().and_then(|x| x.get(&char));
         if let Some(s) = s {
             s.clone()
+        } else if let Some(s) = self.fallback.resolve(char) {
+            s
         } else {
             dlog!(
                 "Unknown character {:?} in {:?} {:?}",
@@ -888,6 +1374,12 @@ impl<'a> PdfFont for PdfCIDFont<'a> {
             "".to_string()
         }
     }
+    fn unicode_table(&self) -> Option<HashMap<CharCode, String>> {
+        self.to_unicode().cloned()
+    }
+    fn vertical_default(&self) -> Option<[f64; 2]> {
+        Some(self.vertical_default)
+    }
 }
 
 impl<'a> fmt::Debug for PdfCIDFont<'a> {
@@ -0,0 +1,38 @@
+//! The geometry types used by `OutputDev` implementations.
+//!
+//! These are re-exported here so that code implementing `OutputDev` doesn't need to depend on
+//! the same version of euclid that we do.
+
+pub use crate::{MediaBox, Space, Transform};
+pub use euclid::{point2, vec2, Transform2D};
+
+/// The bounding box, as `[llx, lly, urx, ury]`, of the rectangle from the origin
+/// to `(w, h)` after it's been transformed by `trm`.
+///
+/// ```
+/// use ara_yaaaay::geometry::{transform_bbox, vec2, Transform};
+///
+/// // a glyph that's 0.5 wide and 1 high, drawn at 12pt at (100, 700)
+/// let trm = Transform::create_scale(12., 12.).post_translate(vec2(100., 700.));
+/// assert_eq!(transform_bbox(&trm, 0.5, 1.), [100., 700., 106., 712.]);
+///
+/// // rotating by 90 degrees swaps the extents
+/// let trm = Transform::row_major(0., 1., -1., 0., 0., 0.);
+/// assert_eq!(transform_bbox(&trm, 2., 1.), [-1., 0., 0., 2.]);
+/// ```
+pub fn transform_bbox(trm: &Transform, w: f64, h: f64) -> [f64; 4] {
+    let corners = [
+        trm.transform_point(point2(0., 0.)),
+        trm.transform_point(point2(w, 0.)),
+        trm.transform_point(point2(0., h)),
+        trm.transform_point(point2(w, h)),
+    ];
+    let mut bbox = [corners[0].x, corners[0].y, corners[0].x, corners[0].y];
+    for p in &corners[1..] {
+        bbox[0] = bbox[0].min(p.x);
+        bbox[1] = bbox[1].min(p.y);
+        bbox[2] = bbox[2].max(p.x);
+        bbox[3] = bbox[3].max(p.y);
+    }
+    bbox
+}
@@ -4,14 +4,24 @@ use adobe_cmap_parser::{ByteMapping, CIDRange, CodeRange};
 use encoding_rs::UTF_16BE;
 use euclid::*;
 use font::PdfFont;
+pub use font::{FontSubstitutes, Glyph, GlyphResolver, NoGlyphResolver};
 use lopdf::content::Content;
 use lopdf::encryption::DecryptionError;
 pub use lopdf::*;
 use output::OutputDev;
 use output::PlainTextOutput;
+use output::{
+    chars_to_text, remove_watermarks, rtl_layout, text_lines, text_words, words_by_line,
+    LineSegment, LineSegmentOutput, PositionedChar, PositionedOutput, TextLine, TextWord,
+    WatermarkFilter,
+};
 use processor::Processor;
+pub use processor::DEFAULT_MAX_GS_DEPTH;
 use std::fmt::{Debug, Formatter};
-use utils::{maybe_deref, maybe_get_obj, pdf_to_utf8, to_utf8, PDFDocEncoding};
+use utils::{
+    ascii85_decode, ascii_hex_decode, get_catalog, maybe_deref, maybe_get_obj, name_tree_entries,
+    pdf_to_utf8, rebuild_xref, run_length_decode, to_utf8, PDFDocEncoding,
+};
 extern crate adobe_cmap_parser;
 extern crate encoding_rs;
 extern crate euclid;
@@ -31,6 +41,7 @@ use unicode_normalization::UnicodeNormalization;
 mod core_fonts;
 mod encodings;
 mod font;
+pub mod geometry;
 mod glyphnames;
 pub mod output;
 mod processor;
@@ -274,29 +285,292 @@ struct Type2Func {
     n: f64,
 }
 
+// An operation of a PostScript calculator function
+#[derive(Clone, Debug)]
+enum PsOp {
+    Num(f64),
+    Bool(bool),
+    Operator(String),
+    If(Vec<PsOp>),
+    IfElse(Vec<PsOp>, Vec<PsOp>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PsValue {
+    Num(f64),
+    Bool(bool),
+}
+
+impl PsValue {
+    fn num(self) -> Option<f64> {
+        match self {
+            PsValue::Num(n) => Some(n),
+            PsValue::Bool(_) => None,
+        }
+    }
+}
+
+// Parse the operations of a `{ ... }` procedure, with `tokens` just past its opening brace
+fn parse_ps_procedure<'a, I: Iterator<Item = &'a str>>(tokens: &mut I) -> Option<Vec<PsOp>> {
+    let mut ops = Vec::new();
+    let mut procedures: Vec<Vec<PsOp>> = Vec::new();
+    while let Some(token) = tokens.next() {
+        let op = match token {
+            "}" => {
+                if !procedures.is_empty() {
+                    return None;
+                }
+                return Some(ops);
+            }
+            "{" => {
+                procedures.push(parse_ps_procedure(tokens)?);
+                continue;
+            }
+            "if" => {
+                let proc = procedures.pop()?;
+                PsOp::If(proc)
+            }
+            "ifelse" => {
+                let else_proc = procedures.pop()?;
+                let if_proc = procedures.pop()?;
+                PsOp::IfElse(if_proc, else_proc)
+            }
+            "true" => PsOp::Bool(true),
+            "false" => PsOp::Bool(false),
+            _ => match token.parse() {
+                Ok(n) => PsOp::Num(n),
+                Err(_) => PsOp::Operator(token.to_owned()),
+            },
+        };
+        if !procedures.is_empty() {
+            return None;
+        }
+        ops.push(op);
+    }
+    None
+}
+
+fn run_ps(ops: &[PsOp], stack: &mut Vec<PsValue>) -> Option<()> {
+    use PsValue::{Bool, Num};
+    for op in ops {
+        match op {
+            PsOp::Num(n) => stack.push(Num(*n)),
+            PsOp::Bool(b) => stack.push(Bool(*b)),
+            PsOp::If(proc) => {
+                if let Bool(true) = stack.pop()? {
+                    run_ps(proc, stack)?;
+                }
+            }
+            PsOp::IfElse(if_proc, else_proc) => match stack.pop()? {
+                Bool(true) => run_ps(if_proc, stack)?,
+                _ => run_ps(else_proc, stack)?,
+            },
+            PsOp::Operator(name) => {
+                let name = name.as_str();
+                match name {
+                    "abs" | "ceiling" | "cos" | "cvi" | "cvr" | "floor" | "ln" | "log" | "neg"
+                    | "round" | "sin" | "sqrt" | "truncate" => {
+                        let x = stack.pop()?.num()?;
+                        stack.push(Num(match name {
+                            "abs" => x.abs(),
+                            "ceiling" => x.ceil(),
+                            // angles are in degrees
+                            "cos" => x.to_radians().cos(),
+                            "sin" => x.to_radians().sin(),
+                            "cvi" | "truncate" => x.trunc(),
+                            "cvr" => x,
+                            "floor" => x.floor(),
+                            "ln" => x.ln(),
+                            "log" => x.log10(),
+                            "neg" => -x,
+                            "round" => (x + 0.5).floor(),
+                            _ => x.sqrt(),
+                        }));
+                    }
+                    "add" | "sub" | "mul" | "div" | "idiv" | "mod" | "exp" | "atan" => {
+                        let b = stack.pop()?.num()?;
+                        let a = stack.pop()?.num()?;
+                        stack.push(Num(match name {
+                            "add" => a + b,
+                            "sub" => a - b,
+                            "mul" => a * b,
+                            "div" => a / b,
+                            "idiv" => (a.trunc() / b.trunc()).trunc(),
+                            "mod" => a.trunc() % b.trunc(),
+                            "exp" => a.powf(b),
+                            _ => a.atan2(b).to_degrees().rem_euclid(360.),
+                        }));
+                    }
+                    "bitshift" => {
+                        let shift = stack.pop()?.num()? as i32;
+                        let n = stack.pop()?.num()? as i64;
+                        // every bit is shifted out by shifts as wide as the integer or wider
+                        let n = if shift >= 0 {
+                            n.checked_shl(shift as u32)
+                        } else {
+                            n.checked_shr(shift.unsigned_abs())
+                        };
+                        stack.push(Num(n.unwrap_or(0) as f64));
+                    }
+                    "eq" | "ne" => {
+                        let b = stack.pop()?;
+                        let a = stack.pop()?;
+                        let equal = match (a, b) {
+                            (Num(a), Num(b)) => a == b,
+                            (Bool(a), Bool(b)) => a == b,
+                            _ => false,
+                        };
+                        stack.push(Bool(equal == (name == "eq")));
+                    }
+                    "gt" | "ge" | "lt" | "le" => {
+                        let b = stack.pop()?.num()?;
+                        let a = stack.pop()?.num()?;
+                        stack.push(Bool(match name {
+                            "gt" => a > b,
+                            "ge" => a >= b,
+                            "lt" => a < b,
+                            _ => a <= b,
+                        }));
+                    }
+                    "and" | "or" | "xor" => {
+                        let b = stack.pop()?;
+                        let a = stack.pop()?;
+                        stack.push(match (a, b) {
+                            (Bool(a), Bool(b)) => Bool(match name {
+                                "and" => a & b,
+                                "or" => a | b,
+                                _ => a ^ b,
+                            }),
+                            (Num(a), Num(b)) => {
+                                let (a, b) = (a as i64, b as i64);
+                                Num(match name {
+                                    "and" => a & b,
+                                    "or" => a | b,
+                                    _ => a ^ b,
+                                } as f64)
+                            }
+                            _ => return None,
+                        });
+                    }
+                    "not" => {
+                        let value = match stack.pop()? {
+                            Bool(b) => Bool(!b),
+                            Num(n) => Num(!(n as i64) as f64),
+                        };
+                        stack.push(value);
+                    }
+                    "pop" => {
+                        stack.pop()?;
+                    }
+                    "exch" => {
+                        let b = stack.pop()?;
+                        let a = stack.pop()?;
+                        stack.extend([b, a]);
+                    }
+                    "dup" => stack.push(*stack.last()?),
+                    "copy" => {
+                        let n = stack.pop()?.num()? as usize;
+                        let start = stack.len().checked_sub(n)?;
+                        stack.extend_from_within(start..);
+                    }
+                    "index" => {
+                        let n = stack.pop()?.num()? as usize;
+                        let i = stack.len().checked_sub(n.checked_add(1)?)?;
+                        stack.push(stack[i]);
+                    }
+                    "roll" => {
+                        let j = stack.pop()?.num()? as i64;
+                        let n = stack.pop()?.num()? as usize;
+                        let start = stack.len().checked_sub(n)?;
+                        if n > 0 {
+                            let j = j.rem_euclid(n as i64) as usize;
+                            stack[start..].rotate_right(j);
+                        }
+                    }
+                    _ => {
+                        dlog!("unknown PostScript operator {}", name);
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+#[derive(Clone, Debug)]
+struct Type3Func {
+    domain: Vec<f64>,
+    functions: Vec<Function>,
+    bounds: Vec<f64>,
+    encode: Vec<f64>,
+}
+
+impl Type3Func {
+    fn eval(&self, input: &[f64]) -> Option<Vec<f64>> {
+        let (d0, d1) = (*self.domain.first()?, *self.domain.get(1)?);
+        let x = input.first()?.clamp(d0, d1);
+        // the subdomains are [d0, bounds[0]), [bounds[0], bounds[1]) ... [bounds[k - 2], d1]
+        let i = self.bounds.iter().take_while(|&&bound| x >= bound).count();
+        let i = i.min(self.functions.len().checked_sub(1)?);
+        let low = if i == 0 { d0 } else { self.bounds[i - 1] };
+        let high = self.bounds.get(i).copied().unwrap_or(d1);
+        let (e0, e1) = (*self.encode.get(2 * i)?, *self.encode.get(2 * i + 1)?);
+        let x = if high == low {
+            e0
+        } else {
+            e0 + (x - low) * (e1 - e0) / (high - low)
+        };
+        self.functions[i].eval(&[x])
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Type4Func {
+    domain: Vec<f64>,
+    range: Vec<f64>,
+    program: Vec<PsOp>,
+}
+
+impl Type4Func {
+    fn eval(&self, input: &[f64]) -> Option<Vec<f64>> {
+        let mut stack: Vec<PsValue> = input
+            .iter()
+            .zip(self.domain.chunks_exact(2))
+            .map(|(x, domain)| PsValue::Num(x.clamp(domain[0], domain[1])))
+            .collect();
+        run_ps(&self.program, &mut stack)?;
+        let n_outputs = self.range.len() / 2;
+        let start = stack.len().checked_sub(n_outputs)?;
+        stack[start..]
+            .iter()
+            .zip(self.range.chunks_exact(2))
+            .map(|(y, range)| Some(y.num()?.clamp(range[0], range[1])))
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug)]
 enum Function {
     Type0(Type0Func),
     Type2(Type2Func),
-    #[allow(dead_code)]
-    Type3,
-    #[allow(dead_code)]
-    Type4,
+    Type3(Type3Func),
+    Type4(Type4Func),
 }
 
 impl Function {
-    fn new(doc: &Document, obj: &Object) -> Function {
+    fn new(doc: &Document, obj: &Object) -> Result<Function, lopdf::Error> {
         let dict = match obj {
             &Object::Dictionary(ref dict) => dict,
             &Object::Stream(ref stream) => &stream.dict,
-            _ => panic!(),
+            _ => return Err(lopdf::Error::Type),
         };
         let function_type: i64 = get(doc, dict, b"FunctionType");
         let f = match function_type {
             0 => {
                 let stream = match obj {
                     &Object::Stream(ref stream) => stream,
-                    _ => panic!(),
+                    _ => return Err(lopdf::Error::Type),
                 };
                 let range: Vec<f64> = get(doc, dict, b"Range");
                 let domain: Vec<f64> = get(doc, dict, b"Domain");
@@ -333,11 +607,75 @@ impl Function {
                 let n = get::<f64>(doc, dict, b"N");
                 Function::Type2(Type2Func { c0, c1, n })
             }
+            3 => {
+                let functions = maybe_get_array(doc, dict, b"Functions")
+                    .into_iter()
+                    .flatten()
+                    .map(|f| Function::new(doc, maybe_deref(doc, f)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Function::Type3(Type3Func {
+                    domain: get(doc, dict, b"Domain"),
+                    bounds: get(doc, dict, b"Bounds"),
+                    encode: get(doc, dict, b"Encode"),
+                    functions,
+                })
+            }
+            4 => {
+                let stream = match obj {
+                    Object::Stream(stream) => stream,
+                    _ => return Err(lopdf::Error::Type),
+                };
+                let contents = get_contents(stream);
+                let text = String::from_utf8_lossy(&contents)
+                    .replace('{', " { ")
+                    .replace('}', " } ");
+                let mut tokens = text.split_whitespace();
+                let program = match tokens.next() {
+                    Some("{") => parse_ps_procedure(&mut tokens),
+                    _ => None,
+                };
+                let program = program.unwrap_or_else(|| {
+                    log::warn!("malformed PostScript calculator function");
+                    Vec::new()
+                });
+                Function::Type4(Type4Func {
+                    domain: get(doc, dict, b"Domain"),
+                    range: get(doc, dict, b"Range"),
+                    program,
+                })
+            }
             _ => {
-                panic!("unhandled function type {}", function_type)
+                return Err(lopdf::Error::Invalid(format!(
+                    "unhandled function type {}",
+                    function_type
+                )))
             }
         };
-        f
+        Ok(f)
+    }
+
+    /// Evaluate the function at `input`. Exponential interpolation, stitching and PostScript
+    /// calculator functions are supported.
+    fn eval(&self, input: &[f64]) -> Option<Vec<f64>> {
+        match self {
+            Function::Type2(f) => {
+                let x = input.first()?.clamp(0., 1.);
+                let c0 = f.c0.clone().unwrap_or_else(|| vec![0.]);
+                let c1 = f.c1.clone().unwrap_or_else(|| vec![1.]);
+                Some(
+                    c0.iter()
+                        .zip(&c1)
+                        .map(|(c0, c1)| c0 + x.powf(f.n) * (c1 - c0))
+                        .collect(),
+                )
+            }
+            Function::Type3(f) => f.eval(input),
+            Function::Type4(f) => f.eval(input),
+            _ => {
+                dlog!("unhandled function evaluation {:?}", self);
+                None
+            }
+        }
     }
 }
 
@@ -345,12 +683,32 @@ fn as_num(o: &Object) -> f64 {
     match o {
         &Object::Integer(i) => i as f64,
         &Object::Real(f) => f.into(),
+        // some producers write numbers as strings, like `/F1 (12) Tf`
+        Object::String(s, _) => maybe_as_num(o).unwrap_or_else(|| {
+            log::warn!("using 0 for the non-numeric operand {:?}", pdf_to_utf8(s));
+            0.
+        }),
         _ => {
             panic!("not a number")
         }
     }
 }
 
+// Like `as_num` but None for anything that isn't a number or a string holding one
+fn maybe_as_num(o: &Object) -> Option<f64> {
+    match o {
+        &Object::Integer(i) => Some(i as f64),
+        &Object::Real(f) => Some(f.into()),
+        Object::String(s, _) => str::from_utf8(s)
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+            .filter(|n: &f64| n.is_finite()),
+        _ => None,
+    }
+}
+
 #[derive(Clone)]
 struct TextState<'a> {
     font: Option<Rc<dyn PdfFont + 'a>>,
@@ -361,6 +719,8 @@ struct TextState<'a> {
     leading: f64,
     rise: f64,
     tm: Transform,
+    // the font dictionary when the font is a Type3 font, for looking up its glyph procedures
+    type3_font: Option<&'a Dictionary>,
 }
 
 // XXX: We'd ideally implement this without having to copy the uncompressed data
@@ -368,12 +728,36 @@ fn get_contents(contents: &Stream) -> Vec<u8> {
     if contents.filter().is_ok() {
         contents
             .decompressed_content()
+            .or_else(|_| decode_filters(contents))
             .unwrap_or_else(|_| contents.content.clone())
     } else {
         contents.content.clone()
     }
 }
 
+// lopdf only knows about FlateDecode and LZWDecode so we run the filter chain ourselves
+// for the filters that it doesn't handle and hand the rest back to lopdf
+fn decode_filters(contents: &Stream) -> Result<Vec<u8>, lopdf::Error> {
+    let mut data = contents.content.clone();
+    for filter in contents.filters()? {
+        data = match filter.as_str() {
+            "ASCIIHexDecode" | "AHx" => ascii_hex_decode(&data),
+            "ASCII85Decode" | "A85" => ascii85_decode(&data),
+            "RunLengthDecode" | "RL" => run_length_decode(&data),
+            "FlateDecode" | "LZWDecode" => {
+                let mut dict = Dictionary::new();
+                dict.set("Filter", Object::Name(filter.into_bytes()));
+                if let Ok(params) = contents.dict.get(b"DecodeParms") {
+                    dict.set("DecodeParms", params.clone());
+                }
+                Stream::new(dict, data).decompressed_content()?
+            }
+            _ => return Err(lopdf::Error::Type),
+        };
+    }
+    Ok(data)
+}
+
 #[derive(Clone)]
 struct GraphicsState<'a> {
     ctm: Transform,
@@ -384,6 +768,90 @@ struct GraphicsState<'a> {
     stroke_colorspace: ColorSpace,
     stroke_color: Vec<f64>,
     line_width: f64,
+    // the constant alpha for non-stroking operations, /ca in an ExtGState
+    fill_alpha: f64,
+    // the /BBox of the form XObjects being drawn, in device space
+    form_clip: Option<[f64; 4]>,
+}
+
+// The /ActualText of the marked-content sequence being drawn. None of the glyphs inside of the
+// sequence are output and the text takes the place of the first one.
+struct ActualText {
+    // the depth of the sequence in the marked-content stack
+    depth: usize,
+    // taken once it has been output
+    text: Option<String>,
+}
+
+/// Where the advance of each glyph, and so the position of the glyphs after it, comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlyphAdvance {
+    /// the widths of the font
+    #[default]
+    FontWidths,
+    /// the `TJ` adjustments, for documents that position every glyph themselves with fonts
+    /// whose widths are wrong. A glyph that is followed by an adjustment doesn't advance, the
+    /// others advance by the font's width. The width of a glyph is the distance to the next one
+    /// when that's no more than an em, and the font's width otherwise.
+    Positioning,
+}
+
+// A glyph drawn with GlyphAdvance::Positioning that is held back until the next glyph of its
+// string, or the adjustment after the string, gives its width
+struct PendingGlyph {
+    trm: Transform,
+    // the width from the font, for the last glyph of a line
+    width: f64,
+    spacing: f64,
+    font_size: f64,
+    text: String,
+    rgb: [f64; 3],
+}
+
+// What show_text keeps track of across strings
+struct ShownGlyphs {
+    // GlyphAdvance::Positioning
+    positioning: bool,
+    // the TJ adjustment right after the string being shown, in thousandths of an em
+    adjustment: Option<f64>,
+    // the codes of the Type3 glyphs shown along with the matrix from their glyph space to device
+    // space, for running their glyph procedures
+    type3: Vec<(u32, Transform)>,
+}
+
+fn output_pending_glyph(
+    pending: &mut Option<PendingGlyph>,
+    next: Option<&Transform>,
+    output: &mut dyn OutputDev,
+) -> Result<(), OutputError> {
+    let Some(glyph) = pending.take() else {
+        return Ok(());
+    };
+    let trm = &glyph.trm;
+    let scale = trm.m11.hypot(trm.m12);
+    let width = next
+        .filter(|_| scale > 0. && glyph.font_size > 0.)
+        .and_then(|next| {
+            let (dx, dy) = (next.m31 - trm.m31, next.m32 - trm.m32);
+            let along = (dx * trm.m11 + dy * trm.m12) / scale;
+            let across = (dy * trm.m11 - dx * trm.m12) / scale;
+            // the next glyph has to be further along the same line
+            if along <= 0. || across.abs() > scale * glyph.font_size * 0.1 {
+                return None;
+            }
+            // a movement of more than an em is taken to be a gap between words
+            let width = (along / scale - glyph.spacing) / glyph.font_size;
+            (width <= 1.).then_some(width.max(0.))
+        })
+        .unwrap_or(glyph.width);
+    output.output_character_with_color(
+        trm,
+        width,
+        glyph.spacing,
+        glyph.font_size,
+        &glyph.text,
+        glyph.rgb,
+    )
 }
 
 fn show_text(
@@ -391,6 +859,8 @@ fn show_text(
     s: &[u8],
     _tlm: &Transform,
     _flip_ctm: &Transform,
+    mut actual_text: Option<&mut ActualText>,
+    glyphs: &mut ShownGlyphs,
     output: &mut dyn OutputDev,
 ) -> Result<(), OutputError> {
     let ts = &mut gs.ts;
@@ -399,19 +869,44 @@ fn show_text(
     dlog!("{:?}", font.decode(s));
     dlog!("{:?}", font.decode(s).as_bytes());
     dlog!("{:?}", s);
-    output.begin_word()?;
 
-    for (c, length) in font.char_codes(s) {
+    // The output devices get glyph widths in text space and the font size separately, so only the
+    // shape of a Type3 FontMatrix (flips, skews, offsets) goes into the rendering matrix and its
+    // scale goes into the widths. The font size isn't part of Tsm here so the offset is scaled by it.
+    let (glyph_matrix, glyph_scale) = match font.font_matrix() {
+        Some(fm) if fm.determinant() != 0. => {
+            let scale = fm.determinant().abs().sqrt();
+            let shape: Transform = Transform2D::row_major(
+                fm.m11 / scale,
+                fm.m12 / scale,
+                fm.m21 / scale,
+                fm.m22 / scale,
+                fm.m31 * ts.font_size,
+                fm.m32 * ts.font_size,
+            );
+            (shape, Some(fm.m11))
+        }
+        _ => (Transform2D::identity(), None),
+    };
+    let mut pending = None;
+    let adjustment = glyphs.adjustment.take();
+    output.begin_word()?;
+    output.fill_alpha(gs.fill_alpha)?;
+    let mut codes = font.char_codes(s).peekable();
+    while let Some((c, length)) = codes.next() {
         // 5.3.3 Text Space Details
         let tsm = Transform2D::row_major(ts.horizontal_scaling, 0., 0., 1.0, 0., ts.rise);
-        // Trm = Tsm × Tm × CTM
-        let trm = tsm.post_transform(&ts.tm.post_transform(&gs.ctm));
+        // Trm = FontMatrix × Tsm × Tm × CTM
+        let trm = glyph_matrix.post_transform(&tsm.post_transform(&ts.tm.post_transform(&gs.ctm)));
         //dlog!("ctm: {:?} tm {:?}", gs.ctm, tm);
         //dlog!("current pos: {:?}", position);
         // 5.9 Extraction of Text Content
 
         //dlog!("w: {}", font.widths[&(*c as i64)]);
-        let w0 = font.get_width(c) / 1000.;
+        let w0 = match glyph_scale {
+            Some(scale) => font.get_width(c) * scale,
+            None => font.get_width(c) / 1000.,
+        };
 
         let mut spacing = ts.character_spacing;
         // "Word spacing is applied to every occurrence of the single-byte character code 32 in a
@@ -423,9 +918,58 @@ fn show_text(
             spacing += ts.word_spacing
         }
 
-        output.output_character(&trm, w0, spacing, ts.font_size, &font.decode_char(c))?;
+        // characters starting outside of the form they're drawn by are clipped away
+        let clipped = gs.form_clip.is_some_and(|clip| {
+            trm.m31 < clip[0] || trm.m31 > clip[2] || trm.m32 < clip[1] || trm.m32 > clip[3]
+        });
+        // the actual text goes with the first glyph of the sequence that's output
+        let text = match actual_text.as_deref_mut() {
+            _ if clipped => None,
+            Some(actual_text) => actual_text.text.take(),
+            None => Some(font.decode_char(c)),
+        };
+        if ts.type3_font.is_some() {
+            if let Some(fm) = font.font_matrix() {
+                let size = Transform2D::row_major(
+                    ts.font_size * ts.horizontal_scaling,
+                    0.,
+                    0.,
+                    ts.font_size,
+                    0.,
+                    ts.rise,
+                );
+                let glyph_ctm =
+                    fm.post_transform(&size.post_transform(&ts.tm.post_transform(&gs.ctm)));
+                glyphs.type3.push((c, glyph_ctm));
+            }
+        }
+        if glyphs.positioning {
+            output_pending_glyph(&mut pending, Some(&trm), output)?;
+            if let Some(text) = text {
+                pending = Some(PendingGlyph {
+                    trm,
+                    width: w0,
+                    spacing,
+                    font_size: ts.font_size,
+                    text,
+                    rgb: to_rgb(&gs.fill_colorspace, &gs.fill_color),
+                });
+            }
+        } else if let Some(text) = text {
+            output.output_character_with_color(
+                &trm,
+                w0,
+                spacing,
+                ts.font_size,
+                &text,
+                to_rgb(&gs.fill_colorspace, &gs.fill_color),
+            )?;
+        }
         let tj = 0.;
         let ty = 0.;
+        // an adjustment after the string places the glyph after the last one
+        let positioned = glyphs.positioning && adjustment.is_some() && codes.peek().is_none();
+        let w0 = if positioned { 0. } else { w0 };
         let tx = ts.horizontal_scaling * ((w0 - tj / 1000.) * ts.font_size + spacing);
         dlog!(
             "horizontal {} adjust {} {} {} {}",
@@ -442,6 +986,17 @@ fn show_text(
         let _trm = ts.tm.pre_transform(&gs.ctm);
         //dlog!("post pos: {:?}", trm);
     }
+    if pending.is_some() {
+        // the last glyph's width comes from where the adjustment puts the next one
+        let tj = adjustment.unwrap_or(0.);
+        let tx = -ts.horizontal_scaling * tj / 1000. * ts.font_size;
+        let tm: Transform = ts
+            .tm
+            .pre_transform(&Transform2D::create_translation(tx, 0.));
+        let tsm = Transform2D::row_major(ts.horizontal_scaling, 0., 0., 1.0, 0., ts.rise);
+        let next = glyph_matrix.post_transform(&tsm.post_transform(&tm.post_transform(&gs.ctm)));
+        output_pending_glyph(&mut pending, Some(&next), output)?;
+    }
     output.end_word()?;
     Ok(())
 }
@@ -473,6 +1028,9 @@ fn apply_state(doc: &Document, gs: &mut GraphicsState, state: &Dictionary) {
                     panic!("unexpected smask type {:?}", v)
                 }
             },
+            b"ca" => {
+                gs.fill_alpha = as_num(maybe_deref(doc, v));
+            }
             b"Type" => match v {
                 &Object::Name(ref name) => {
                     assert_eq!(name, b"ExtGState")
@@ -556,7 +1114,19 @@ pub enum AlternateColorSpace {
 pub struct Separation {
     name: String,
     alternate_space: AlternateColorSpace,
-    tint_transform: Box<Function>,
+    /// `None` when the tint transform couldn't be loaded
+    tint_transform: Option<Box<Function>>,
+}
+
+impl Separation {
+    /// The components in the alternate colorspace of the color with tint `tint`, as given by the
+    /// tint transform. Empty if the transform can't be evaluated.
+    pub fn tint_to_alternate(&self, tint: &[f64]) -> Vec<f64> {
+        self.tint_transform
+            .as_ref()
+            .and_then(|f| f.eval(tint))
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone)]
@@ -570,6 +1140,155 @@ pub enum ColorSpace {
     Lab(Lab),
     Separation(Separation),
     ICCBased(Vec<u8>),
+    /// A palette of `hival + 1` colors in `base`, whose components are stored one byte each
+    /// in `lookup`
+    Indexed {
+        base: Box<ColorSpace>,
+        hival: i64,
+        lookup: Vec<u8>,
+    },
+}
+
+impl ColorSpace {
+    /// The sRGB components, from 0 to 1, of `color` given in this colorspace.
+    /// CalGray and CalRGB are treated as their device counterparts, ICCBased spaces as the
+    /// device space with the same number of components and Lab goes through XYZ without
+    /// white point adaptation. Patterns and malformed colors come out black.
+    pub fn to_rgb(&self, color: &[f64]) -> (f64, f64, f64) {
+        let [r, g, b] = to_rgb(self, color);
+        (r, g, b)
+    }
+
+    // The number of components of a color in this colorspace
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::DeviceRGB | ColorSpace::CalRGB(_) | ColorSpace::Lab(_) => 3,
+            ColorSpace::DeviceCMYK => 4,
+            // the profile header gives the colorspace the profile converts from
+            ColorSpace::ICCBased(profile) => match profile.get(16..20) {
+                Some(b"GRAY") => 1,
+                Some(b"CMYK") => 4,
+                _ => 3,
+            },
+            _ => 1,
+        }
+    }
+}
+
+// The color at `index` of an Indexed palette, in its base colorspace. The bytes of the lookup
+// table map linearly onto the range of each component of the base.
+fn palette_color(base: &ColorSpace, hival: i64, lookup: &[u8], index: f64) -> Option<Vec<f64>> {
+    let n = base.components();
+    let index = (index.round() as i64).clamp(0, hival.max(0)) as usize;
+    let bytes = lookup.get(index * n..(index + 1) * n)?;
+    Some(match base {
+        ColorSpace::Lab(lab) => {
+            let [a_min, a_max, b_min, b_max] = lab.range.unwrap_or([-100., 100., -100., 100.]);
+            let v = |i: usize| bytes[i] as f64 / 255.;
+            vec![
+                v(0) * 100.,
+                a_min + v(1) * (a_max - a_min),
+                b_min + v(2) * (b_max - b_min),
+            ]
+        }
+        _ => bytes.iter().map(|&b| b as f64 / 255.).collect(),
+    })
+}
+
+fn gray_to_rgb(c: &[f64]) -> [f64; 3] {
+    [c[0], c[0], c[0]]
+}
+
+// the naive conversion from section 10.3.5 of the PDF 1.7 spec
+fn cmyk_to_rgb(c: &[f64]) -> [f64; 3] {
+    [
+        1. - (c[0] + c[3]).min(1.),
+        1. - (c[1] + c[3]).min(1.),
+        1. - (c[2] + c[3]).min(1.),
+    ]
+}
+
+// L*a*b* to XYZ relative to the space's white point, then the XYZ to linear sRGB matrix
+// and the sRGB transfer curve. We don't adapt the white point to D65, which is close
+// enough for the usual D50 and D65 white points.
+fn lab_to_rgb(lab: &Lab, c: &[f64]) -> [f64; 3] {
+    let fy = (c[0] + 16.) / 116.;
+    let fx = fy + c[1] / 500.;
+    let fz = fy - c[2] / 200.;
+    let g = |f: f64| {
+        if f > 6. / 29. {
+            f * f * f
+        } else {
+            3. * (6f64 / 29.).powi(2) * (f - 4. / 29.)
+        }
+    };
+    let [xw, yw, zw] = lab.white_point;
+    let (x, y, z) = (xw * g(fx), yw * g(fy), zw * g(fz));
+    let gamma = |l: f64| {
+        let l = l.clamp(0., 1.);
+        if l <= 0.0031308 {
+            12.92 * l
+        } else {
+            1.055 * l.powf(1. / 2.4) - 0.055
+        }
+    };
+    [
+        gamma(3.2406 * x - 1.5372 * y - 0.4986 * z),
+        gamma(-0.9689 * x + 1.8758 * y + 0.0415 * z),
+        gamma(0.0557 * x - 0.2040 * y + 1.0570 * z),
+    ]
+}
+
+fn alternate_to_rgb(colorspace: &AlternateColorSpace, color: &[f64]) -> Option<[f64; 3]> {
+    Some(match (colorspace, color.len()) {
+        (AlternateColorSpace::DeviceGray, 1) | (AlternateColorSpace::CalGray(_), 1) => {
+            gray_to_rgb(color)
+        }
+        (AlternateColorSpace::DeviceRGB, 3) | (AlternateColorSpace::CalRGB(_), 3) => {
+            [color[0], color[1], color[2]]
+        }
+        (AlternateColorSpace::DeviceCMYK, 4) => cmyk_to_rgb(color),
+        (AlternateColorSpace::Lab(lab), 3) => lab_to_rgb(lab, color),
+        (AlternateColorSpace::ICCBased(_), 1) => gray_to_rgb(color),
+        (AlternateColorSpace::ICCBased(_), 3) => [color[0], color[1], color[2]],
+        (AlternateColorSpace::ICCBased(_), 4) => cmyk_to_rgb(color),
+        _ => return None,
+    })
+}
+
+/// Convert `color` given in `colorspace` to RGB, with components from 0 to 1.
+/// Separations are converted through their tint transform and alternate space, as far as
+/// we can evaluate the transform. Colors we can't convert, like patterns, come out black.
+pub fn to_rgb(colorspace: &ColorSpace, color: &[f64]) -> [f64; 3] {
+    let rgb = |c: &[f64]| [c[0], c[1], c[2]];
+    match (colorspace, color.len()) {
+        (ColorSpace::DeviceGray, 1) | (ColorSpace::CalGray(_), 1) => gray_to_rgb(color),
+        (ColorSpace::DeviceRGB, 3) | (ColorSpace::CalRGB(_), 3) => rgb(color),
+        (ColorSpace::DeviceCMYK, 4) => cmyk_to_rgb(color),
+        (ColorSpace::Lab(lab), 3) => lab_to_rgb(lab, color),
+        // we don't interpret ICC profiles so go by the number of components
+        (ColorSpace::ICCBased(_), 1) => gray_to_rgb(color),
+        (ColorSpace::ICCBased(_), 3) => rgb(color),
+        (ColorSpace::ICCBased(_), 4) => cmyk_to_rgb(color),
+        (
+            ColorSpace::Indexed {
+                base,
+                hival,
+                lookup,
+            },
+            1,
+        ) => palette_color(base, *hival, lookup, color[0])
+            .map(|color| to_rgb(base, &color))
+            .unwrap_or([0., 0., 0.]),
+        // the tint transform gives as many components as the alternate space has, which for
+        // an ICCBased space is its /N
+        (ColorSpace::Separation(separation), 1) => alternate_to_rgb(
+            &separation.alternate_space,
+            &separation.tint_to_alternate(color),
+        )
+        .unwrap_or([0., 0., 0.]),
+        _ => [0., 0., 0.],
+    }
 }
 
 fn make_colorspace<'a>(doc: &'a Document, name: &[u8], resources: &'a Dictionary) -> ColorSpace {
@@ -582,119 +1301,165 @@ fn make_colorspace<'a>(doc: &'a Document, name: &[u8], resources: &'a Dictionary
             let colorspaces: &Dictionary = get(&doc, resources, b"ColorSpace");
             let cs: &Object = maybe_get_obj(doc, colorspaces, &name[..])
                 .unwrap_or_else(|| panic!("missing colorspace {:?}", &name[..]));
-            if let Ok(cs) = cs.as_array() {
-                let cs_name = pdf_to_utf8(cs[0].as_name().expect("first arg must be a name"));
-                match cs_name.as_ref() {
-                    "Separation" => {
-                        let name = pdf_to_utf8(cs[1].as_name().expect("second arg must be a name"));
-                        let alternate_space = match &maybe_deref(doc, &cs[2]) {
-                            Object::Name(name) => match &name[..] {
-                                b"DeviceGray" => AlternateColorSpace::DeviceGray,
-                                b"DeviceRGB" => AlternateColorSpace::DeviceRGB,
-                                b"DeviceCMYK" => AlternateColorSpace::DeviceCMYK,
-                                _ => panic!("unexpected color space name"),
-                            },
-                            Object::Array(cs) => {
-                                let cs_name =
-                                    pdf_to_utf8(cs[0].as_name().expect("first arg must be a name"));
-                                match cs_name.as_ref() {
-                                    "ICCBased" => {
-                                        let stream = maybe_deref(doc, &cs[1]).as_stream().unwrap();
-                                        dlog!("ICCBased {:?}", stream);
-                                        // XXX: we're going to be continually decompressing everytime this object is referenced
-                                        AlternateColorSpace::ICCBased(get_contents(stream))
-                                    }
-                                    "CalGray" => {
-                                        let dict =
-                                            cs[1].as_dict().expect("second arg must be a dict");
-                                        AlternateColorSpace::CalGray(CalGray {
-                                            white_point: get(&doc, dict, b"WhitePoint"),
-                                            black_point: get(&doc, dict, b"BackPoint"),
-                                            gamma: get(&doc, dict, b"Gamma"),
-                                        })
-                                    }
-                                    "CalRGB" => {
-                                        let dict =
-                                            cs[1].as_dict().expect("second arg must be a dict");
-                                        AlternateColorSpace::CalRGB(CalRGB {
-                                            white_point: get(&doc, dict, b"WhitePoint"),
-                                            black_point: get(&doc, dict, b"BackPoint"),
-                                            gamma: get(&doc, dict, b"Gamma"),
-                                            matrix: get(&doc, dict, b"Matrix"),
-                                        })
-                                    }
-                                    "Lab" => {
-                                        let dict =
-                                            cs[1].as_dict().expect("second arg must be a dict");
-                                        AlternateColorSpace::Lab(Lab {
-                                            white_point: get(&doc, dict, b"WhitePoint"),
-                                            black_point: get(&doc, dict, b"BackPoint"),
-                                            range: get(&doc, dict, b"Range"),
-                                        })
-                                    }
-                                    _ => panic!("Unexpected color space name"),
-                                }
+            colorspace_from_object(doc, cs)
+        }
+    }
+}
+
+// A colorspace given by the name of its family or by an array of the family and its parameters
+fn colorspace_from_object(doc: &Document, cs: &Object) -> ColorSpace {
+    if let Ok(cs) = cs.as_array() {
+        let cs_name = pdf_to_utf8(
+            maybe_deref(doc, &cs[0])
+                .as_name()
+                .expect("first arg must be a name"),
+        );
+        match cs_name.as_ref() {
+            "Separation" => {
+                let name = pdf_to_utf8(
+                    maybe_deref(doc, &cs[1])
+                        .as_name()
+                        .expect("second arg must be a name"),
+                );
+                let alternate_space = match &maybe_deref(doc, &cs[2]) {
+                    Object::Name(name) => match &name[..] {
+                        b"DeviceGray" => AlternateColorSpace::DeviceGray,
+                        b"DeviceRGB" => AlternateColorSpace::DeviceRGB,
+                        b"DeviceCMYK" => AlternateColorSpace::DeviceCMYK,
+                        _ => panic!("unexpected color space name"),
+                    },
+                    Object::Array(cs) => {
+                        let cs_name = pdf_to_utf8(
+                            maybe_deref(doc, &cs[0])
+                                .as_name()
+                                .expect("first arg must be a name"),
+                        );
+                        match cs_name.as_ref() {
+                            "ICCBased" => {
+                                let stream = maybe_deref(doc, &cs[1]).as_stream().unwrap();
+                                dlog!("ICCBased {:?}", stream);
+                                // XXX: we're going to be continually decompressing everytime this object is referenced
+                                AlternateColorSpace::ICCBased(get_contents(stream))
                             }
-                            _ => panic!("Alternate space should be name or array {:?}", cs[2]),
-                        };
-                        let tint_transform = Box::new(Function::new(doc, maybe_deref(doc, &cs[3])));
-
-                        dlog!("{:?} {:?} {:?}", name, alternate_space, tint_transform);
-                        ColorSpace::Separation(Separation {
-                            name,
-                            alternate_space,
-                            tint_transform,
-                        })
-                    }
-                    "ICCBased" => {
-                        let stream = maybe_deref(doc, &cs[1]).as_stream().unwrap();
-                        dlog!("ICCBased {:?}", stream);
-                        // XXX: we're going to be continually decompressing everytime this object is referenced
-                        ColorSpace::ICCBased(get_contents(stream))
-                    }
-                    "CalGray" => {
-                        let dict = cs[1].as_dict().expect("second arg must be a dict");
-                        ColorSpace::CalGray(CalGray {
-                            white_point: get(&doc, dict, b"WhitePoint"),
-                            black_point: get(&doc, dict, b"BackPoint"),
-                            gamma: get(&doc, dict, b"Gamma"),
-                        })
-                    }
-                    "CalRGB" => {
-                        let dict = cs[1].as_dict().expect("second arg must be a dict");
-                        ColorSpace::CalRGB(CalRGB {
-                            white_point: get(&doc, dict, b"WhitePoint"),
-                            black_point: get(&doc, dict, b"BackPoint"),
-                            gamma: get(&doc, dict, b"Gamma"),
-                            matrix: get(&doc, dict, b"Matrix"),
-                        })
-                    }
-                    "Lab" => {
-                        let dict = cs[1].as_dict().expect("second arg must be a dict");
-                        ColorSpace::Lab(Lab {
-                            white_point: get(&doc, dict, b"WhitePoint"),
-                            black_point: get(&doc, dict, b"BackPoint"),
-                            range: get(&doc, dict, b"Range"),
-                        })
+                            "CalGray" => {
+                                let dict = maybe_deref(doc, &cs[1])
+                                    .as_dict()
+                                    .expect("second arg must be a dict");
+                                AlternateColorSpace::CalGray(CalGray {
+                                    white_point: get(&doc, dict, b"WhitePoint"),
+                                    black_point: get(&doc, dict, b"BackPoint"),
+                                    gamma: get(&doc, dict, b"Gamma"),
+                                })
+                            }
+                            "CalRGB" => {
+                                let dict = maybe_deref(doc, &cs[1])
+                                    .as_dict()
+                                    .expect("second arg must be a dict");
+                                AlternateColorSpace::CalRGB(CalRGB {
+                                    white_point: get(&doc, dict, b"WhitePoint"),
+                                    black_point: get(&doc, dict, b"BackPoint"),
+                                    gamma: get(&doc, dict, b"Gamma"),
+                                    matrix: get(&doc, dict, b"Matrix"),
+                                })
+                            }
+                            "Lab" => {
+                                let dict = maybe_deref(doc, &cs[1])
+                                    .as_dict()
+                                    .expect("second arg must be a dict");
+                                AlternateColorSpace::Lab(Lab {
+                                    white_point: get(&doc, dict, b"WhitePoint"),
+                                    black_point: get(&doc, dict, b"BackPoint"),
+                                    range: get(&doc, dict, b"Range"),
+                                })
+                            }
+                            _ => panic!("Unexpected color space name"),
+                        }
                     }
-                    "Pattern" => ColorSpace::Pattern,
-                    "DeviceGray" => ColorSpace::DeviceGray,
-                    "DeviceRGB" => ColorSpace::DeviceRGB,
-                    "DeviceCMYK" => ColorSpace::DeviceCMYK,
-                    _ => {
-                        panic!("color_space {:?} {:?} {:?}", name, cs_name, cs)
+                    _ => panic!("Alternate space should be name or array {:?}", cs[2]),
+                };
+                let tint_transform = match Function::new(doc, maybe_deref(doc, &cs[3])) {
+                    Ok(f) => Some(Box::new(f)),
+                    Err(e) => {
+                        log::warn!("ignoring the tint transform of {}: {}", name, e);
+                        None
                     }
+                };
+
+                dlog!("{:?} {:?} {:?}", name, alternate_space, tint_transform);
+                ColorSpace::Separation(Separation {
+                    name,
+                    alternate_space,
+                    tint_transform,
+                })
+            }
+            "ICCBased" => {
+                let stream = maybe_deref(doc, &cs[1]).as_stream().unwrap();
+                dlog!("ICCBased {:?}", stream);
+                // XXX: we're going to be continually decompressing everytime this object is referenced
+                ColorSpace::ICCBased(get_contents(stream))
+            }
+            "CalGray" => {
+                let dict = maybe_deref(doc, &cs[1])
+                    .as_dict()
+                    .expect("second arg must be a dict");
+                ColorSpace::CalGray(CalGray {
+                    white_point: get(&doc, dict, b"WhitePoint"),
+                    black_point: get(&doc, dict, b"BackPoint"),
+                    gamma: get(&doc, dict, b"Gamma"),
+                })
+            }
+            "CalRGB" => {
+                let dict = maybe_deref(doc, &cs[1])
+                    .as_dict()
+                    .expect("second arg must be a dict");
+                ColorSpace::CalRGB(CalRGB {
+                    white_point: get(&doc, dict, b"WhitePoint"),
+                    black_point: get(&doc, dict, b"BackPoint"),
+                    gamma: get(&doc, dict, b"Gamma"),
+                    matrix: get(&doc, dict, b"Matrix"),
+                })
+            }
+            "Lab" => {
+                let dict = maybe_deref(doc, &cs[1])
+                    .as_dict()
+                    .expect("second arg must be a dict");
+                ColorSpace::Lab(Lab {
+                    white_point: get(&doc, dict, b"WhitePoint"),
+                    black_point: get(&doc, dict, b"BackPoint"),
+                    range: get(&doc, dict, b"Range"),
+                })
+            }
+            "Pattern" => ColorSpace::Pattern,
+            "DeviceGray" => ColorSpace::DeviceGray,
+            "DeviceRGB" => ColorSpace::DeviceRGB,
+            "DeviceCMYK" => ColorSpace::DeviceCMYK,
+            "Indexed" | "I" => {
+                let base = colorspace_from_object(doc, maybe_deref(doc, &cs[1]));
+                let hival = as_num(maybe_deref(doc, &cs[2])) as i64;
+                let lookup = match maybe_deref(doc, &cs[3]) {
+                    Object::String(lookup, _) => lookup.clone(),
+                    Object::Stream(stream) => get_contents(stream),
+                    lookup => panic!("unexpected Indexed lookup {:?}", lookup),
+                };
+                ColorSpace::Indexed {
+                    base: Box::new(base),
+                    hival,
+                    lookup,
                 }
-            } else if let Ok(cs) = cs.as_name() {
-                match pdf_to_utf8(cs).as_ref() {
-                    "DeviceRGB" => ColorSpace::DeviceRGB,
-                    "DeviceGray" => ColorSpace::DeviceGray,
-                    _ => panic!(),
-                }
-            } else {
-                panic!();
             }
+            _ => {
+                panic!("color_space {:?} {:?}", cs_name, cs)
+            }
+        }
+    } else if let Ok(cs) = cs.as_name() {
+        match pdf_to_utf8(cs).as_ref() {
+            "DeviceRGB" => ColorSpace::DeviceRGB,
+            "DeviceGray" => ColorSpace::DeviceGray,
+            "DeviceCMYK" => ColorSpace::DeviceCMYK,
+            _ => panic!(),
         }
+    } else {
+        panic!();
     }
 }
 
@@ -705,13 +1470,68 @@ pub fn extract_text<P: std::convert::AsRef<std::path::Path>>(
     let mut s = String::new();
     {
         let mut output = PlainTextOutput::new(&mut s);
-        let mut doc = Document::load(path)?;
+        let mut doc = load_path_with_recovery(path)?;
         maybe_decrypt(&mut doc)?;
         output_doc(&doc, &mut output)?;
     }
     Ok(s)
 }
 
+// The number of objects in the xref table that lopdf couldn't load. It drops those silently,
+// which happens when the offsets are wrong, like in the damaged first-page xref of a
+// linearized file.
+fn unloaded_objects(doc: &Document) -> usize {
+    doc.reference_table
+        .entries
+        .iter()
+        .filter(|(&id, entry)| {
+            matches!(entry, lopdf::xref::XrefEntry::Normal { .. })
+                && doc.objects.range((id, 0)..=(id, u16::MAX)).next().is_none()
+        })
+        .count()
+}
+
+/// Load a document from `buffer`. If that fails, for instance because the cross-reference table
+/// is damaged or missing, try again after rebuilding it by scanning the file for objects.
+/// We also rebuild it when objects it lists can't be read at their offsets, and use the rebuilt
+/// document if that recovers more of them.
+pub fn load_with_recovery(buffer: &[u8]) -> Result<Document, OutputError> {
+    match Document::load_mem(buffer) {
+        Ok(doc) => {
+            let unloaded = unloaded_objects(&doc);
+            if unloaded == 0 {
+                return Ok(doc);
+            }
+            match rebuild_xref(buffer).and_then(|repaired| Document::load_mem(&repaired).ok()) {
+                Some(repaired) if repaired.objects.len() > doc.objects.len() => {
+                    log::warn!(
+                        "{} objects weren't where the xref table put them, recovered the document by rebuilding it",
+                        unloaded
+                    );
+                    Ok(repaired)
+                }
+                _ => Ok(doc),
+            }
+        }
+        Err(e) => match rebuild_xref(buffer) {
+            Some(repaired) => {
+                log::warn!(
+                    "failed to load document ({}), recovered it by rebuilding the xref table",
+                    e
+                );
+                Ok(Document::load_mem(&repaired)?)
+            }
+            None => Err(e.into()),
+        },
+    }
+}
+
+fn load_path_with_recovery<P: std::convert::AsRef<std::path::Path>>(
+    path: P,
+) -> Result<Document, OutputError> {
+    load_with_recovery(&std::fs::read(path)?)
+}
+
 fn maybe_decrypt(doc: &mut Document) -> Result<(), OutputError> {
     if !doc.is_encrypted() {
         return Ok(());
@@ -719,7 +1539,7 @@ fn maybe_decrypt(doc: &mut Document) -> Result<(), OutputError> {
 
     if let Err(e) = doc.decrypt("") {
         if let Error::Decryption(DecryptionError::IncorrectPassword) = e {
-            eprintln!("Encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted")
+            log::warn!("encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted")
         }
 
         return Err(OutputError::PdfError(e));
@@ -735,7 +1555,7 @@ pub fn extract_text_encrypted<P: std::convert::AsRef<std::path::Path>, PW: AsRef
     let mut s = String::new();
     {
         let mut output = PlainTextOutput::new(&mut s);
-        let mut doc = Document::load(path)?;
+        let mut doc = load_path_with_recovery(path)?;
         output_doc_encrypted(&mut doc, &mut output, password)?;
     }
     Ok(s)
@@ -745,7 +1565,7 @@ pub fn extract_text_from_mem(buffer: &[u8]) -> Result<String, OutputError> {
     let mut s = String::new();
     {
         let mut output = PlainTextOutput::new(&mut s);
-        let mut doc = Document::load_mem(buffer)?;
+        let mut doc = load_with_recovery(buffer)?;
         maybe_decrypt(&mut doc)?;
         output_doc(&doc, &mut output)?;
     }
@@ -759,7 +1579,7 @@ pub fn extract_text_from_mem_encrypted<PW: AsRef<[u8]>>(
     let mut s = String::new();
     {
         let mut output = PlainTextOutput::new(&mut s);
-        let mut doc = Document::load_mem(buffer)?;
+        let mut doc = load_with_recovery(buffer)?;
         output_doc_encrypted(&mut doc, &mut output, password)?;
     }
     Ok(s)
@@ -781,7 +1601,7 @@ pub fn extract_text_by_pages<P: std::convert::AsRef<std::path::Path>>(
 ) -> Result<Vec<String>, OutputError> {
     let mut v = Vec::new();
     {
-        let mut doc = Document::load(path)?;
+        let mut doc = load_path_with_recovery(path)?;
         maybe_decrypt(&mut doc)?;
         let mut page_num = 1;
         while let Ok(content) = extract_text_by_page(&doc, page_num) {
@@ -792,13 +1612,56 @@ pub fn extract_text_by_pages<P: std::convert::AsRef<std::path::Path>>(
     Ok(v)
 }
 
+/// Like `extract_text_by_pages` but calls `progress` with the number of the page and the
+/// number of pages each time a page is done, e.g. for a progress bar
+pub fn extract_text_by_pages_with_progress<P: std::convert::AsRef<std::path::Path>>(
+    path: P,
+    mut progress: impl FnMut(u32, u32),
+) -> Result<Vec<String>, OutputError> {
+    let mut doc = load_path_with_recovery(path)?;
+    maybe_decrypt(&mut doc)?;
+    Ok(extract_pages_with_progress(&doc, &mut progress))
+}
+
+/// Like `extract_text_from_mem_by_pages` but calls `progress` with the number of the page and
+/// the number of pages each time a page is done
+pub fn extract_text_from_mem_by_pages_with_progress(
+    buffer: &[u8],
+    mut progress: impl FnMut(u32, u32),
+) -> Result<Vec<String>, OutputError> {
+    let mut doc = load_with_recovery(buffer)?;
+    maybe_decrypt(&mut doc)?;
+    Ok(extract_pages_with_progress(&doc, &mut progress))
+}
+
+fn extract_pages_with_progress(doc: &Document, progress: &mut dyn FnMut(u32, u32)) -> Vec<String> {
+    let total = doc.get_pages().len() as u32;
+    let mut v = Vec::new();
+    let mut page_num = 1;
+    while let Ok(content) = extract_text_by_page(doc, page_num) {
+        v.push(content);
+        progress(page_num, total);
+        page_num += 1;
+    }
+    v
+}
+
+/// Like `extract_text_by_pages` but with the last page first
+pub fn extract_text_by_pages_reversed<P: std::convert::AsRef<std::path::Path>>(
+    path: P,
+) -> Result<Vec<String>, OutputError> {
+    let mut v = extract_text_by_pages(path)?;
+    v.reverse();
+    Ok(v)
+}
+
 pub fn extract_text_by_pages_encrypted<P: std::convert::AsRef<std::path::Path>, PW: AsRef<[u8]>>(
     path: P,
     password: PW,
 ) -> Result<Vec<String>, OutputError> {
     let mut v = Vec::new();
     {
-        let mut doc = Document::load(path)?;
+        let mut doc = load_path_with_recovery(path)?;
         doc.decrypt(password)?;
         let mut page_num = 1;
         while let Ok(content) = extract_text_by_page(&mut doc, page_num) {
@@ -812,7 +1675,7 @@ pub fn extract_text_by_pages_encrypted<P: std::convert::AsRef<std::path::Path>,
 pub fn extract_text_from_mem_by_pages(buffer: &[u8]) -> Result<Vec<String>, OutputError> {
     let mut v = Vec::new();
     {
-        let mut doc = Document::load_mem(buffer)?;
+        let mut doc = load_with_recovery(buffer)?;
         maybe_decrypt(&mut doc)?;
         let mut page_num = 1;
         while let Ok(content) = extract_text_by_page(&doc, page_num) {
@@ -823,13 +1686,20 @@ pub fn extract_text_from_mem_by_pages(buffer: &[u8]) -> Result<Vec<String>, Outp
     Ok(v)
 }
 
+/// Like `extract_text_from_mem_by_pages` but with the last page first
+pub fn extract_text_from_mem_by_pages_reversed(buffer: &[u8]) -> Result<Vec<String>, OutputError> {
+    let mut v = extract_text_from_mem_by_pages(buffer)?;
+    v.reverse();
+    Ok(v)
+}
+
 pub fn extract_text_from_mem_by_pages_encrypted<PW: AsRef<[u8]>>(
     buffer: &[u8],
     password: PW,
 ) -> Result<Vec<String>, OutputError> {
     let mut v = Vec::new();
     {
-        let mut doc = Document::load_mem(buffer)?;
+        let mut doc = load_with_recovery(buffer)?;
         doc.decrypt(password)?;
         let mut page_num = 1;
         while let Ok(content) = extract_text_by_page(&doc, page_num) {
@@ -840,45 +1710,376 @@ pub fn extract_text_from_mem_by_pages_encrypted<PW: AsRef<[u8]>>(
     Ok(v)
 }
 
-fn get_inherited<'a, T: FromObj<'a>>(
-    doc: &'a Document,
-    dict: &'a Dictionary,
-    key: &[u8],
-) -> Option<T> {
-    let o: Option<T> = get(doc, dict, key);
-    if let Some(o) = o {
-        Some(o)
-    } else {
-        let parent = dict
-            .get(b"Parent")
-            .and_then(|parent| parent.as_reference())
-            .and_then(|id| doc.get_dictionary(id))
-            .ok()?;
-        get_inherited(doc, parent, key)
-    }
+/// Extract the text from a pdf at `path` with a result for every page, so that a page
+/// that can't be extracted doesn't hide the pages after it
+pub fn extract_text_by_pages_results<P: std::convert::AsRef<std::path::Path>>(
+    path: P,
+) -> Result<Vec<Result<String, OutputError>>, OutputError> {
+    let mut doc = load_path_with_recovery(path)?;
+    maybe_decrypt(&mut doc)?;
+    Ok(extract_text_of_every_page(&doc))
 }
 
-pub fn output_doc_encrypted<PW: AsRef<[u8]>>(
-    doc: &mut Document,
-    output: &mut dyn OutputDev,
-    password: PW,
-) -> Result<(), OutputError> {
-    doc.decrypt(password)?;
-    output_doc(doc, output)
+/// Like `extract_text_by_pages_results` but for a pdf in memory
+pub fn extract_text_from_mem_by_pages_results(
+    buffer: &[u8],
+) -> Result<Vec<Result<String, OutputError>>, OutputError> {
+    let mut doc = load_with_recovery(buffer)?;
+    maybe_decrypt(&mut doc)?;
+    Ok(extract_text_of_every_page(&doc))
 }
 
-/// Parse a given document and output it to `output`
-pub fn output_doc(doc: &Document, output: &mut dyn OutputDev) -> Result<(), OutputError> {
-    if doc.is_encrypted() {
-        eprintln!("Encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted");
-    }
-    let empty_resources = Dictionary::new();
+fn extract_text_of_every_page(doc: &Document) -> Vec<Result<String, OutputError>> {
+    doc.get_pages()
+        .keys()
+        .map(|page_num| extract_text_by_page(doc, *page_num))
+        .collect()
+}
+
+// Wraps a device and fails once `remaining` printable characters have been drawn so that
+// processing stops early. `done` tells that failure apart from real errors.
+struct PreviewOutput<'a> {
+    output: &'a mut dyn OutputDev,
+    remaining: usize,
+    done: bool,
+}
+
+impl OutputDev for PreviewOutput<'_> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.output.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        self.output.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.output
+            .output_character(trm, width, spacing, font_size, char)?;
+        let printable = char
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+            .count();
+        self.remaining = self.remaining.saturating_sub(printable);
+        if self.remaining == 0 {
+            self.done = true;
+            return Err(fmt::Error.into());
+        }
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.output.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.output.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.output.end_line()
+    }
+}
+
+/// The text at the start of `doc`, up to `max_chars` printable characters plus the whitespace
+/// between them, e.g. for showing a snippet in search results. Processing stops as soon as
+/// there are enough characters, even in the middle of a page.
+pub fn extract_preview(doc: &Document, max_chars: usize) -> Result<String, OutputError> {
+    let mut s = String::new();
+    if max_chars == 0 {
+        return Ok(s);
+    }
+    {
+        let mut text = PlainTextOutput::new(&mut s);
+        let mut output = PreviewOutput {
+            output: &mut text,
+            remaining: max_chars,
+            done: false,
+        };
+        match output_doc(doc, &mut output) {
+            // the page was cut short so make sure what we have of it is written out
+            Err(_) if output.done => text.end_page()?,
+            result => result?,
+        }
+    }
+    Ok(s.trim().to_owned())
+}
+
+/// A writing system, as told apart by `detect_primary_script`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    /// Chinese, Japanese and Korean
+    Cjk,
+    /// no letters from any of the others
+    Unknown,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c as u32 {
+        0x41..=0x5a | 0x61..=0x7a | 0xc0..=0x24f | 0x1e00..=0x1eff => Some(Script::Latin),
+        0x370..=0x3ff | 0x1f00..=0x1fff => Some(Script::Greek),
+        0x400..=0x52f => Some(Script::Cyrillic),
+        0x590..=0x5ff | 0xfb1d..=0xfb4f => Some(Script::Hebrew),
+        0x600..=0x6ff | 0x750..=0x77f | 0x8a0..=0x8ff | 0xfb50..=0xfdff | 0xfe70..=0xfeff => {
+            Some(Script::Arabic)
+        }
+        0x3040..=0x30ff | 0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xac00..=0xd7af | 0xf900..=0xfaff => {
+            Some(Script::Cjk)
+        }
+        _ => None,
+    }
+}
+
+/// Guess the script that most of `doc` is written in from the letters at its start, e.g. to turn
+/// on right-to-left handling for Arabic documents that have no /Lang. When scripts are tied the
+/// one whose letters come first wins.
+pub fn detect_primary_script(doc: &Document) -> Result<Script, OutputError> {
+    // enough letters to outweigh headers, page numbers and the odd foreign word
+    const SAMPLE_CHARS: usize = 2000;
+    let sample = extract_preview(doc, SAMPLE_CHARS)?;
+    // in the order the scripts are first seen
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for script in sample.chars().filter_map(script) {
+        match counts.iter_mut().find(|(s, _)| *s == script) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+    // max_by_key returns the last of equal maximums
+    Ok(counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map_or(Script::Unknown, |(script, _)| script))
+}
+
+fn get_inherited<'a, T: FromObj<'a>>(
+    doc: &'a Document,
+    dict: &'a Dictionary,
+    key: &[u8],
+) -> Option<T> {
+    let o: Option<T> = get(doc, dict, key);
+    if let Some(o) = o {
+        Some(o)
+    } else {
+        let parent = dict
+            .get(b"Parent")
+            .and_then(|parent| parent.as_reference())
+            .and_then(|id| doc.get_dictionary(id))
+            .ok()?;
+        get_inherited(doc, parent, key)
+    }
+}
+
+pub fn output_doc_encrypted<PW: AsRef<[u8]>>(
+    doc: &mut Document,
+    output: &mut dyn OutputDev,
+    password: PW,
+) -> Result<(), OutputError> {
+    doc.decrypt(password)?;
+    output_doc(doc, output)
+}
+
+/// Parse a given document and output it to `output`
+pub fn output_doc(doc: &Document, output: &mut dyn OutputDev) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new())
+}
+
+/// Like `output_doc` but glyphs that can't be decoded any other way are given to `resolver`
+pub fn output_doc_with_glyph_resolver(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    resolver: &dyn GlyphResolver,
+) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new().glyph_resolver(resolver))
+}
+
+/// Like `output_doc` but calls `unknown_operator` with every operator that isn't handled,
+/// e.g. to deal with vendor extensions or to find out what a document relies on
+pub fn output_doc_with_unknown_operator(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    unknown_operator: &mut dyn FnMut(&content::Operation),
+) -> Result<(), OutputError> {
+    let options = OutputOptions::new().unknown_operator(unknown_operator);
+    output_doc_with_options(doc, output, options)
+}
+
+/// Like `output_doc` but content streams with more than `max_depth` nested `q` operators fail
+/// with an error. `output_doc` uses `DEFAULT_MAX_GS_DEPTH`.
+pub fn output_doc_with_max_gs_depth(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    max_depth: usize,
+) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new().max_gs_depth(max_depth))
+}
+
+/// Like `output_doc` but goes from the last page to the first, e.g. for books bound
+/// right-to-left. The pages keep their numbers.
+pub fn output_doc_reversed(doc: &Document, output: &mut dyn OutputDev) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new().reversed(true))
+}
+
+/// Like `output_doc` but with the advances of glyphs coming from `advance`
+pub fn output_doc_with_glyph_advance(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    advance: GlyphAdvance,
+) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new().glyph_advance(advance))
+}
+
+/// Like `output_doc` but calls `progress` with the number of pages done so far and the
+/// number of pages each time a page is done
+pub fn output_doc_with_progress(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    mut progress: impl FnMut(u32, u32),
+) -> Result<(), OutputError> {
+    output_doc_with_options(doc, output, OutputOptions::new().progress(&mut progress))
+}
+
+/// The ways that `output_doc_with_options` can customize processing. Each of the
+/// `output_doc_with_*` functions sets one of them, this combines any of them:
+///
+/// ```no_run
+/// use ara_yaaaay::output::PlainTextOutput;
+/// use ara_yaaaay::{output_doc_with_options, GlyphAdvance, OutputOptions};
+///
+/// let doc = ara_yaaaay::Document::load("book.pdf")?;
+/// let mut text = String::new();
+/// let mut output = PlainTextOutput::new(&mut text);
+/// let mut report = |done, total| eprintln!("{}/{}", done, total);
+/// let options = OutputOptions::new()
+///     .reversed(true)
+///     .glyph_advance(GlyphAdvance::Positioning)
+///     .progress(&mut report);
+/// output_doc_with_options(&doc, &mut output, options)?;
+/// # Ok::<(), ara_yaaaay::OutputError>(())
+/// ```
+pub struct OutputOptions<'a> {
+    resolver: Option<&'a dyn GlyphResolver>,
+    unknown_operator: Option<&'a mut dyn FnMut(&content::Operation)>,
+    max_gs_depth: usize,
+    reverse: bool,
+    glyph_advance: GlyphAdvance,
+    progress: Option<&'a mut dyn FnMut(u32, u32)>,
+    font_substitutes: Option<&'a FontSubstitutes>,
+}
+
+impl Default for OutputOptions<'_> {
+    fn default() -> Self {
+        OutputOptions {
+            resolver: None,
+            unknown_operator: None,
+            max_gs_depth: DEFAULT_MAX_GS_DEPTH,
+            reverse: false,
+            glyph_advance: GlyphAdvance::FontWidths,
+            progress: None,
+            font_substitutes: None,
+        }
+    }
+}
+
+impl<'a> OutputOptions<'a> {
+    /// The options that `output_doc` uses
+    pub fn new() -> OutputOptions<'a> {
+        OutputOptions::default()
+    }
+
+    /// See `output_doc_with_glyph_resolver`
+    pub fn glyph_resolver(mut self, resolver: &'a dyn GlyphResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// See `output_doc_with_unknown_operator`
+    pub fn unknown_operator(
+        mut self,
+        unknown_operator: &'a mut dyn FnMut(&content::Operation),
+    ) -> Self {
+        self.unknown_operator = Some(unknown_operator);
+        self
+    }
+
+    /// See `output_doc_with_max_gs_depth`
+    pub fn max_gs_depth(mut self, max_depth: usize) -> Self {
+        self.max_gs_depth = max_depth;
+        self
+    }
+
+    /// See `output_doc_reversed`
+    pub fn reversed(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    /// See `output_doc_with_glyph_advance`
+    pub fn glyph_advance(mut self, advance: GlyphAdvance) -> Self {
+        self.glyph_advance = advance;
+        self
+    }
+
+    /// See `output_doc_with_progress`
+    pub fn progress(mut self, progress: &'a mut dyn FnMut(u32, u32)) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The metrics to use for fonts without widths or an embedded font program
+    pub fn font_substitutes(mut self, substitutes: &'a FontSubstitutes) -> Self {
+        self.font_substitutes = Some(substitutes);
+        self
+    }
+}
+
+/// Like `output_doc` but customized by `options`
+pub fn output_doc_with_options<'a, 'o: 'a>(
+    doc: &'a Document,
+    output: &mut dyn OutputDev,
+    options: OutputOptions<'o>,
+) -> Result<(), OutputError> {
+    if doc.is_encrypted() {
+        log::warn!("encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted");
+    }
+    let empty_resources = Dictionary::new();
     let pages = doc.get_pages();
-    let mut p = Processor::new();
-    for dict in pages {
-        let page_num = dict.0;
-        let object_id = dict.1;
+    let mut p = match options.resolver {
+        Some(resolver) => Processor::with_glyph_resolver(resolver),
+        None => Processor::new(),
+    };
+    if let Some(unknown_operator) = options.unknown_operator {
+        p.set_unknown_operator(unknown_operator);
+    }
+    p.set_max_gs_depth(options.max_gs_depth);
+    p.set_glyph_advance(options.glyph_advance);
+    if let Some(substitutes) = options.font_substitutes {
+        p.set_font_substitutes(substitutes);
+    }
+    let mut pages: Vec<_> = pages.into_iter().collect();
+    if options.reverse {
+        pages.reverse();
+    }
+    let total = pages.len() as u32;
+    let mut progress = options.progress;
+    for (i, (page_num, object_id)) in pages.into_iter().enumerate() {
         output_doc_inner(page_num, object_id, doc, &mut p, output, &empty_resources)?;
+        if let Some(progress) = progress.as_mut() {
+            progress(i as u32 + 1, total);
+        }
     }
     Ok(())
 }
@@ -889,7 +2090,7 @@ pub fn output_doc_page(
     page_num: u32,
 ) -> Result<(), OutputError> {
     if doc.is_encrypted() {
-        eprintln!("Encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted");
+        log::warn!("encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted");
     }
     let empty_resources = Dictionary::new();
     let pages = doc.get_pages();
@@ -901,6 +2102,1262 @@ pub fn output_doc_page(
     Ok(())
 }
 
+/// Output a piece of page `page_num`: `count` operators of its content starting from operator
+/// `start`. Returns where the next piece starts, or `None` once the page is done. `begin_page`
+/// and `end_page` are called for every piece.
+///
+/// Every call decodes the page again and runs the operators before `start` to rebuild the
+/// state that the piece starts with, so use [`PageOperations`] to work through a whole page a
+/// piece at a time.
+pub fn output_doc_page_operations(
+    doc: &Document,
+    output: &mut dyn OutputDev,
+    page_num: u32,
+    start: usize,
+    count: usize,
+) -> Result<Option<usize>, OutputError> {
+    let mut page = PageOperations::new(doc, page_num)?;
+    page.skip(start)?;
+    page.output_next(output, count)
+}
+
+/// A page that is output a piece at a time, to work through huge pages without holding up
+/// the caller. The content is decoded once and the state of the content stream (graphics
+/// state stack, text matrices, fonts) carries on from one piece to the next.
+pub struct PageOperations<'a> {
+    doc: &'a Document,
+    page_num: u32,
+    page_dict: &'a Dictionary,
+    resources: &'a Dictionary,
+    media_box: MediaBox,
+    art_box: Option<(f64, f64, f64, f64)>,
+    processor: Processor<'a>,
+    content: processor::DecodedContent,
+    state: processor::StreamState<'a>,
+}
+
+impl<'a> PageOperations<'a> {
+    pub fn new(doc: &'a Document, page_num: u32) -> Result<PageOperations<'a>, OutputError> {
+        static EMPTY_RESOURCES: std::sync::OnceLock<Dictionary> = std::sync::OnceLock::new();
+        let pages = doc.get_pages();
+        let object_id = pages
+            .get(&page_num)
+            .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+        let page_dict = doc.get_dictionary(*object_id)?;
+        let resources = get_inherited(doc, page_dict, b"Resources")
+            .unwrap_or_else(|| EMPTY_RESOURCES.get_or_init(Dictionary::new));
+        let media_box = get_box(doc, page_dict, b"MediaBox", true).ok_or(lopdf::Error::DictKey)?;
+        let art_box =
+            get::<Option<Vec<f64>>>(doc, page_dict, b"ArtBox").map(|x| (x[0], x[1], x[2], x[3]));
+        let processor = Processor::new();
+        let state = processor.new_state();
+        Ok(PageOperations {
+            doc,
+            page_num,
+            page_dict,
+            resources,
+            media_box,
+            art_box,
+            processor,
+            content: processor::DecodedContent::decode(page_content(doc, page_dict)?)?,
+            state,
+        })
+    }
+
+    /// The number of operators in the page's content
+    pub fn operation_count(&self) -> usize {
+        self.content.len()
+    }
+
+    /// The operator that the next piece starts from
+    pub fn position(&self) -> usize {
+        self.state.next()
+    }
+
+    /// Run the page up to operator `end` without any output, e.g. to carry on from where an
+    /// earlier handle stopped
+    pub fn skip(&mut self, end: usize) -> Result<(), OutputError> {
+        self.run(&mut processor::NullOutput, end)
+    }
+
+    /// Output the next `count` operators. Returns where the piece after it starts, or `None`
+    /// once the page is done. `begin_page` and `end_page` are called for every piece.
+    pub fn output_next(
+        &mut self,
+        output: &mut dyn OutputDev,
+        count: usize,
+    ) -> Result<Option<usize>, OutputError> {
+        output.page_rotation(page_rotation(self.doc, self.page_dict))?;
+        output.begin_page(self.page_num, &self.media_box, self.art_box)?;
+        let end = self.position().saturating_add(count);
+        self.run(output, end)?;
+        output.end_page()?;
+        Ok(if self.position() < self.operation_count() {
+            Some(self.position())
+        } else {
+            None
+        })
+    }
+
+    fn run(&mut self, output: &mut dyn OutputDev, end: usize) -> Result<(), OutputError> {
+        self.processor.run(
+            self.doc,
+            &self.content,
+            &mut self.state,
+            end,
+            self.resources,
+            &self.media_box,
+            output,
+            self.page_num,
+        )
+    }
+}
+
+/// Output every page of `doc` to its own device. `make_output` is called with the page number
+/// before each page, e.g. to create one HTML file per page, and the device is finished and
+/// dropped once the page has ended.
+pub fn output_doc_per_page<'o, F>(doc: &Document, mut make_output: F) -> Result<(), OutputError>
+where
+    F: FnMut(u32) -> Box<dyn OutputDev + 'o>,
+{
+    if doc.is_encrypted() {
+        log::warn!("encrypted documents must be decrypted with a password using {{extract_text|extract_text_from_mem|output_doc}}_encrypted");
+    }
+    let empty_resources = Dictionary::new();
+    let mut p = Processor::new();
+    for (page_num, object_id) in doc.get_pages() {
+        let mut output = make_output(page_num);
+        output_doc_inner(
+            page_num,
+            object_id,
+            doc,
+            &mut p,
+            output.as_mut(),
+            &empty_resources,
+        )?;
+        output.finish()?;
+    }
+    Ok(())
+}
+
+fn get_box(
+    doc: &Document,
+    page_dict: &Dictionary,
+    key: &[u8],
+    inherited: bool,
+) -> Option<MediaBox> {
+    let b: Vec<f64> = if inherited {
+        get_inherited(doc, page_dict, key)?
+    } else {
+        get::<Option<Vec<f64>>>(doc, page_dict, key)?
+    };
+    if b.len() != 4 {
+        dlog!("malformed {:?} {:?}", key, b);
+        return None;
+    }
+    Some(MediaBox {
+        llx: b[0],
+        lly: b[1],
+        urx: b[2],
+        ury: b[3],
+    })
+}
+
+/// The page boundaries from section 14.11.2 of the spec along with the page rotation
+#[derive(Debug, Clone, Copy)]
+pub struct PageBoxes {
+    pub media_box: MediaBox,
+    pub crop_box: MediaBox,
+    pub bleed_box: MediaBox,
+    pub trim_box: MediaBox,
+    pub art_box: MediaBox,
+    /// the clockwise rotation of the page, one of 0, 90, 180 or 270
+    pub rotate: i64,
+}
+
+/// Read the boxes of page `page_num` without processing its content. Missing boxes get their
+/// defaults: the CropBox defaults to the MediaBox and the others default to the CropBox.
+pub fn page_boxes(doc: &Document, page_num: u32) -> Result<PageBoxes, OutputError> {
+    let pages = doc.get_pages();
+    let object_id = pages
+        .get(&page_num)
+        .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+    let page_dict = doc.get_dictionary(*object_id)?;
+    let media_box = get_box(doc, page_dict, b"MediaBox", true).ok_or(lopdf::Error::DictKey)?;
+    let crop_box = get_box(doc, page_dict, b"CropBox", true).unwrap_or(media_box);
+    Ok(PageBoxes {
+        media_box,
+        crop_box,
+        bleed_box: get_box(doc, page_dict, b"BleedBox", false).unwrap_or(crop_box),
+        trim_box: get_box(doc, page_dict, b"TrimBox", false).unwrap_or(crop_box),
+        art_box: get_box(doc, page_dict, b"ArtBox", false).unwrap_or(crop_box),
+        rotate: page_rotation(doc, page_dict),
+    })
+}
+
+/// The inherited /Rotate of a page normalized to 0, 90, 180 or 270. Values that aren't a
+/// multiple of 90 are invalid and treated as 0.
+fn page_rotation(doc: &Document, page_dict: &Dictionary) -> i64 {
+    let rotate: f64 = match get_inherited(doc, page_dict, b"Rotate") {
+        Some(rotate) => rotate,
+        None => return 0,
+    };
+    let normalized = ((rotate % 360.) + 360.) % 360.;
+    let quarter_turns = (normalized / 90.).round();
+    if (normalized - quarter_turns * 90.).abs() > 0.5 {
+        log::warn!("ignoring /Rotate {} which isn't a multiple of 90", rotate);
+        return 0;
+    }
+    (quarter_turns as i64 % 4) * 90
+}
+
+/// Collect the characters of page `page_num` along with their positions
+pub fn positioned_chars(doc: &Document, page_num: u32) -> Result<Vec<PositionedChar>, OutputError> {
+    let mut output = PositionedOutput::new();
+    output_doc_page(doc, &mut output, page_num)?;
+    Ok(output.chars)
+}
+
+/// A character of the document as a flat record, for bindings and columnar formats. With the
+/// `serde` feature records can be serialized, e.g. to JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExtractRecord {
+    pub page: u32,
+    pub text: String,
+    /// the origin of the glyph on the baseline, in the same space as `positioned_chars`
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    /// the height of the character's box, see `PositionedChar::bbox`
+    pub height: f64,
+    /// the /BaseFont of the font, without a subset tag
+    pub font_name: String,
+    pub font_size: f64,
+}
+
+// Collects characters along with the name of the font they're drawn with
+struct RecordOutput {
+    chars: PositionedOutput,
+    font_name: String,
+    font_names: Vec<String>,
+}
+
+impl RecordOutput {
+    // give the characters that were just collected the current font
+    fn name_fonts(&mut self) {
+        let new = self.chars.chars.len() - self.font_names.len();
+        self.font_names
+            .extend(std::iter::repeat_n(self.font_name.clone(), new));
+    }
+}
+
+impl OutputDev for RecordOutput {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.chars.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        self.chars.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character(trm, width, spacing, font_size, char)?;
+        self.name_fonts();
+        Ok(())
+    }
+    fn output_character_with_color(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+        rgb: [f64; 3],
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character_with_color(trm, width, spacing, font_size, char, rgb)?;
+        self.name_fonts();
+        Ok(())
+    }
+    fn set_font(&mut self, base_font: &str, _style: font::FontStyle) -> Result<(), OutputError> {
+        self.font_name = font::strip_subset_tag(base_font).to_owned();
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.chars.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.chars.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.chars.end_line()
+    }
+}
+
+// The characters of `doc` along with the names of their fonts
+fn chars_with_fonts(doc: &Document) -> Result<(Vec<PositionedChar>, Vec<String>), OutputError> {
+    let mut output = RecordOutput {
+        chars: PositionedOutput::new(),
+        font_name: String::new(),
+        font_names: Vec::new(),
+    };
+    output_doc(doc, &mut output)?;
+    Ok((output.chars.chars, output.font_names))
+}
+
+/// Every character of `doc` as an `ExtractRecord`, page by page in content stream order
+pub fn extract_records(doc: &Document) -> Result<Vec<ExtractRecord>, OutputError> {
+    let (chars, font_names) = chars_with_fonts(doc)?;
+    Ok(chars
+        .into_iter()
+        .zip(font_names)
+        .map(|(c, font_name)| {
+            let (_, lly, _, ury) = c.bbox();
+            ExtractRecord {
+                page: c.page_num,
+                text: c.char,
+                x: c.x,
+                y: c.y,
+                width: c.width,
+                height: ury - lly,
+                font_name,
+                font_size: c.font_size,
+            }
+        })
+        .collect())
+}
+
+/// The text of `doc` as paragraphs. Consecutive lines of a page are joined with spaces as long
+/// as they start with the same font and size, share a left margin and keep the same spacing.
+/// A change in any of those, like at a heading, an indented first line or a wider gap, starts
+/// a new paragraph.
+pub fn extract_paragraphs(doc: &Document) -> Result<Vec<String>, OutputError> {
+    // the characters of a text-showing operator share a font
+    let (chars, font_names) = chars_with_fonts(doc)?;
+    let fonts: HashMap<(u32, usize), &str> = chars
+        .iter()
+        .zip(&font_names)
+        .map(|(c, name)| ((c.page_num, c.word), name.as_str()))
+        .collect();
+
+    struct Line<'a> {
+        page: u32,
+        font: &'a str,
+        size: f64,
+        left: f64,
+        baseline: f64,
+        text: String,
+    }
+    let mut lines = Vec::new();
+    for page in chars.chunk_by(|a, b| a.page_num == b.page_num) {
+        for line in words_by_line(page) {
+            let first = line[0][0];
+            lines.push(Line {
+                page: first.page_num,
+                font: fonts[&(first.page_num, first.word)],
+                size: first.font_size,
+                left: first.x,
+                baseline: first.y,
+                text: line
+                    .iter()
+                    .map(|word| word.iter().map(|c| c.char.as_str()).collect::<String>())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            });
+        }
+    }
+
+    let mut paragraphs = Vec::new();
+    let mut last: Option<&Line> = None;
+    let mut spacing = None;
+    for line in &lines {
+        let continues = last.is_some_and(|last| {
+            let gap = (last.baseline - line.baseline).abs();
+            let tolerance = line.size * 0.2;
+            last.page == line.page
+                && last.font == line.font
+                && (last.size - line.size).abs() <= 0.01 * line.size
+                && (last.left - line.left).abs() <= tolerance
+                && gap <= line.size * 2.
+                && spacing.is_none_or(|spacing: f64| (gap - spacing).abs() <= tolerance)
+        });
+        if continues {
+            spacing = last.map(|last| (last.baseline - line.baseline).abs());
+            let paragraph: &mut String = paragraphs.last_mut().unwrap();
+            paragraph.push(' ');
+            paragraph.push_str(&line.text);
+        } else {
+            spacing = None;
+            paragraphs.push(line.text.clone());
+        }
+        last = Some(line);
+    }
+    Ok(paragraphs)
+}
+
+/// How well the widths of the fonts on page `page_num` agree with where the glyphs are drawn,
+/// from 0 to 1. Glyphs positioned on their own, e.g. by the elements of a `TJ`, should start
+/// about where the width of the glyph before them ends, so a low score means broken font
+/// metrics and text that's likely to come out wrong, and that the page may need OCR.
+///
+/// Gaps of more than a fifth of an em are taken to be spaces between words and don't count.
+/// A page without any glyphs to compare scores 1.
+pub fn text_layer_confidence(doc: &Document, page_num: u32) -> Result<f64, OutputError> {
+    let chars = positioned_chars(doc, page_num)?;
+    let (mut consistent, mut counted) = (0, 0);
+    for pair in chars.windows(2) {
+        let (c, next) = (&pair[0], &pair[1]);
+        // glyphs shown by the same string are placed by the widths so they always agree
+        if c.word == next.word
+            || c.font_size <= 0.
+            || c.rotation.abs() > 1.
+            || next.rotation.abs() > 1.
+            || (next.y - c.y).abs() > c.font_size * 0.1
+        {
+            continue;
+        }
+        let mismatch = (next.x - (c.x + c.width)) / c.font_size;
+        if mismatch > 0.2 {
+            continue;
+        }
+        counted += 1;
+        if mismatch.abs() <= 0.1 {
+            consistent += 1;
+        }
+    }
+    Ok(if counted == 0 {
+        1.
+    } else {
+        consistent as f64 / counted as f64
+    })
+}
+
+/// Collect the horizontal and vertical edges of the paths drawn on page `page_num`, like the
+/// ruling lines of tables. Their coordinates are in the same space as `positioned_chars`.
+pub fn extract_lines_geometry(
+    doc: &Document,
+    page_num: u32,
+) -> Result<Vec<LineSegment>, OutputError> {
+    let mut output = LineSegmentOutput::new();
+    output_doc_page(doc, &mut output, page_num)?;
+    Ok(output.segments)
+}
+
+// The state of a walk over the structure tree for extract_by_structure and
+// extract_structure_role
+struct StructureWalk<'a> {
+    doc: &'a Document,
+    role_map: Option<&'a Dictionary>,
+    page_numbers: HashMap<ObjectId, u32>,
+    // the characters of each marked-content sequence that hasn't been claimed by an element yet
+    marked: HashMap<(u32, i64), Vec<&'a PositionedChar>>,
+    result: Vec<(String, String)>,
+    // the characters of every element of type `role` including those of its descendants, and
+    // the indices of the ones we're inside of
+    role: Option<&'a str>,
+    role_chars: Vec<Vec<&'a PositionedChar>>,
+    open_roles: Vec<usize>,
+}
+
+impl<'a> StructureWalk<'a> {
+    fn element(&mut self, elem: &'a Dictionary, page: Option<u32>, depth: usize) {
+        let raw = maybe_get_name(self.doc, elem, b"S").unwrap_or(b"Content");
+        let mut s = raw;
+        // custom types are mapped to standard ones
+        if let Some(mapped) = self.role_map.and_then(|r| maybe_get_name(self.doc, r, s)) {
+            s = mapped;
+        }
+        let s = pdf_to_utf8(s);
+        let is_role = self
+            .role
+            .is_some_and(|role| role == s || role.as_bytes() == raw);
+        if is_role {
+            self.open_roles.push(self.role_chars.len());
+            self.role_chars.push(Vec::new());
+        }
+        self.element_content(elem, s, page, depth);
+        if is_role {
+            self.open_roles.pop();
+        }
+    }
+
+    fn element_content(
+        &mut self,
+        elem: &'a Dictionary,
+        s: String,
+        page: Option<u32>,
+        depth: usize,
+    ) {
+        let page = self.page(elem).or(page);
+        let mut chars = Vec::new();
+        if let Ok(kids) = elem.get(b"K") {
+            self.kid(kids, &s, page, &mut chars, depth);
+        }
+        self.flush(&s, &mut chars);
+        // the alternate description of a figure or formula, which has no text of its own
+        if let Some(Object::String(alt, _)) = maybe_get_obj(self.doc, elem, b"Alt") {
+            let alt = pdf_to_utf8(alt);
+            if !alt.trim().is_empty() {
+                self.result.push((s, alt.trim().to_owned()));
+            }
+        }
+    }
+
+    fn kid(
+        &mut self,
+        kid: &'a Object,
+        s: &str,
+        page: Option<u32>,
+        chars: &mut Vec<&'a PositionedChar>,
+        depth: usize,
+    ) {
+        // guard against malformed trees that refer back to themselves
+        if depth > 64 {
+            dlog!("structure tree too deep");
+            return;
+        }
+        match maybe_deref(self.doc, kid) {
+            Object::Integer(mcid) => self.marked_content(page, *mcid, chars),
+            Object::Array(kids) => {
+                for kid in kids {
+                    self.kid(kid, s, page, chars, depth + 1);
+                }
+            }
+            Object::Dictionary(dict) => match maybe_get_name(self.doc, dict, b"Type") {
+                Some(b"MCR") => {
+                    let page = self.page(dict).or(page);
+                    if let Some(mcid) = maybe_get::<i64>(self.doc, dict, b"MCID") {
+                        self.marked_content(page, mcid, chars);
+                    }
+                }
+                // references to annotations and XObjects have no text of their own
+                Some(b"OBJR") => {}
+                _ => {
+                    self.flush(s, chars);
+                    self.element(dict, page, depth + 1);
+                }
+            },
+            kid => dlog!("unexpected structure element kid {:?}", kid),
+        }
+    }
+
+    fn page(&self, dict: &Dictionary) -> Option<u32> {
+        let id = dict.get(b"Pg").and_then(Object::as_reference).ok()?;
+        self.page_numbers.get(&id).copied()
+    }
+
+    fn marked_content(
+        &mut self,
+        page: Option<u32>,
+        mcid: i64,
+        chars: &mut Vec<&'a PositionedChar>,
+    ) {
+        if let Some(marked) = page.and_then(|page| self.marked.remove(&(page, mcid))) {
+            for &i in &self.open_roles {
+                self.role_chars[i].extend(marked.iter().copied());
+            }
+            chars.extend(marked);
+        }
+    }
+
+    fn flush(&mut self, s: &str, chars: &mut Vec<&'a PositionedChar>) {
+        let text = chars_to_text(chars.drain(..));
+        let text = text.trim();
+        if !text.is_empty() {
+            self.result.push((s.to_owned(), text.to_owned()));
+        }
+    }
+}
+
+/// Extract the text of the document as (structure type, text) pairs, like `("H1", "Introduction")`,
+/// in the order of the structure tree. Custom types are mapped through the /RoleMap. Elements
+/// with an /Alt description, like figures, also give it after their text. Text that isn't part
+/// of any structure element comes last, as one "Content" pair per page.
+pub fn extract_by_structure(doc: &Document) -> Result<Vec<(String, String)>, OutputError> {
+    Ok(walk_structure(doc, None)?.0)
+}
+
+/// Extract the text of every structure element of type `role`, like "H2" or "Sect", in the order
+/// of the structure tree. The text of an element includes that of the elements inside it. The
+/// type matches either the element's own /S or the standard type the /RoleMap maps it to.
+/// Elements without any text are left out.
+pub fn extract_structure_role(doc: &Document, role: &str) -> Result<Vec<String>, OutputError> {
+    Ok(walk_structure(doc, Some(role))?.1)
+}
+
+// The (type, text) pairs of extract_by_structure and the text of the elements of type `role`
+type StructureText = (Vec<(String, String)>, Vec<String>);
+
+fn walk_structure(doc: &Document, role: Option<&str>) -> Result<StructureText, OutputError> {
+    let pages = doc.get_pages();
+    let mut chars = Vec::new();
+    for page_num in pages.keys() {
+        chars.push(positioned_chars(doc, *page_num)?);
+    }
+    let mut marked: HashMap<(u32, i64), Vec<&PositionedChar>> = HashMap::new();
+    for c in chars.iter().flatten() {
+        if let Some(mcid) = c.mcid {
+            marked.entry((c.page_num, mcid)).or_default().push(c);
+        }
+    }
+    let catalog = get_catalog(doc);
+    let root: Option<&Dictionary> = maybe_get(doc, catalog, b"StructTreeRoot");
+    let mut walk = StructureWalk {
+        doc,
+        role_map: root.and_then(|root| maybe_get(doc, root, b"RoleMap")),
+        page_numbers: pages.iter().map(|(num, id)| (*id, *num)).collect(),
+        marked,
+        result: Vec::new(),
+        role,
+        role_chars: Vec::new(),
+        open_roles: Vec::new(),
+    };
+    if let Some(kids) = root.and_then(|root| root.get(b"K").ok()) {
+        let mut direct = Vec::new();
+        walk.kid(kids, "Content", None, &mut direct, 0);
+        walk.flush("Content", &mut direct);
+    }
+    for page_chars in &chars {
+        let mut untagged: Vec<_> = page_chars
+            .iter()
+            .filter(|c| match c.mcid {
+                Some(mcid) => walk.marked.contains_key(&(c.page_num, mcid)),
+                None => true,
+            })
+            .collect();
+        walk.flush("Content", &mut untagged);
+    }
+    let role_text = walk
+        .role_chars
+        .into_iter()
+        .map(|chars| chars_to_text(chars).trim().to_owned())
+        .filter(|text| !text.is_empty())
+        .collect();
+    Ok((walk.result, role_text))
+}
+
+/// A character of the document, as yielded by `chars`
+#[derive(Debug, Clone)]
+pub struct DocChar {
+    pub page: u32,
+    pub text: String,
+    /// the box covered by the character as [llx, lly, urx, ury]
+    pub bbox: [f64; 4],
+}
+
+/// Iterate over every character of `doc` in page order. Pages are only processed once the
+/// iterator gets to them, and a page that fails yields a single error before moving on to
+/// the next page.
+pub fn chars(doc: &Document) -> impl Iterator<Item = Result<DocChar, OutputError>> + '_ {
+    doc.get_pages().into_keys().flat_map(move |page_num| {
+        let chars: Vec<_> = match positioned_chars(doc, page_num) {
+            Ok(chars) => chars
+                .into_iter()
+                .map(|c| {
+                    let (llx, lly, urx, ury) = c.bbox();
+                    Ok(DocChar {
+                        page: c.page_num,
+                        text: c.char,
+                        bbox: [llx, lly, urx, ury],
+                    })
+                })
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        chars
+    })
+}
+
+/// Extract the text of page `page_num` leaving out what looks like a watermark according to `filter`
+pub fn extract_text_without_watermarks(
+    doc: &Document,
+    page_num: u32,
+    filter: &WatermarkFilter,
+) -> Result<String, OutputError> {
+    let chars = remove_watermarks(positioned_chars(doc, page_num)?, filter);
+    Ok(chars_to_text(&chars))
+}
+
+/// Extract the words of page `page_num` grouped by line. Words come from the text-showing
+/// operators, split further at whitespace and gaps, so no re-splitting on spaces is needed.
+pub fn extract_words(doc: &Document, page_num: u32) -> Result<Vec<Vec<String>>, OutputError> {
+    let chars = positioned_chars(doc, page_num)?;
+    Ok(words_by_line(&chars)
+        .into_iter()
+        .map(|line| {
+            line.into_iter()
+                .map(|word| word.into_iter().map(|c| c.char.as_str()).collect())
+                .collect()
+        })
+        .collect())
+}
+
+/// Extract the lines of page `page_num` with their bounding boxes. Lines are split wherever the
+/// baseline changes, like in `extract_words`.
+pub fn extract_lines(doc: &Document, page_num: u32) -> Result<Vec<TextLine>, OutputError> {
+    let chars = positioned_chars(doc, page_num)?;
+    Ok(text_lines(&chars))
+}
+
+/// A step of `text_diff`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffOp {
+    /// a word on both pages, positioned on the second one
+    Equal(TextWord),
+    /// a word that's only on the first page
+    Delete(TextWord),
+    /// a word that's only on the second page
+    Insert(TextWord),
+}
+
+/// Compare the words of page `page_num` of `doc_a` and `doc_b`, e.g. to mark up the changes
+/// between two versions of a document. The words are matched up with a longest common
+/// subsequence and each one keeps its position so the changes can be highlighted.
+pub fn text_diff(
+    doc_a: &Document,
+    doc_b: &Document,
+    page_num: u32,
+) -> Result<Vec<DiffOp>, OutputError> {
+    let a = text_words(&positioned_chars(doc_a, page_num)?);
+    let b = text_words(&positioned_chars(doc_b, page_num)?);
+    // the table below is quadratic so leave out the words that are the same at either end
+    let prefix = a
+        .iter()
+        .zip(&b)
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    // lcs[i][j] is the length of the longest common subsequence of middle_a[i..] and middle_b[j..]
+    let mut lcs = vec![vec![0usize; middle_b.len() + 1]; middle_a.len() + 1];
+    for i in (0..middle_a.len()).rev() {
+        for j in (0..middle_b.len()).rev() {
+            lcs[i][j] = if middle_a[i].text == middle_b[j].text {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops: Vec<DiffOp> = b[..prefix].iter().cloned().map(DiffOp::Equal).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < middle_a.len() || j < middle_b.len() {
+        if i < middle_a.len() && j < middle_b.len() && middle_a[i].text == middle_b[j].text {
+            ops.push(DiffOp::Equal(middle_b[j].clone()));
+            i += 1;
+            j += 1;
+        } else if i < middle_a.len() && (j == middle_b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffOp::Delete(middle_a[i].clone()));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(middle_b[j].clone()));
+            j += 1;
+        }
+    }
+    ops.extend(b[b.len() - suffix..].iter().cloned().map(DiffOp::Equal));
+    Ok(ops)
+}
+
+/// Extract the text of page `page_num` keeping its columns apart, for right-to-left documents.
+/// The text is laid out on a grid of characters anchored to the right edge of the CropBox,
+/// so the rightmost column comes first on each line.
+pub fn extract_text_rtl_layout(doc: &Document, page_num: u32) -> Result<String, OutputError> {
+    let boxes = page_boxes(doc, page_num)?;
+    let chars = positioned_chars(doc, page_num)?;
+    Ok(rtl_layout(&chars, boxes.crop_box.urx))
+}
+
+/// Return the text drawn inside of `rect` on page `page_num`. `rect` is (llx, lly, urx, ury) in
+/// PDF user space, like the /Rect of a link annotation, so this gives the anchor text of a link.
+pub fn anchor_text(doc: &Document, page_num: u32, rect: [f64; 4]) -> Result<String, OutputError> {
+    let chars = positioned_chars(doc, page_num)?;
+    let text = chars_to_text(chars.iter().filter(|c| c.inside(&rect)));
+    Ok(text.trim().to_owned())
+}
+
+/// Return the text drawn inside of a selection made up of rectangles on several pages, given
+/// as (page number, (llx, lly, urx, ury)) like for `anchor_text`. The pages are taken in order
+/// whatever the order of `rects` and their text is joined with newlines.
+pub fn extract_text_in_rects(
+    doc: &Document,
+    rects: &[(u32, [f64; 4])],
+) -> Result<String, OutputError> {
+    let mut pages: Vec<u32> = rects.iter().map(|(page_num, _)| *page_num).collect();
+    pages.sort_unstable();
+    pages.dedup();
+    let mut texts = Vec::new();
+    for page_num in pages {
+        let chars = positioned_chars(doc, page_num)?;
+        let page_rects: Vec<&[f64; 4]> = rects
+            .iter()
+            .filter(|(p, _)| *p == page_num)
+            .map(|(_, rect)| rect)
+            .collect();
+        let text = chars_to_text(
+            chars
+                .iter()
+                .filter(|c| page_rects.iter().any(|rect| c.inside(rect))),
+        );
+        let text = text.trim();
+        if !text.is_empty() {
+            texts.push(text.to_owned());
+        }
+    }
+    Ok(texts.join("\n"))
+}
+
+/// Where `extract_text_with_annotations` puts the text of annotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationOrder {
+    /// after the text of the page
+    Append,
+    /// among the lines of the page by their position, top to bottom
+    Interleave,
+}
+
+// The characters drawn by the normal appearance stream of each visible annotation on the page
+fn annotation_chars(
+    doc: &Document,
+    page_num: u32,
+    page_dict: &Dictionary,
+) -> Result<Vec<Vec<PositionedChar>>, OutputError> {
+    let media_box = get_box(doc, page_dict, b"MediaBox", true).ok_or(lopdf::Error::DictKey)?;
+    let empty_resources = Dictionary::new();
+    let mut result = Vec::new();
+    for annot in page_annotations(doc, page_dict) {
+        // the Hidden and NoView flags
+        if maybe_get::<i64>(doc, annot, b"F").unwrap_or(0) & (2 | 32) != 0 {
+            continue;
+        }
+        let normal =
+            maybe_get::<&Dictionary>(doc, annot, b"AP").and_then(|ap| maybe_get_obj(doc, ap, b"N"));
+        // appearances with several states are picked from by /AS
+        let appearance = match normal {
+            Some(Object::Stream(stream)) => stream,
+            Some(Object::Dictionary(states)) => {
+                match maybe_get_name(doc, annot, b"AS").and_then(|s| maybe_get(doc, states, s)) {
+                    Some(stream) => stream,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let rect = match get::<Option<[f64; 4]>>(doc, annot, b"Rect") {
+            Some(r) => [
+                r[0].min(r[2]),
+                r[1].min(r[3]),
+                r[0].max(r[2]),
+                r[1].max(r[3]),
+            ],
+            None => continue,
+        };
+        let bbox = get::<Option<[f64; 4]>>(doc, &appearance.dict, b"BBox").unwrap_or(rect);
+        let matrix = match get::<Option<Vec<f64>>>(doc, &appearance.dict, b"Matrix").as_deref() {
+            Some(&[a, b, c, d, e, f]) => Transform2D::row_major(a, b, c, d, e, f),
+            _ => Transform2D::identity(),
+        };
+        // 12.5.5: the transformed bbox is scaled and moved onto the annotation's rect
+        let transformed = processor::transform_rect(&matrix, bbox);
+        let (width, height) = (
+            transformed[2] - transformed[0],
+            transformed[3] - transformed[1],
+        );
+        if width == 0. || height == 0. {
+            continue;
+        }
+        let fit = Transform2D::create_translation(-transformed[0], -transformed[1])
+            .post_scale((rect[2] - rect[0]) / width, (rect[3] - rect[1]) / height)
+            .post_translate(vec2(rect[0], rect[1]));
+        let resources = maybe_get::<&Dictionary>(doc, &appearance.dict, b"Resources")
+            .unwrap_or(&empty_resources);
+        let mut output = PositionedOutput::new();
+        output.begin_page(page_num, &media_box, None)?;
+        let mut p = Processor::new();
+        p.set_form_placement(matrix.post_transform(&fit), Some(rect));
+        p.process_stream(
+            doc,
+            get_contents(appearance),
+            resources,
+            &media_box,
+            &mut output,
+            page_num,
+        )?;
+        output.end_page()?;
+        result.push(output.chars);
+    }
+    Ok(result)
+}
+
+/// Extract the text of page `page_num` along with the text drawn by the appearances of its
+/// annotations, like stamps and notes, with `order` saying where the annotations go. The text
+/// is given line by line.
+pub fn extract_text_with_annotations(
+    doc: &Document,
+    page_num: u32,
+    order: AnnotationOrder,
+) -> Result<String, OutputError> {
+    let pages = doc.get_pages();
+    let object_id = pages
+        .get(&page_num)
+        .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+    let page_dict = doc.get_dictionary(*object_id)?;
+    let lines = text_lines(&positioned_chars(doc, page_num)?);
+    let mut annotations: Vec<Vec<TextLine>> = annotation_chars(doc, page_num, page_dict)?
+        .iter()
+        .map(|chars| text_lines(chars))
+        .filter(|lines| !lines.is_empty())
+        .collect();
+    if order == AnnotationOrder::Interleave {
+        annotations.sort_by(|a, b| b[0].baseline_y.total_cmp(&a[0].baseline_y));
+    }
+    let mut text: Vec<String> = Vec::new();
+    let mut annotations = annotations.into_iter().peekable();
+    for line in lines {
+        // the annotations that start above this line come before it
+        while let (AnnotationOrder::Interleave, Some(annotation)) = (order, annotations.peek()) {
+            if annotation[0].baseline_y <= line.baseline_y {
+                break;
+            }
+            text.extend(annotations.next().into_iter().flatten().map(|l| l.text));
+        }
+        text.push(line.text);
+    }
+    text.extend(annotations.flatten().map(|l| l.text));
+    Ok(text.join("\n"))
+}
+
+fn page_annotations<'a>(doc: &'a Document, page_dict: &'a Dictionary) -> Vec<&'a Dictionary> {
+    maybe_get_array(doc, page_dict, b"Annots")
+        .map(|annots| {
+            annots
+                .iter()
+                .filter_map(|a| maybe_deref(doc, a).as_dict().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Return the text under each highlight annotation on page `page_num` along with the
+/// annotation's /Rect. Highlights spanning several lines have one quadrilateral per line
+/// in /QuadPoints and we take the text from all of them.
+pub fn highlighted_text(
+    doc: &Document,
+    page_num: u32,
+) -> Result<Vec<(String, [f64; 4])>, OutputError> {
+    let pages = doc.get_pages();
+    let object_id = pages
+        .get(&page_num)
+        .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+    let page_dict = doc.get_dictionary(*object_id)?;
+    let highlights: Vec<_> = page_annotations(doc, page_dict)
+        .into_iter()
+        .filter(|a| maybe_get_name(doc, a, b"Subtype") == Some(b"Highlight"))
+        .collect();
+    if highlights.is_empty() {
+        return Ok(Vec::new());
+    }
+    let chars = positioned_chars(doc, page_num)?;
+    let mut result = Vec::new();
+    for annot in highlights {
+        let quad_points = get::<Option<Vec<f64>>>(doc, annot, b"QuadPoints").unwrap_or_default();
+        // each quadrilateral is given by 4 points, we use its bounding box
+        let quads: Vec<[f64; 4]> = quad_points
+            .chunks_exact(8)
+            .map(|q| {
+                let xs = [q[0], q[2], q[4], q[6]];
+                let ys = [q[1], q[3], q[5], q[7]];
+                [
+                    xs.iter().cloned().fold(f64::INFINITY, f64::min),
+                    ys.iter().cloned().fold(f64::INFINITY, f64::min),
+                    xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                    ys.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                ]
+            })
+            .collect();
+        let rect = match get::<Option<Vec<f64>>>(doc, annot, b"Rect").as_deref() {
+            Some(&[llx, lly, urx, ury]) => [llx, lly, urx, ury],
+            _ => quads.first().copied().unwrap_or_default(),
+        };
+        // fall back to the /Rect when there are no quads
+        let quads = if quads.is_empty() { vec![rect] } else { quads };
+        let text = chars_to_text(chars.iter().filter(|c| quads.iter().any(|q| c.inside(q))));
+        result.push((text.trim().to_owned(), rect));
+    }
+    Ok(result)
+}
+
+/// A form field widget on a page
+#[derive(Debug, Clone)]
+pub struct FormField {
+    /// the fully qualified name, the names of the field and its parents joined with '.'
+    pub name: String,
+    pub value: Option<String>,
+    /// (llx, lly, urx, ury) in PDF user space
+    pub rect: [f64; 4],
+}
+
+fn field_name(doc: &Document, widget: &Dictionary) -> String {
+    let mut names = Vec::new();
+    let mut field = Some(widget);
+    // guard against /Parent cycles
+    for _ in 0..32 {
+        let f = match field {
+            Some(f) => f,
+            None => break,
+        };
+        if let Some(Object::String(name, _)) = maybe_get_obj(doc, f, b"T") {
+            names.push(pdf_to_utf8(name));
+        }
+        field = maybe_get::<&Dictionary>(doc, f, b"Parent");
+    }
+    names.reverse();
+    names.join(".")
+}
+
+// The objects referenced from the structure tree, in tree order
+fn structure_objects(doc: &Document, kid: &Object, objects: &mut Vec<ObjectId>, depth: usize) {
+    if depth > 64 {
+        dlog!("structure tree too deep");
+        return;
+    }
+    match maybe_deref(doc, kid) {
+        Object::Array(kids) => {
+            for kid in kids {
+                structure_objects(doc, kid, objects, depth + 1);
+            }
+        }
+        Object::Dictionary(dict) => {
+            if maybe_get_name(doc, dict, b"Type") == Some(b"OBJR") {
+                if let Ok(id) = dict.get(b"Obj").and_then(Object::as_reference) {
+                    objects.push(id);
+                }
+            } else if let Ok(kids) = dict.get(b"K") {
+                structure_objects(doc, kids, objects, depth + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Sort `fields` into lines, by row when `rows` and otherwise by column. Fields whose tops (or
+// left edges) are within half a field of the first one in the line share it.
+fn geometric_order(fields: &mut Vec<FormField>, rows: bool) {
+    // the position across lines, decreasing from one line to the next, and along them
+    let across = |f: &FormField| if rows { f.rect[3] } else { -f.rect[0] };
+    let along = |f: &FormField| if rows { f.rect[0] } else { -f.rect[3] };
+    let size = |f: &FormField| {
+        if rows {
+            f.rect[3] - f.rect[1]
+        } else {
+            f.rect[2] - f.rect[0]
+        }
+    };
+    fields.sort_by(|a, b| across(b).total_cmp(&across(a)));
+    let mut sorted = Vec::new();
+    let mut line: Vec<FormField> = Vec::new();
+    for field in fields.drain(..) {
+        if let Some(first) = line.first() {
+            if across(&field) < across(first) - size(first).abs() / 2. {
+                line.sort_by(|a, b| along(a).total_cmp(&along(b)));
+                sorted.append(&mut line);
+            }
+        }
+        line.push(field);
+    }
+    line.sort_by(|a, b| along(a).total_cmp(&along(b)));
+    sorted.append(&mut line);
+    *fields = sorted;
+}
+
+/// Return the form fields on page `page_num` in the order they're meant to be filled in. This is
+/// given by the page's /Tabs: /R goes row by row, /C column by column and /S follows the
+/// structure tree. Without /Tabs we follow the structure tree when there is one, and fields that
+/// aren't part of it come after the others, row by row.
+pub fn form_fields(doc: &Document, page_num: u32) -> Result<Vec<FormField>, OutputError> {
+    let pages = doc.get_pages();
+    let object_id = pages
+        .get(&page_num)
+        .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+    let page_dict = doc.get_dictionary(*object_id)?;
+    let annots = maybe_get_array(doc, page_dict, b"Annots").map_or(&[][..], |a| &a[..]);
+    let mut fields = Vec::new();
+    for annot in annots {
+        let id = annot.as_reference().ok();
+        let widget = match maybe_deref(doc, annot).as_dict() {
+            Ok(widget) if maybe_get_name(doc, widget, b"Subtype") == Some(b"Widget") => widget,
+            _ => continue,
+        };
+        let value = match get_inherited::<&Object>(doc, widget, b"V") {
+            Some(Object::String(s, _)) => Some(pdf_to_utf8(s)),
+            Some(Object::Name(name)) => Some(String::from_utf8_lossy(name).into_owned()),
+            _ => None,
+        };
+        let rect = get::<Option<[f64; 4]>>(doc, widget, b"Rect").unwrap_or_default();
+        let field = FormField {
+            name: field_name(doc, widget),
+            value,
+            rect,
+        };
+        fields.push((id, field));
+    }
+    let tabs = maybe_get_name(doc, page_dict, b"Tabs");
+    if let Some(b"R") | Some(b"C") = tabs {
+        let mut fields = fields.into_iter().map(|(_, f)| f).collect();
+        geometric_order(&mut fields, tabs == Some(b"R"));
+        return Ok(fields);
+    }
+    let mut structure = Vec::new();
+    let root: Option<&Dictionary> = maybe_get(doc, get_catalog(doc), b"StructTreeRoot");
+    if let Some(kids) = root.and_then(|root| root.get(b"K").ok()) {
+        structure_objects(doc, kids, &mut structure, 0);
+    }
+    let position = |id: Option<ObjectId>| id.and_then(|id| structure.iter().position(|o| *o == id));
+    let (mut tagged, untagged): (Vec<_>, Vec<_>) = fields
+        .into_iter()
+        .partition(|(id, _)| position(*id).is_some());
+    tagged.sort_by_key(|(id, _)| position(*id));
+    let mut untagged = untagged.into_iter().map(|(_, f)| f).collect();
+    geometric_order(&mut untagged, true);
+    Ok(tagged.into_iter().map(|(_, f)| f).chain(untagged).collect())
+}
+
+/// The text of a filled form as it appears: the lines of each page's content together with the
+/// values of its form fields, placed at their widgets, in reading order. Pieces whose vertical
+/// centers fall within the first piece of a row are joined on one line from left to right, and
+/// pages are separated by a blank line.
+pub fn flatten_forms_text(doc: &Document) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+    for page_num in doc.get_pages().into_keys() {
+        let chars = positioned_chars(doc, page_num)?;
+        let mut pieces: Vec<(String, [f64; 4])> = text_lines(&chars)
+            .into_iter()
+            .map(|line| (line.text, line.bbox))
+            .collect();
+        for field in form_fields(doc, page_num)? {
+            match field.value {
+                Some(value) if !value.trim().is_empty() => {
+                    let r = field.rect;
+                    let rect = [
+                        r[0].min(r[2]),
+                        r[1].min(r[3]),
+                        r[0].max(r[2]),
+                        r[1].max(r[3]),
+                    ];
+                    pieces.push((value.trim().to_owned(), rect))
+                }
+                _ => {}
+            }
+        }
+        pieces.sort_by(|a, b| b.1[3].total_cmp(&a.1[3]));
+        let mut lines = Vec::new();
+        let mut row: Vec<(String, [f64; 4])> = Vec::new();
+        for piece in pieces {
+            if let Some(first) = row.first() {
+                let center = (piece.1[1] + piece.1[3]) / 2.;
+                if center < first.1[1] {
+                    lines.push(join_row(&mut row));
+                }
+            }
+            row.push(piece);
+        }
+        if !row.is_empty() {
+            lines.push(join_row(&mut row));
+        }
+        pages.push(lines.join("\n"));
+    }
+    Ok(pages.join("\n\n"))
+}
+
+// The pieces of a row of flatten_forms_text from left to right
+fn join_row(row: &mut Vec<(String, [f64; 4])>) -> String {
+    row.sort_by(|a, b| a.1[0].total_cmp(&b.1[0]));
+    let text: Vec<String> = row.drain(..).map(|(text, _)| text).collect();
+    text.join(" ")
+}
+
+/// The decoded content of page `page_num`. `/Contents` can be a single stream or an array of
+/// streams, in which case they're decoded separately and joined with whitespace. A page without
+/// `/Contents` is empty, while one whose `/Contents` refers to a missing object or to something
+/// other than streams is an error.
+pub fn page_content_bytes(doc: &Document, page_num: u32) -> Result<Vec<u8>, OutputError> {
+    let pages = doc.get_pages();
+    let object_id = pages
+        .get(&page_num)
+        .ok_or(lopdf::Error::PageNumberNotFound(page_num))?;
+    page_content(doc, doc.get_dictionary(*object_id)?)
+}
+
+/// Extract the text of the content stream `stream_id` on its own, outside of any page. The
+/// stream isn't tied to a page so the caller supplies the `resources` that it uses, e.g. its
+/// fonts, and the `media_box` that it's drawn on.
+pub fn extract_stream_text(
+    doc: &Document,
+    stream_id: ObjectId,
+    resources: &Dictionary,
+    media_box: MediaBox,
+) -> Result<String, OutputError> {
+    let stream = doc.get_object(stream_id)?.as_stream()?;
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        output.begin_page(1, &media_box, None)?;
+        Processor::new().process_stream(
+            doc,
+            get_contents(stream),
+            resources,
+            &media_box,
+            &mut output,
+            1,
+        )?;
+        output.end_page()?;
+    }
+    Ok(s)
+}
+
+// Only a page without /Contents is empty. Contents that refer to a missing object or to
+// something other than streams are an error.
+fn page_content(doc: &Document, page_dict: &Dictionary) -> Result<Vec<u8>, OutputError> {
+    let contents = match page_dict.get(b"Contents") {
+        Ok(contents) => doc.dereference(contents)?.1,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let streams: Vec<&Stream> = match contents {
+        Object::Stream(stream) => vec![stream],
+        Object::Array(contents) => contents
+            .iter()
+            .map(|c| doc.dereference(c)?.1.as_stream())
+            .collect::<Result<_, _>>()?,
+        _ => return Err(lopdf::Error::Type.into()),
+    };
+    let mut content = Vec::new();
+    for stream in streams {
+        if !content.is_empty() {
+            // streams can only be split between tokens, so this keeps the last token of one
+            // from running into the first token of the next
+            content.push(b'\n');
+        }
+        content.extend(get_contents(stream));
+    }
+    Ok(content)
+}
+
 fn output_doc_inner<'a>(
     page_num: u32,
     object_id: ObjectId,
@@ -909,25 +3366,22 @@ fn output_doc_inner<'a>(
     output: &mut dyn OutputDev,
     empty_resources: &'a Dictionary,
 ) -> Result<(), OutputError> {
-    let page_dict = doc.get_object(object_id).unwrap().as_dict().unwrap();
+    // pages can live in object streams, which lopdf unpacks when loading, so a page that
+    // still fails to resolve to a dictionary is a broken document rather than a bug
+    let page_dict = doc.get_dictionary(object_id)?;
     dlog!("page {} {:?}", page_num, page_dict);
     // XXX: Some pdfs lack a Resources directory
     let resources = get_inherited(doc, page_dict, b"Resources").unwrap_or(empty_resources);
     dlog!("resources {:?}", resources);
     // pdfium searches up the page tree for MediaBoxes as needed
-    let media_box: Vec<f64> = get_inherited(doc, page_dict, b"MediaBox").expect("MediaBox");
-    let media_box = MediaBox {
-        llx: media_box[0],
-        lly: media_box[1],
-        urx: media_box[2],
-        ury: media_box[3],
-    };
+    let media_box = get_box(doc, page_dict, b"MediaBox", true).ok_or(lopdf::Error::DictKey)?;
     let art_box =
         get::<Option<Vec<f64>>>(&doc, page_dict, b"ArtBox").map(|x| (x[0], x[1], x[2], x[3]));
+    output.page_rotation(page_rotation(doc, page_dict))?;
     output.begin_page(page_num, &media_box, art_box)?;
     p.process_stream(
         &doc,
-        doc.get_page_content(object_id).unwrap(),
+        page_content(doc, page_dict)?,
         resources,
         &media_box,
         output,
@@ -936,3 +3390,391 @@ fn output_doc_inner<'a>(
     output.end_page()?;
     Ok(())
 }
+
+/// A problem found by `validate` that gets in the way of extracting text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// the document is encrypted and hasn't been decrypted
+    Encrypted,
+    /// the page has no /MediaBox, not even an inherited one
+    MissingMediaBox { page: u32 },
+    /// the page has no /Resources, not even inherited ones
+    MissingResources { page: u32 },
+    /// the page's content streams are missing, can't be decompressed or don't parse
+    BrokenContent { page: u32, error: String },
+    /// a simple font that isn't one of the standard 14 has no /Widths
+    FontWithoutWidths { page: u32, font: String },
+    /// a font has no /ToUnicode map and no encoding that its text can be decoded with
+    FontWithoutUnicode { page: u32, font: String },
+}
+
+impl ValidationIssue {
+    /// The page that the issue is on, None for issues with the whole document
+    pub fn page(&self) -> Option<u32> {
+        match self {
+            ValidationIssue::Encrypted => None,
+            ValidationIssue::MissingMediaBox { page }
+            | ValidationIssue::MissingResources { page }
+            | ValidationIssue::BrokenContent { page, .. }
+            | ValidationIssue::FontWithoutWidths { page, .. }
+            | ValidationIssue::FontWithoutUnicode { page, .. } => Some(*page),
+        }
+    }
+}
+
+/// Look for the common reasons that extracting the text of `doc` fails or comes out wrong,
+/// without extracting anything. Only the fonts in the resources of the pages themselves are
+/// checked.
+pub fn validate(doc: &Document) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if doc.is_encrypted() {
+        issues.push(ValidationIssue::Encrypted);
+    }
+    for (page, object_id) in doc.get_pages() {
+        let Ok(page_dict) = doc.get_dictionary(object_id) else {
+            issues.push(ValidationIssue::BrokenContent {
+                page,
+                error: "the page isn't a dictionary".into(),
+            });
+            continue;
+        };
+        if get_box(doc, page_dict, b"MediaBox", true).is_none() {
+            issues.push(ValidationIssue::MissingMediaBox { page });
+        }
+        if let Some(error) = content_error(doc, page_dict) {
+            issues.push(ValidationIssue::BrokenContent { page, error });
+        }
+        let Some(resources) = get_inherited::<&Dictionary>(doc, page_dict, b"Resources") else {
+            issues.push(ValidationIssue::MissingResources { page });
+            continue;
+        };
+        let fonts = maybe_get::<&Dictionary>(doc, resources, b"Font");
+        for (name, font) in fonts.into_iter().flatten() {
+            let Ok(font) = maybe_deref(doc, font).as_dict() else {
+                continue;
+            };
+            let font_name = maybe_get_name(doc, font, b"BaseFont")
+                .map(pdf_to_utf8)
+                .unwrap_or_else(|| pdf_to_utf8(name));
+            let subtype = maybe_get_name(doc, font, b"Subtype").unwrap_or(b"Type1");
+            let has_unicode = font.has(b"ToUnicode");
+            let encoding = maybe_get_name(doc, font, b"Encoding");
+            if subtype == b"Type0" {
+                // the codes of Identity encodings are glyph ids, which say nothing about the text
+                if !has_unicode && matches!(encoding, Some(b"Identity-H" | b"Identity-V")) {
+                    issues.push(ValidationIssue::FontWithoutUnicode {
+                        page,
+                        font: font_name,
+                    });
+                }
+                continue;
+            }
+            if !font.has(b"Widths") && (subtype == b"Type3" || !font::is_core_font(&font_name)) {
+                issues.push(ValidationIssue::FontWithoutWidths {
+                    page,
+                    font: font_name.clone(),
+                });
+            }
+            if subtype == b"Type3" && !has_unicode && !font.has(b"Encoding") {
+                issues.push(ValidationIssue::FontWithoutUnicode {
+                    page,
+                    font: font_name,
+                });
+            }
+        }
+    }
+    issues
+}
+
+// Why the content of a page can't be read, if it can't
+fn content_error(doc: &Document, page_dict: &Dictionary) -> Option<String> {
+    let streams: Vec<&Object> = match page_dict.get(b"Contents").ok()? {
+        Object::Array(contents) => contents.iter().collect(),
+        contents => vec![contents],
+    };
+    for &object in &streams {
+        let resolved = match object {
+            Object::Reference(id) => doc.get_object(*id),
+            object => Ok(object),
+        };
+        let Ok(stream) = resolved.and_then(Object::as_stream) else {
+            return Some(format!("{:?} isn't a content stream", object));
+        };
+        if stream.filter().is_ok()
+            && stream.decompressed_content().is_err()
+            && decode_filters(stream).is_err()
+        {
+            return Some(format!("can't decompress {:?}", stream.dict));
+        }
+    }
+    let content = match page_content(doc, page_dict) {
+        Ok(content) => content,
+        Err(e) => return Some(e.to_string()),
+    };
+    let (content, _) = processor::split_inline_images(content);
+    match Content::decode(&content) {
+        Err(e) => Some(e.to_string()),
+        // lopdf stops at the first operation it can't parse, so not getting any operations
+        // out of a stream that has something in it means it's garbage
+        Ok(c) if c.operations.is_empty() && !content.iter().all(u8::is_ascii_whitespace) => {
+            Some("the content doesn't parse".into())
+        }
+        Ok(_) => None,
+    }
+}
+
+/// A file attached to the document through the `/EmbeddedFiles` name tree
+#[derive(Debug, Clone)]
+pub struct EmbeddedFile {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub mime: Option<String>,
+}
+
+/// Return all of the files attached to `doc` in name tree order
+pub fn embedded_files(doc: &Document) -> Vec<EmbeddedFile> {
+    let catalog = get_catalog(doc);
+    let tree = maybe_get::<&Dictionary>(doc, catalog, b"Names")
+        .and_then(|names| maybe_get::<&Dictionary>(doc, names, b"EmbeddedFiles"));
+    let tree = match tree {
+        Some(tree) => tree,
+        None => return Vec::new(),
+    };
+    let mut files = Vec::new();
+    for (key, spec) in name_tree_entries(doc, tree) {
+        let spec = match spec.as_dict() {
+            Ok(spec) => spec,
+            Err(_) => continue,
+        };
+        let ef: Option<&Dictionary> = maybe_get(doc, spec, b"EF");
+        // prefer the unicode version of the file stream the same way we prefer /UF for the name
+        let stream = ef.and_then(|ef| {
+            maybe_get::<&Stream>(doc, ef, b"UF").or_else(|| maybe_get::<&Stream>(doc, ef, b"F"))
+        });
+        let stream = match stream {
+            Some(stream) => stream,
+            None => {
+                dlog!("file spec without an embedded stream {:?}", spec);
+                continue;
+            }
+        };
+        let name = [&b"UF"[..], &b"F"[..]]
+            .iter()
+            .find_map(|k| match maybe_get_obj(doc, spec, k) {
+                Some(Object::String(s, _)) => Some(pdf_to_utf8(s)),
+                _ => None,
+            })
+            .unwrap_or_else(|| pdf_to_utf8(&key));
+        files.push(EmbeddedFile {
+            name,
+            data: get_contents(stream),
+            mime: maybe_get_name_string(doc, &stream.dict, b"Subtype"),
+        });
+    }
+    files
+}
+
+/// The code to text map that extraction would use for `font_dict`: the font's ToUnicode map
+/// with any gaps filled in from its encoding. Glyphs recovered by a `GlyphResolver` aren't
+/// included. Returns None for fonts that have nothing to decode with, like a CID font without
+/// a ToUnicode map.
+pub fn font_unicode_map(doc: &Document, font_dict: &Dictionary) -> Option<HashMap<u32, String>> {
+    font::make_font(doc, font_dict, None, None).unicode_table()
+}
+
+/// The default vertical metrics of the CID font `font_dict` as [vy w1y] in glyph space units,
+/// from its /DW2 or the default of [880 -1000]. Returns None for fonts that aren't CID fonts.
+pub fn font_vertical_default(doc: &Document, font_dict: &Dictionary) -> Option<[f64; 2]> {
+    font::make_font(doc, font_dict, None, None).vertical_default()
+}
+
+/// How a destination shows its page. Coordinates are in the default user space of the page and
+/// are `None` where the destination leaves the current value alone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DestinationView {
+    /// `/XYZ`: the point at the top left corner of the window and the zoom
+    XYZ {
+        left: Option<f64>,
+        top: Option<f64>,
+        zoom: Option<f64>,
+    },
+    /// `/Fit` and `/FitB`: the whole page
+    Fit,
+    /// `/FitH` and `/FitBH`: the top of the window, with the page fit to its width
+    FitH { top: Option<f64> },
+    /// `/FitV` and `/FitBV`: the left edge of the window, with the page fit to its height
+    FitV { left: Option<f64> },
+    /// `/FitR`: the rectangle to fit as (left, bottom, right, top)
+    FitR { rect: [f64; 4] },
+}
+
+/// An item of the document outline (the bookmarks)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineItem {
+    pub title: String,
+    /// 0 for top level items, 1 for their children and so on
+    pub level: usize,
+    /// the page number the item points at, if it points at a page of the document
+    pub page: Option<u32>,
+    pub view: Option<DestinationView>,
+}
+
+// The page number and view of `dest`, which is an explicit destination, a dictionary with a /D
+// entry or the name of one of those
+fn resolve_destination(
+    doc: &Document,
+    dest: &Object,
+    page_numbers: &HashMap<ObjectId, u32>,
+) -> Option<(u32, Option<DestinationView>)> {
+    let dest = match maybe_deref(doc, dest) {
+        Object::Name(name) | Object::String(name, _) => named_destination(doc, name)?,
+        dest => dest,
+    };
+    let dest = match dest {
+        Object::Dictionary(d) => maybe_get_obj(doc, d, b"D")?,
+        _ => dest,
+    };
+    let dest = dest.as_array().ok()?;
+    // remote destinations give the page as a number, but those point at other documents
+    let page = match dest.first()? {
+        Object::Reference(id) => *page_numbers.get(id)?,
+        _ => return None,
+    };
+    let num = |i: usize| dest.get(i).and_then(|o| maybe_as_num(maybe_deref(doc, o)));
+    let view = match dest.get(1).map(|o| maybe_deref(doc, o)) {
+        Some(Object::Name(kind)) => match &kind[..] {
+            b"XYZ" => Some(DestinationView::XYZ {
+                left: num(2),
+                top: num(3),
+                // a zoom of 0 also means unchanged
+                zoom: num(4).filter(|&zoom| zoom != 0.),
+            }),
+            b"Fit" | b"FitB" => Some(DestinationView::Fit),
+            b"FitH" | b"FitBH" => Some(DestinationView::FitH { top: num(2) }),
+            b"FitV" | b"FitBV" => Some(DestinationView::FitV { left: num(2) }),
+            b"FitR" => Some(DestinationView::FitR {
+                rect: [num(2)?, num(3)?, num(4)?, num(5)?],
+            }),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some((page, view))
+}
+
+// Look `name` up in the `/Names /Dests` name tree and then the older `/Dests` dictionary
+fn named_destination<'a>(doc: &'a Document, name: &[u8]) -> Option<&'a Object> {
+    let catalog = get_catalog(doc);
+    let tree = maybe_get::<&Dictionary>(doc, catalog, b"Names")
+        .and_then(|names| maybe_get::<&Dictionary>(doc, names, b"Dests"));
+    if let Some(tree) = tree {
+        if let Some((_, dest)) = name_tree_entries(doc, tree)
+            .into_iter()
+            .find(|(key, _)| key == name)
+        {
+            return Some(dest);
+        }
+    }
+    maybe_get::<&Dictionary>(doc, catalog, b"Dests").and_then(|old| maybe_get_obj(doc, old, name))
+}
+
+/// The document outline in order, with each item's level of nesting and where it points. An
+/// item points at its /Dest or at the destination of its /GoTo action.
+pub fn outline(doc: &Document) -> Vec<OutlineItem> {
+    let page_numbers: HashMap<ObjectId, u32> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(num, id)| (id, num))
+        .collect();
+    let mut items = Vec::new();
+    let root = maybe_get::<&Dictionary>(doc, get_catalog(doc), b"Outlines");
+    // the first item of each level we're in, along with the level
+    let mut pending: Vec<(&Object, usize)> = Vec::new();
+    // the links are looked up without dereferencing them so that we can spot cycles
+    if let Some(first) = root.and_then(|root| root.get(b"First").ok()) {
+        pending.push((first, 0));
+    }
+    // guard against /Next and /First cycles
+    let mut seen = std::collections::HashSet::new();
+    while let Some((item, level)) = pending.pop() {
+        let mut next = Some(item);
+        while let Some(item) = next {
+            if let Object::Reference(id) = item {
+                if !seen.insert(*id) {
+                    dlog!("outline cycle at {:?}", id);
+                    break;
+                }
+            }
+            let dict = match maybe_deref(doc, item).as_dict() {
+                Ok(dict) => dict,
+                Err(_) => break,
+            };
+            let title = match maybe_get_obj(doc, dict, b"Title") {
+                Some(Object::String(title, _)) => pdf_to_utf8(title),
+                _ => String::new(),
+            };
+            let dest = maybe_get_obj(doc, dict, b"Dest").or_else(|| {
+                let action: &Dictionary = maybe_get(doc, dict, b"A")?;
+                if maybe_get_name(doc, action, b"S") != Some(b"GoTo") {
+                    return None;
+                }
+                maybe_get_obj(doc, action, b"D")
+            });
+            let (page, view) = match dest.and_then(|d| resolve_destination(doc, d, &page_numbers)) {
+                Some((page, view)) => (Some(page), view),
+                None => (None, None),
+            };
+            items.push(OutlineItem {
+                title,
+                level,
+                page,
+                view,
+            });
+            next = dict.get(b"Next").ok();
+            if let Ok(first) = dict.get(b"First") {
+                // finish the children before the siblings that follow
+                if let Some(next) = next {
+                    pending.push((next, level));
+                }
+                pending.push((first, level + 1));
+                break;
+            }
+        }
+    }
+    items
+}
+
+/// Map the document's named destinations to the page numbers they point at. Names come from
+/// the `/Names /Dests` name tree and from the older `/Dests` dictionary in the catalog.
+/// Destinations that don't point at a page of the document are skipped.
+pub fn named_destinations(doc: &Document) -> HashMap<String, u32> {
+    let page_numbers: HashMap<ObjectId, u32> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(num, id)| (id, num))
+        .collect();
+    let page_of =
+        |dest: &Object| resolve_destination(doc, dest, &page_numbers).map(|(page, _)| page);
+    let catalog = get_catalog(doc);
+    let mut dests = HashMap::new();
+    if let Some(old) = maybe_get::<&Dictionary>(doc, catalog, b"Dests") {
+        for (name, dest) in old.iter() {
+            if let Some(page) = page_of(maybe_deref(doc, dest)) {
+                dests.insert(String::from_utf8_lossy(name).into_owned(), page);
+            }
+        }
+    }
+    let tree = maybe_get::<&Dictionary>(doc, catalog, b"Names")
+        .and_then(|names| maybe_get::<&Dictionary>(doc, names, b"Dests"));
+    if let Some(tree) = tree {
+        for (name, dest) in name_tree_entries(doc, tree) {
+            match page_of(dest) {
+                Some(page) => {
+                    dests.insert(pdf_to_utf8(&name), page);
+                }
+                None => dlog!("destination without a page {:?}", dest),
+            }
+        }
+    }
+    dests
+}
@@ -1,6 +1,11 @@
-use crate::utils::{get_info, get_pages, pdf_to_utf8};
+pub use crate::font::FontStyle;
+use crate::geometry::transform_bbox;
+use crate::utils::{
+    continues_arabic_word, decompose_ligatures, direction, get_info, get_pages, is_rtl,
+    maybe_get_obj, pdf_to_utf8,
+};
 use crate::{get, vec2, ColorSpace, MediaBox, OutputError, Path, PathOp, Transform, Transform2D};
-use lopdf::{Document, Object, StringFormat};
+use lopdf::{Document, Object, Stream, StringFormat};
 use std::fmt;
 use std::fs::File;
 
@@ -17,6 +22,11 @@ pub trait OutputDev {
         art_box: Option<(f64, f64, f64, f64)>,
     ) -> Result<(), OutputError>;
     fn end_page(&mut self) -> Result<(), OutputError>;
+    /// The /Rotate of the next page in clockwise degrees: 0, 90, 180 or 270.
+    /// This is called before `begin_page`.
+    fn page_rotation(&mut self, _rotate: i64) -> Result<(), OutputError> {
+        Ok(())
+    }
     fn output_character(
         &mut self,
         trm: &Transform,
@@ -28,15 +38,52 @@ pub trait OutputDev {
     fn begin_word(&mut self) -> Result<(), OutputError>;
     fn end_word(&mut self) -> Result<(), OutputError>;
     fn end_line(&mut self) -> Result<(), OutputError>;
+    /// The constant alpha (/ca) that the following characters are drawn with, from 0 to 1.
+    /// This is called before each run of characters.
+    fn fill_alpha(&mut self, _alpha: f64) -> Result<(), OutputError> {
+        Ok(())
+    }
+    /// The marked-content identifier (/MCID) that the following characters are tagged with,
+    /// from the innermost marked-content sequence that has one. This is called whenever a
+    /// sequence begins or ends.
+    fn marked_content(&mut self, _mcid: Option<i64>) -> Result<(), OutputError> {
+        Ok(())
+    }
+    /// The font that the following characters are drawn with, given by its /BaseFont. This is
+    /// called by every `Tf`.
+    fn set_font(&mut self, _base_font: &str, _style: FontStyle) -> Result<(), OutputError> {
+        Ok(())
+    }
+    /// An image drawn into the unit square transformed by `ctm`. Inline images are given as an
+    /// image XObject, with their abbreviated keys and names spelled out.
+    fn image(&mut self, _ctm: &Transform, _image: &Stream) -> Result<(), OutputError> {
+        Ok(())
+    }
+    /// A stroked path. `line_width` is the width from the graphics state already scaled by
+    /// `ctm`, so 0 is the thinnest line that can be drawn.
     fn stroke(
         &mut self,
         _ctm: &Transform,
         _colorspace: &ColorSpace,
         _color: &[f64],
+        _line_width: f64,
         _path: &Path,
     ) -> Result<(), OutputError> {
         Ok(())
     }
+    /// Like `output_character` but also gets the fill color the character is drawn with, as RGB.
+    /// The default implementation drops the color and calls `output_character`.
+    fn output_character_with_color(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+        _rgb: [f64; 3],
+    ) -> Result<(), OutputError> {
+        self.output_character(trm, width, spacing, font_size, char)
+    }
     fn fill(
         &mut self,
         _ctm: &Transform,
@@ -46,6 +93,22 @@ pub trait OutputDev {
     ) -> Result<(), OutputError> {
         Ok(())
     }
+    /// Like `fill` but for `f*`, which uses the even-odd rule to decide what's inside the path.
+    /// The default implementation calls `fill`.
+    fn fill_even_odd(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        self.fill(ctm, colorspace, color, path)
+    }
+    /// Called once after the last page, for devices that have to close what they've written,
+    /// like the array of `JSONOutput`. The default implementation does nothing.
+    fn finish(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
 }
 
 pub struct HTMLOutput<'a> {
@@ -55,6 +118,20 @@ pub struct HTMLOutput<'a> {
     buf_ctm: Transform,
     buf_font_size: f64,
     buf: String,
+    style: FontStyle,
+    buf_style: FontStyle,
+}
+
+// `text` wrapped in <b> and <i> as `style` calls for
+fn styled(text: &str, style: FontStyle) -> String {
+    let mut text = text.to_owned();
+    if style.italic {
+        text = format!("<i>{}</i>", text);
+    }
+    if style.bold {
+        text = format!("<b>{}</b>", text);
+    }
+    text
 }
 
 fn insert_nbsp(input: &str) -> String {
@@ -86,22 +163,19 @@ impl<'a> HTMLOutput<'a> {
             buf_ctm: Transform2D::identity(),
             buf: String::new(),
             buf_font_size: 0.,
+            style: FontStyle::default(),
+            buf_style: FontStyle::default(),
         }
     }
     fn flush_string(&mut self) -> Result<(), OutputError> {
         if self.buf.len() != 0 {
             let position = self.buf_ctm.post_transform(&self.flip_ctm);
-            let transformed_font_size_vec = self
-                .buf_ctm
-                .transform_vector(vec2(self.buf_font_size, self.buf_font_size));
-            // get the length of one sized of the square with the same area with a rectangle of size (x, y)
-            let transformed_font_size =
-                (transformed_font_size_vec.x * transformed_font_size_vec.y).sqrt();
+            let (transformed_font_size, _) = device_font_size(&self.buf_ctm, self.buf_font_size);
             let (x, y) = (position.m31, position.m32);
             println!("flush {} {:?}", self.buf, (x, y));
 
             write!(self.file, "<div style='position: absolute; left: {}px; top: {}px; font-size: {}px'>{}</div>\n",
-                   x, y, transformed_font_size, insert_nbsp(&self.buf))?;
+                   x, y, transformed_font_size, styled(&insert_nbsp(&self.buf), self.buf_style))?;
         }
         Ok(())
     }
@@ -109,6 +183,15 @@ impl<'a> HTMLOutput<'a> {
 
 type ArtBox = (f64, f64, f64, f64);
 
+// The height of text drawn with `trm` at `font_size` and how much its advances are scaled by in
+// device space. They differ for horizontally scaled (Tz) text, which `trm` stretches along
+// the baseline.
+fn device_font_size(trm: &Transform, font_size: f64) -> (f64, f64) {
+    let height = trm.transform_vector(vec2(0., font_size)).length();
+    let advance = trm.transform_vector(vec2(font_size, 0.)).length();
+    (height, advance)
+}
+
 impl<'a> OutputDev for HTMLOutput<'a> {
     fn begin_page(
         &mut self,
@@ -137,7 +220,7 @@ impl<'a> OutputDev for HTMLOutput<'a> {
         font_size: f64,
         char: &str,
     ) -> Result<(), OutputError> {
-        if trm.approx_eq(&self.last_ctm) {
+        if trm.approx_eq(&self.last_ctm) && self.style == self.buf_style {
             let position = trm.post_transform(&self.flip_ctm);
             let (x, y) = (position.m31, position.m32);
 
@@ -152,15 +235,13 @@ impl<'a> OutputDev for HTMLOutput<'a> {
             self.buf = char.to_owned();
             self.buf_font_size = font_size;
             self.buf_ctm = *trm;
+            self.buf_style = self.style;
         }
         let position = trm.post_transform(&self.flip_ctm);
-        let transformed_font_size_vec = trm.transform_vector(vec2(font_size, font_size));
-        // get the length of one sized of the square with the same area with a rectangle of size (x, y)
-        let transformed_font_size =
-            (transformed_font_size_vec.x * transformed_font_size_vec.y).sqrt();
+        let (transformed_font_size, _) = device_font_size(trm, font_size);
         let (x, y) = (position.m31, position.m32);
         write!(self.file, "<div style='position: absolute; color: red; left: {}px; top: {}px; font-size: {}px'>{}</div>",
-               x, y, transformed_font_size, char)?;
+               x, y, transformed_font_size, styled(char, self.style))?;
         self.last_ctm = trm.pre_transform(&Transform2D::create_translation(
             width * font_size + spacing,
             0.,
@@ -168,6 +249,10 @@ impl<'a> OutputDev for HTMLOutput<'a> {
 
         Ok(())
     }
+    fn set_font(&mut self, _base_font: &str, style: FontStyle) -> Result<(), OutputError> {
+        self.style = style;
+        Ok(())
+    }
     fn begin_word(&mut self) -> Result<(), OutputError> {
         Ok(())
     }
@@ -181,10 +266,11 @@ impl<'a> OutputDev for HTMLOutput<'a> {
 
 pub struct SVGOutput<'a> {
     file: &'a mut dyn std::io::Write,
+    rotate: i64,
 }
 impl<'a> SVGOutput<'a> {
     pub fn new(file: &mut dyn std::io::Write) -> SVGOutput {
-        SVGOutput { file }
+        SVGOutput { file, rotate: 0 }
     }
 }
 
@@ -208,20 +294,37 @@ impl<'a> OutputDev for SVGOutput<'a> {
                 r#"<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.0//EN" "http://www.w3.org/TR/2001/REC-SVG-20010904/DTD/svg10.dtd">"#
             )?;
         }
-        if let Some(art_box) = art_box {
+        let (x, y, width, height) = if let Some(art_box) = art_box {
             let width = art_box.2 - art_box.0;
             let height = art_box.3 - art_box.1;
             let y = media_box.ury - art_box.1 - height;
-            write!(self.file, "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" version=\"{}\" viewBox='{} {} {} {}'>", width, height, ver, art_box.0, y, width, height)?;
+            (art_box.0, y, width, height)
         } else {
             let width = media_box.urx - media_box.llx;
             let height = media_box.ury - media_box.lly;
-            write!(self.file, "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" version=\"{}\" viewBox='{} {} {} {}'>", width, height, ver, media_box.llx, media_box.lly, width, height)?;
-        }
-        write!(self.file, "\n")?;
+            (media_box.llx, media_box.lly, width, height)
+        };
         type Mat = Transform;
 
-        let ctm = Mat::create_scale(1., -1.).post_translate(vec2(0., media_box.ury));
+        let mut ctm = Mat::create_scale(1., -1.).post_translate(vec2(0., media_box.ury));
+        if self.rotate == 0 {
+            write!(self.file, "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" version=\"{}\" viewBox='{} {} {} {}'>", width, height, ver, x, y, width, height)?;
+        } else {
+            // turn the page clockwise so that its top left corner lands on the origin
+            let rotation = match self.rotate {
+                90 => Mat::row_major(0., 1., -1., 0., height + y, -x),
+                180 => Mat::row_major(-1., 0., 0., -1., width + x, height + y),
+                _ => Mat::row_major(0., -1., 1., 0., -y, width + x),
+            };
+            ctm = ctm.post_transform(&rotation);
+            let (width, height) = if self.rotate == 180 {
+                (width, height)
+            } else {
+                (height, width)
+            };
+            write!(self.file, "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" version=\"{}\" viewBox='0 0 {} {}'>", width, height, ver, width, height)?;
+        }
+        write!(self.file, "\n")?;
         write!(
             self.file,
             "<g transform='matrix({}, {}, {}, {}, {}, {})'>\n",
@@ -234,6 +337,10 @@ impl<'a> OutputDev for SVGOutput<'a> {
         write!(self.file, "</svg>")?;
         Ok(())
     }
+    fn page_rotation(&mut self, rotate: i64) -> Result<(), OutputError> {
+        self.rotate = rotate;
+        Ok(())
+    }
     fn output_character(
         &mut self,
         _trm: &Transform,
@@ -256,10 +363,64 @@ impl<'a> OutputDev for SVGOutput<'a> {
     fn fill(
         &mut self,
         ctm: &Transform,
-        _colorspace: &ColorSpace,
-        _color: &[f64],
+        colorspace: &ColorSpace,
+        color: &[f64],
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        self.write_fill(ctm, colorspace, color, path, false)
+    }
+    fn fill_even_odd(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
         path: &Path,
     ) -> Result<(), OutputError> {
+        self.write_fill(ctm, colorspace, color, path, true)
+    }
+    fn stroke(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        line_width: f64,
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        // a width of 0 would hide the line in SVG, while PDF draws it as thin as it can
+        let line_width = line_width.max(SVG_HAIRLINE);
+        // the path is drawn inside a group transformed by `ctm`, which scales the stroke width
+        // too, so undo the scaling the processor applied
+        let scale = ctm.determinant().abs().sqrt();
+        let line_width = if scale > 0. {
+            line_width / scale
+        } else {
+            line_width
+        };
+        let (r, g, b) = svg_rgb(colorspace, color);
+        let paint = format!(
+            "fill='none' stroke='rgb({}, {}, {})' stroke-width='{}'",
+            r, g, b, line_width
+        );
+        self.write_path(ctm, path, &paint)
+    }
+}
+
+impl SVGOutput<'_> {
+    fn write_fill(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        path: &Path,
+        even_odd: bool,
+    ) -> Result<(), OutputError> {
+        let (r, g, b) = svg_rgb(colorspace, color);
+        let fill_rule = if even_odd { " fill-rule='evenodd'" } else { "" };
+        let paint = format!("fill='rgb({}, {}, {})'{}", r, g, b, fill_rule);
+        self.write_path(ctm, path, &paint)
+    }
+
+    fn write_path(&mut self, ctm: &Transform, path: &Path, paint: &str) -> Result<(), OutputError> {
         write!(
             self.file,
             "<g transform='matrix({}, {}, {}, {}, {}, {})'>",
@@ -291,13 +452,24 @@ impl<'a> OutputDev for SVGOutput<'a> {
                 }
             }
         }
-        write!(self.file, "<path d='{}' />", d.join(" "))?;
+        write!(self.file, "<path d='{}' {} />", d.join(" "), paint)?;
         write!(self.file, "</g>")?;
         write!(self.file, "\n")?;
         Ok(())
     }
 }
 
+// The narrowest stroke SVGOutput draws, in page units
+const SVG_HAIRLINE: f64 = 0.5;
+
+// The color as SVG's 0-255 components. An empty color, e.g. from a malformed operator, comes out
+// black.
+fn svg_rgb(colorspace: &ColorSpace, color: &[f64]) -> (f64, f64, f64) {
+    let (r, g, b) = colorspace.to_rgb(color);
+    let [r, g, b] = [r, g, b].map(|c| (c.clamp(0., 1.) * 255.).round());
+    (r, g, b)
+}
+
 /*
 File doesn't implement std::fmt::Write so we have
 to do some gymnastics to accept a File or String
@@ -307,6 +479,10 @@ See https://github.com/rust-lang/rust/issues/51305
 pub trait ConvertToFmt {
     type Writer: std::fmt::Write;
     fn convert(self) -> Self::Writer;
+    /// Push anything buffered by `writer` through to its destination
+    fn flush(_writer: &mut Self::Writer) -> Result<(), fmt::Error> {
+        Ok(())
+    }
 }
 
 impl<'a> ConvertToFmt for &'a mut String {
@@ -326,11 +502,20 @@ impl<W: std::io::Write> std::fmt::Write for WriteAdapter<W> {
     }
 }
 
+impl<W: std::io::Write> WriteAdapter<W> {
+    fn flush(&mut self) -> Result<(), fmt::Error> {
+        self.f.flush().map_err(|_| fmt::Error)
+    }
+}
+
 impl<'a> ConvertToFmt for &'a mut dyn std::io::Write {
     type Writer = WriteAdapter<Self>;
     fn convert(self) -> Self::Writer {
         WriteAdapter { f: self }
     }
+    fn flush(writer: &mut Self::Writer) -> Result<(), fmt::Error> {
+        writer.flush()
+    }
 }
 
 impl<'a> ConvertToFmt for &'a mut File {
@@ -338,26 +523,330 @@ impl<'a> ConvertToFmt for &'a mut File {
     fn convert(self) -> Self::Writer {
         WriteAdapter { f: self }
     }
+    fn flush(writer: &mut Self::Writer) -> Result<(), fmt::Error> {
+        writer.flush()
+    }
+}
+
+/// Writing to a `File` directly makes a system call for every character, wrapping it in a
+/// `BufWriter` is much faster. `PlainTextOutput` flushes it at the end of every line and page.
+impl<W: std::io::Write> ConvertToFmt for std::io::BufWriter<W> {
+    type Writer = WriteAdapter<Self>;
+    fn convert(self) -> Self::Writer {
+        WriteAdapter { f: self }
+    }
+    fn flush(writer: &mut Self::Writer) -> Result<(), fmt::Error> {
+        writer.flush()
+    }
+}
+
+// A character that has been positioned on the page but not written out yet
+struct BufferedChar {
+    x: f64,
+    end: f64,
+    font_size: f64,
+    char: String,
+}
+
+// A line of a page that's held back until the page's margins are known
+struct IndentedLine {
+    // the offset in `page_text` that the line starts at
+    offset: usize,
+    left: f64,
+    right: f64,
+    // the direction of the line's first strongly directional character
+    rtl: Option<bool>,
+}
+
+/// What `PlainTextOutput` does with text whose baseline isn't horizontal, like the
+/// labels running up the side of a figure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotatedText {
+    /// write it where it's drawn, along with the rest of the text
+    Include,
+    /// leave it out
+    Skip,
+    /// write it after the rest of the text of its page
+    Separate,
 }
 
 pub struct PlainTextOutput<W: ConvertToFmt> {
     writer: W::Writer,
+    last_start: f64,
     last_end: f64,
     last_y: f64,
+    // whether the current run is written right-to-left, based on the last strongly directional character
+    rtl: bool,
     first_char: bool,
     flip_ctm: Transform,
+    group_baselines: bool,
+    join_arabic_wraps: bool,
+    last_char: Option<char>,
+    line: Vec<BufferedChar>,
+    bidi_isolates: bool,
+    // the direction of the isolate we're in the middle of, if any
+    isolate: Option<bool>,
+    decompose_ligatures: bool,
+    // cleared once the BOM has been written
+    utf8_bom: bool,
+    // the width of a tab stop and the number of spaces written for each one
+    indentation: Option<(f64, usize)>,
+    // with indentation the page is held back until we know its left margin
+    page_text: String,
+    lines: Vec<IndentedLine>,
+    // the offset in `page_text` of a line that nothing has been written to yet
+    line_start: Option<usize>,
+    rotated_text: RotatedText,
+    // the rotated text of the page when it's kept separate
+    rotated: String,
 }
 
 impl<W: ConvertToFmt> PlainTextOutput<W> {
     pub fn new(writer: W) -> PlainTextOutput<W> {
         PlainTextOutput {
             writer: writer.convert(),
+            last_start: -100000.,
             last_end: 100000.,
+            rtl: false,
             first_char: false,
             last_y: 0.,
             flip_ctm: Transform2D::identity(),
+            group_baselines: false,
+            join_arabic_wraps: false,
+            last_char: None,
+            line: Vec::new(),
+            bidi_isolates: false,
+            isolate: None,
+            decompose_ligatures: false,
+            utf8_bom: false,
+            indentation: None,
+            page_text: String::new(),
+            lines: Vec::new(),
+            line_start: Some(0),
+            rotated_text: RotatedText::Include,
+            rotated: String::new(),
+        }
+    }
+
+    /// Collect all of the characters that share a baseline into a single line and emit them
+    /// sorted by x instead of breaking the line whenever the text moves to the left. Lines that
+    /// are mostly right-to-left are emitted from right to left. This helps with PDFs that draw a line out of order, like justified Arabic text.
+    pub fn group_baselines(mut self, group_baselines: bool) -> Self {
+        self.group_baselines = group_baselines;
+        self
+    }
+
+    /// Don't break the line when an Arabic word is wrapped onto the next line without a
+    /// hyphen, as happens with text justified by stretching instead of by adding spaces.
+    /// This relies on the shaping of the letters at the end and start of the lines.
+    pub fn join_arabic_wraps(mut self, join_arabic_wraps: bool) -> Self {
+        self.join_arabic_wraps = join_arabic_wraps;
+        self
+    }
+
+    /// Wrap each run of right-to-left or left-to-right text in a directional isolate
+    /// (U+2067 or U+2066 up to U+2069) and leave the reordering to whatever displays the text.
+    /// Runs end at line breaks. This doesn't apply when grouping baselines.
+    pub fn bidi_isolates(mut self, bidi_isolates: bool) -> Self {
+        self.bidi_isolates = bidi_isolates;
+        self
+    }
+
+    /// Write ligatures like "\u{FB01}" and the Arabic lam-alef as the letters they're made of,
+    /// even when the font maps them to the ligature, so the text can be searched.
+    pub fn decompose_ligatures(mut self, decompose_ligatures: bool) -> Self {
+        self.decompose_ligatures = decompose_ligatures;
+        self
+    }
+
+    /// Start the output with a UTF-8 byte order mark. Some Windows tools, like Excel and older
+    /// versions of Notepad, need it to show non-ASCII text such as Arabic correctly.
+    pub fn utf8_bom(mut self, utf8_bom: bool) -> Self {
+        self.utf8_bom = utf8_bom;
+        self
+    }
+
+    /// Indent each line by `spaces_per_level` spaces for every `tab_width` its first character
+    /// is to the right of the leftmost line on the page, to keep the structure of code listings
+    /// and nested lists. Right-to-left lines are measured from the right end of the rightmost
+    /// right-to-left line instead. The text of each page is written once the page ends.
+    pub fn infer_indentation(mut self, tab_width: f64, spaces_per_level: usize) -> Self {
+        self.indentation = Some((tab_width, spaces_per_level));
+        self
+    }
+
+    /// Choose what to do with rotated text, which is included with the rest by default
+    pub fn rotated_text(mut self, rotated_text: RotatedText) -> Self {
+        self.rotated_text = rotated_text;
+        self
+    }
+
+    fn out(&mut self) -> &mut dyn fmt::Write {
+        if self.indentation.is_some() {
+            &mut self.page_text
+        } else {
+            &mut self.writer
+        }
+    }
+
+    fn newline(&mut self) -> Result<(), OutputError> {
+        writeln!(self.out())?;
+        self.line_start = Some(self.page_text.len());
+        Ok(())
+    }
+
+    // Keep track of the extent and direction of the line that `char`, from `x` to `end`, is
+    // about to be written to
+    fn track_line(&mut self, x: f64, end: f64, char: &str) {
+        if self.indentation.is_none() {
+            return;
+        }
+        let rtl = char.chars().find_map(direction);
+        match (self.line_start.take(), self.lines.last_mut()) {
+            (None, Some(line)) => {
+                line.left = line.left.min(x);
+                line.right = line.right.max(end);
+                line.rtl = line.rtl.or(rtl);
+            }
+            (offset, _) => self.lines.push(IndentedLine {
+                offset: offset.unwrap_or(self.page_text.len()),
+                left: x,
+                right: end,
+                rtl,
+            }),
+        }
+    }
+
+    fn write_indented_page(&mut self) -> Result<(), OutputError> {
+        use std::fmt::Write;
+        let (tab_width, spaces_per_level) = match self.indentation {
+            Some(indentation) => indentation,
+            None => return Ok(()),
+        };
+        let text = std::mem::take(&mut self.page_text);
+        let lines = std::mem::take(&mut self.lines);
+        self.line_start = Some(0);
+        let left_margin = lines
+            .iter()
+            .filter(|l| l.rtl != Some(true))
+            .map(|l| l.left)
+            .fold(f64::INFINITY, f64::min);
+        let right_margin = lines
+            .iter()
+            .filter(|l| l.rtl == Some(true))
+            .map(|l| l.right)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let mut written = 0;
+        for line in lines {
+            self.writer.write_str(&text[written..line.offset])?;
+            let indent = if line.rtl == Some(true) {
+                right_margin - line.right
+            } else {
+                line.left - left_margin
+            };
+            let levels = (indent / tab_width).round() as usize;
+            write!(self.writer, "{:1$}", "", levels * spaces_per_level)?;
+            written = line.offset;
+        }
+        self.writer.write_str(&text[written..])?;
+        Ok(())
+    }
+
+    fn close_isolate(&mut self) -> Result<(), OutputError> {
+        if self.isolate.take().is_some() {
+            write!(self.out(), "\u{2069}")?;
+        }
+        Ok(())
+    }
+
+    fn buffer_char(
+        &mut self,
+        x: f64,
+        y: f64,
+        end: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        if !self.line.is_empty() && (y - self.last_y).abs() > font_size * 0.5 {
+            self.flush_line()?;
+            self.newline()?;
+        }
+        self.line.push(BufferedChar {
+            x,
+            end,
+            font_size,
+            char: char.to_owned(),
+        });
+        self.last_y = y;
+        Ok(())
+    }
+
+    fn flush_line(&mut self) -> Result<(), OutputError> {
+        let mut line = std::mem::take(&mut self.line);
+        line.sort_by(|a, b| a.x.total_cmp(&b.x));
+        // the characters from left to right, with `None` for the spaces between words
+        let mut visual = Vec::new();
+        let mut last_end = None;
+        for (i, c) in line.iter().enumerate() {
+            if last_end.is_some_and(|last_end| c.x > last_end + c.font_size * 0.1) {
+                visual.push(None);
+            }
+            visual.push(Some(i));
+            last_end = Some(c.end);
+        }
+        let order = if is_rtl_line(&line) {
+            right_to_left_order(&line, visual)
+        } else {
+            visual
+        };
+        for item in order {
+            match item {
+                Some(i) => {
+                    let c = &line[i];
+                    self.track_line(c.x, c.end, &c.char);
+                    write!(self.out(), "{}", c.char)?;
+                }
+                None => write!(self.out(), " ")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Whether most of the strongly directional characters of `line` are right-to-left
+fn is_rtl_line(line: &[BufferedChar]) -> bool {
+    let directions = line
+        .iter()
+        .filter_map(|c| c.char.chars().find_map(direction));
+    let (rtl, ltr) = directions.fold((0, 0), |(rtl, ltr), is_rtl| {
+        if is_rtl {
+            (rtl + 1, ltr)
+        } else {
+            (rtl, ltr + 1)
+        }
+    });
+    rtl > ltr
+}
+
+// Put the characters of a right-to-left line, given from left to right with `None` for the
+// spaces between words, in reading order. Neighbouring left-to-right words and numbers are
+// read left to right as a unit, like in `rtl_layout`.
+fn right_to_left_order(line: &[BufferedChar], visual: Vec<Option<usize>>) -> Vec<Option<usize>> {
+    let ltr = |item: &Option<usize>| item.is_some_and(|i| left_to_right(&line[i].char));
+    let mut groups: Vec<Vec<Option<usize>>> = Vec::new();
+    for (n, item) in visual.iter().enumerate() {
+        let joins = match groups.last() {
+            Some(group) if ltr(&group[0]) => {
+                ltr(item) || (item.is_none() && visual.get(n + 1).is_some_and(ltr))
+            }
+            _ => false,
+        };
+        match groups.last_mut() {
+            Some(group) if joins => group.push(*item),
+            _ => groups.push(vec![*item]),
         }
     }
+    groups.into_iter().rev().flatten().collect()
 }
 
 /* There are some structural hints that PDFs can use to signal word and line endings:
@@ -370,9 +859,22 @@ impl<W: ConvertToFmt> OutputDev for PlainTextOutput<W> {
         _: Option<ArtBox>,
     ) -> Result<(), OutputError> {
         self.flip_ctm = Transform2D::row_major(1., 0., 0., -1., 0., media_box.ury - media_box.lly);
+        if self.utf8_bom {
+            use std::fmt::Write;
+            self.writer.write_char('\u{FEFF}')?;
+            self.utf8_bom = false;
+        }
         Ok(())
     }
     fn end_page(&mut self) -> Result<(), OutputError> {
+        self.close_isolate()?;
+        self.flush_line()?;
+        if !self.rotated.is_empty() {
+            let rotated = std::mem::take(&mut self.rotated);
+            write!(self.out(), "\n{}\n", rotated)?;
+        }
+        self.write_indented_page()?;
+        W::flush(&mut self.writer)?;
         Ok(())
     }
     fn output_character(
@@ -384,38 +886,98 @@ impl<W: ConvertToFmt> OutputDev for PlainTextOutput<W> {
         char: &str,
     ) -> Result<(), OutputError> {
         let position = trm.post_transform(&self.flip_ctm);
-        let transformed_font_size_vec = trm.transform_vector(vec2(font_size, font_size));
-        // get the length of one sized of the square with the same area with a rectangle of size (x, y)
-        let transformed_font_size =
-            (transformed_font_size_vec.x * transformed_font_size_vec.y).sqrt();
+        let (transformed_font_size, advance_scale) = device_font_size(trm, font_size);
         let (x, y) = (position.m31, position.m32);
-        use std::fmt::Write;
+        let decomposed;
+        let char = if self.decompose_ligatures {
+            decomposed = decompose_ligatures(char);
+            &decomposed
+        } else {
+            char
+        };
+        // the baseline is more than a degree away from horizontal
+        if self.rotated_text != RotatedText::Include
+            && trm.m12.atan2(trm.m11).abs() > 1f64.to_radians()
+        {
+            if self.rotated_text == RotatedText::Separate {
+                if self.first_char && !self.rotated.is_empty() {
+                    self.rotated.push(' ');
+                }
+                self.rotated += char;
+                self.first_char = false;
+            }
+            return Ok(());
+        }
+        let end = x + width * advance_scale;
+        if self.group_baselines {
+            return self.buffer_char(x, y, end, transformed_font_size, char);
+        }
+        let char_direction = char.chars().find_map(direction);
+        if let Some(rtl) = char_direction {
+            self.rtl = rtl;
+            // the separator before a change of direction belongs to neither run
+            if self.bidi_isolates && self.isolate.is_some_and(|isolate| isolate != rtl) {
+                self.close_isolate()?;
+            }
+        }
         //dlog!("last_end: {} x: {}, width: {}", self.last_end, x, width);
-        if self.first_char {
+        let continues_word = self.join_arabic_wraps
+            && match (self.last_char, char.chars().next()) {
+                (Some(last), Some(first)) => continues_arabic_word(last, first),
+                _ => false,
+            };
+        if self.first_char && continues_word {
+            dlog!("joining wrapped word at {:?}", char);
+        } else if self.first_char {
+            let mut new_line = false;
             if (y - self.last_y).abs() > transformed_font_size * 1.5 {
-                write!(self.writer, "\n")?;
+                self.close_isolate()?;
+                self.newline()?;
+                new_line = true;
             }
 
-            // we've moved to the left and down
-            if x < self.last_end && (y - self.last_y).abs() > transformed_font_size * 0.5 {
-                write!(self.writer, "\n")?;
+            // we've moved to the left and down, or to the right and down for right-to-left text
+            let moved_back = if self.rtl {
+                end > self.last_start
+            } else {
+                x < self.last_end
+            };
+            if moved_back && (y - self.last_y).abs() > transformed_font_size * 0.5 {
+                self.close_isolate()?;
+                self.newline()?;
+                new_line = true;
             }
 
-            if x > self.last_end + transformed_font_size * 0.1 {
+            // right-to-left text advances towards smaller x so the gap is between the
+            // end of this character and the start of the previous one
+            let gap = if self.rtl && end <= self.last_start {
+                self.last_start - end
+            } else {
+                x - self.last_end
+            };
+            // the gap to the previous line doesn't need a space at the start of this one
+            if gap > transformed_font_size * 0.1 && !new_line {
                 dlog!(
                     "width: {}, space: {}, thresh: {}",
                     width,
-                    x - self.last_end,
+                    gap,
                     transformed_font_size * 0.1
                 );
-                write!(self.writer, " ")?;
+                write!(self.out(), " ")?;
             }
         }
+        if let (true, None, Some(rtl)) = (self.bidi_isolates, self.isolate, char_direction) {
+            write!(self.out(), "{}", if rtl { '\u{2067}' } else { '\u{2066}' })?;
+            self.isolate = Some(rtl);
+        }
+        self.track_line(x, end, char);
         //let norm = unicode_normalization::UnicodeNormalization::nfkc(char);
-        write!(self.writer, "{}", char)?;
+        write!(self.out(), "{}", char)?;
         self.first_char = false;
         self.last_y = y;
-        self.last_end = x + width * transformed_font_size;
+        self.last_start = x;
+        self.last_end = end;
+        self.last_char = char.chars().last().or(self.last_char);
         Ok(())
     }
     fn begin_word(&mut self) -> Result<(), OutputError> {
@@ -427,8 +989,1291 @@ impl<W: ConvertToFmt> OutputDev for PlainTextOutput<W> {
     }
     fn end_line(&mut self) -> Result<(), OutputError> {
         //write!(self.file, "\n");
+        W::flush(&mut self.writer)?;
+        Ok(())
+    }
+}
+
+/// Forwards everything to each of `outputs` so that several kinds of output can be produced
+/// in a single pass. The first error from any of them is returned.
+pub struct TeeOutput<'a> {
+    pub outputs: Vec<Box<dyn OutputDev + 'a>>,
+}
+
+impl<'a> TeeOutput<'a> {
+    pub fn new(outputs: Vec<Box<dyn OutputDev + 'a>>) -> TeeOutput<'a> {
+        TeeOutput { outputs }
+    }
+}
+
+impl<'a> OutputDev for TeeOutput<'a> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.begin_page(page_num, media_box, art_box)?;
+        }
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.end_page()?;
+        }
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.output_character(trm, width, spacing, font_size, char)?;
+        }
+        Ok(())
+    }
+    fn output_character_with_color(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+        rgb: [f64; 3],
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.output_character_with_color(trm, width, spacing, font_size, char, rgb)?;
+        }
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.begin_word()?;
+        }
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.end_word()?;
+        }
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.end_line()?;
+        }
+        Ok(())
+    }
+    fn fill_alpha(&mut self, alpha: f64) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.fill_alpha(alpha)?;
+        }
+        Ok(())
+    }
+    fn marked_content(&mut self, mcid: Option<i64>) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.marked_content(mcid)?;
+        }
+        Ok(())
+    }
+    fn set_font(&mut self, base_font: &str, style: FontStyle) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.set_font(base_font, style)?;
+        }
+        Ok(())
+    }
+    fn image(&mut self, ctm: &Transform, image: &Stream) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.image(ctm, image)?;
+        }
+        Ok(())
+    }
+    fn page_rotation(&mut self, rotate: i64) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.page_rotation(rotate)?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.finish()?;
+        }
+        Ok(())
+    }
+    fn stroke(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        line_width: f64,
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.stroke(ctm, colorspace, color, line_width, path)?;
+        }
+        Ok(())
+    }
+    fn fill(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.fill(ctm, colorspace, color, path)?;
+        }
         Ok(())
     }
+    fn fill_even_odd(
+        &mut self,
+        ctm: &Transform,
+        colorspace: &ColorSpace,
+        color: &[f64],
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        for output in &mut self.outputs {
+            output.fill_even_odd(ctm, colorspace, color, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// A character along with where it was drawn. Coordinates are in PDF user space
+/// (y grows upwards) so they can be compared directly with annotation rectangles.
+#[derive(Debug, Clone)]
+pub struct PositionedChar {
+    pub char: String,
+    pub page_num: u32,
+    /// the origin of the glyph on the baseline
+    pub x: f64,
+    pub y: f64,
+    /// the advance of the glyph
+    pub width: f64,
+    pub font_size: f64,
+    /// characters shown by the same text-showing operator share a word number
+    pub word: usize,
+    /// the fill color as RGB, black unless the content stream sets one
+    pub color: [f64; 3],
+    /// the constant fill alpha, 1 for opaque text
+    pub alpha: f64,
+    /// the angle of the baseline in degrees, counterclockwise from the x axis
+    pub rotation: f64,
+    /// the marked-content identifier that ties the character to the structure tree
+    pub mcid: Option<i64>,
+}
+
+impl PositionedChar {
+    /// The box covered by the character as (llx, lly, urx, ury). For rotated characters it's
+    /// the bounding box of the rotated glyph.
+    pub fn bbox(&self) -> (f64, f64, f64, f64) {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let trm = Transform::row_major(cos, sin, -sin, cos, self.x, self.y);
+        let [llx, lly, urx, ury] = transform_bbox(&trm, self.width, self.font_size);
+        (llx, lly, urx, ury)
+    }
+
+    fn center(&self) -> (f64, f64) {
+        let (llx, lly, urx, ury) = self.bbox();
+        ((llx + urx) / 2., (lly + ury) / 2.)
+    }
+
+    /// Whether the center of the character is inside of `rect` given as (llx, lly, urx, ury)
+    pub fn inside(&self, rect: &[f64; 4]) -> bool {
+        let (x, y) = self.center();
+        let (llx, urx) = (rect[0].min(rect[2]), rect[0].max(rect[2]));
+        let (lly, ury) = (rect[1].min(rect[3]), rect[1].max(rect[3]));
+        x >= llx && x <= urx && y >= lly && y <= ury
+    }
+}
+
+/// Collects every character along with its position instead of writing text out
+pub struct PositionedOutput {
+    pub chars: Vec<PositionedChar>,
+    page_num: u32,
+    word: usize,
+    alpha: f64,
+    mcid: Option<i64>,
+}
+
+impl PositionedOutput {
+    pub fn new() -> PositionedOutput {
+        PositionedOutput {
+            chars: Vec::new(),
+            page_num: 0,
+            word: 0,
+            alpha: 1.,
+            mcid: None,
+        }
+    }
+}
+
+impl Default for PositionedOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputDev for PositionedOutput {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        _media_box: &MediaBox,
+        _: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        self.page_num = page_num;
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.output_character_with_color(trm, width, spacing, font_size, char, [0., 0., 0.])
+    }
+    fn output_character_with_color(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        _spacing: f64,
+        font_size: f64,
+        char: &str,
+        rgb: [f64; 3],
+    ) -> Result<(), OutputError> {
+        let (transformed_font_size, advance_scale) = device_font_size(trm, font_size);
+        self.chars.push(PositionedChar {
+            char: char.to_owned(),
+            page_num: self.page_num,
+            x: trm.m31,
+            y: trm.m32,
+            width: width * advance_scale,
+            font_size: transformed_font_size,
+            word: self.word,
+            color: rgb,
+            alpha: self.alpha,
+            rotation: trm.m12.atan2(trm.m11).to_degrees(),
+            mcid: self.mcid,
+        });
+        Ok(())
+    }
+    fn fill_alpha(&mut self, alpha: f64) -> Result<(), OutputError> {
+        self.alpha = alpha;
+        Ok(())
+    }
+    fn marked_content(&mut self, mcid: Option<i64>) -> Result<(), OutputError> {
+        self.mcid = mcid;
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.word += 1;
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// A horizontal or vertical edge of a path, from (x0, y0) to (x1, y1) in device space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub x0: f64,
+    pub y0: f64,
+    pub x1: f64,
+    pub y1: f64,
+}
+
+impl LineSegment {
+    pub fn is_horizontal(&self) -> bool {
+        (self.y1 - self.y0).abs() <= LineSegmentOutput::TOLERANCE
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        (self.x1 - self.x0).abs() <= LineSegmentOutput::TOLERANCE
+    }
+}
+
+/// Collects the straight horizontal and vertical edges of the paths that are stroked or filled,
+/// like the ruling lines of a table. Curves and diagonal lines are skipped.
+#[derive(Default)]
+pub struct LineSegmentOutput {
+    pub segments: Vec<LineSegment>,
+}
+
+impl LineSegmentOutput {
+    // how far from horizontal or vertical an edge can be, in device units
+    const TOLERANCE: f64 = 0.5;
+
+    pub fn new() -> LineSegmentOutput {
+        LineSegmentOutput::default()
+    }
+
+    fn add_path(&mut self, ctm: &Transform, path: &Path) {
+        let mut add = |from: (f64, f64), to: (f64, f64)| {
+            let p0 = ctm.transform_point(euclid::point2(from.0, from.1));
+            let p1 = ctm.transform_point(euclid::point2(to.0, to.1));
+            let segment = LineSegment {
+                x0: p0.x,
+                y0: p0.y,
+                x1: p1.x,
+                y1: p1.y,
+            };
+            if p0 != p1 && (segment.is_horizontal() || segment.is_vertical()) {
+                self.segments.push(segment);
+            }
+        };
+        let mut current = (0., 0.);
+        let mut start = (0., 0.);
+        for op in &path.ops {
+            match *op {
+                PathOp::MoveTo(x, y) => {
+                    current = (x, y);
+                    start = current;
+                }
+                PathOp::LineTo(x, y) => {
+                    add(current, (x, y));
+                    current = (x, y);
+                }
+                PathOp::CurveTo(_, _, _, _, x, y) => current = (x, y),
+                PathOp::Rect(x, y, width, height) => {
+                    let corners = [
+                        (x, y),
+                        (x + width, y),
+                        (x + width, y + height),
+                        (x, y + height),
+                    ];
+                    for i in 0..4 {
+                        add(corners[i], corners[(i + 1) % 4]);
+                    }
+                    current = (x, y);
+                    start = current;
+                }
+                PathOp::Close => {
+                    add(current, start);
+                    current = start;
+                }
+            }
+        }
+    }
+}
+
+impl OutputDev for LineSegmentOutput {
+    fn begin_page(
+        &mut self,
+        _page_num: u32,
+        _media_box: &MediaBox,
+        _: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        _trm: &Transform,
+        _width: f64,
+        _spacing: f64,
+        _font_size: f64,
+        _char: &str,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn stroke(
+        &mut self,
+        ctm: &Transform,
+        _colorspace: &ColorSpace,
+        _color: &[f64],
+        _line_width: f64,
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        self.add_path(ctm, path);
+        Ok(())
+    }
+    fn fill(
+        &mut self,
+        ctm: &Transform,
+        _colorspace: &ColorSpace,
+        _color: &[f64],
+        path: &Path,
+    ) -> Result<(), OutputError> {
+        self.add_path(ctm, path);
+        Ok(())
+    }
+}
+
+/// Join `chars` into a string, putting a space wherever there's a gap between two characters
+/// and a newline wherever the baseline changes
+pub fn chars_to_text<'a, I: IntoIterator<Item = &'a PositionedChar>>(chars: I) -> String {
+    let mut s = String::new();
+    let mut last: Option<&PositionedChar> = None;
+    for c in chars {
+        if let Some(last) = last {
+            if (c.y - last.y).abs() > c.font_size * 0.5 {
+                s.push('\n');
+            } else if (c.x - (last.x + last.width)).max(last.x - (c.x + c.width))
+                > c.font_size * 0.1
+            {
+                s.push(' ');
+            }
+        }
+        s += &c.char;
+        last = Some(c);
+    }
+    s
+}
+
+/// The thresholds `remove_watermarks` uses to decide whether a run of text is a watermark
+#[derive(Debug, Clone)]
+pub struct WatermarkFilter {
+    /// text drawn with a fill alpha at or below this is see-through
+    pub max_alpha: f64,
+    /// text whose baseline is more than this many degrees away from horizontal or vertical is rotated
+    pub min_rotation: f64,
+    /// text this many times the median font size of the page is oversized
+    pub size_ratio: f64,
+    /// how many of being see-through, rotated and oversized make a run a watermark
+    pub min_signals: usize,
+}
+
+impl Default for WatermarkFilter {
+    fn default() -> Self {
+        WatermarkFilter {
+            max_alpha: 0.6,
+            min_rotation: 10.,
+            size_ratio: 3.,
+            min_signals: 2,
+        }
+    }
+}
+
+/// Drop the runs of `chars` that look like a watermark: large, rotated, semi-transparent text.
+/// A run is the characters drawn by one text-showing operator.
+pub fn remove_watermarks(
+    chars: Vec<PositionedChar>,
+    filter: &WatermarkFilter,
+) -> Vec<PositionedChar> {
+    let mut sizes: Vec<f64> = chars
+        .iter()
+        .filter(|c| !c.char.trim().is_empty())
+        .map(|c| c.font_size)
+        .collect();
+    if sizes.is_empty() {
+        return chars;
+    }
+    sizes.sort_by(|a, b| a.total_cmp(b));
+    let median = sizes[sizes.len() / 2];
+
+    let mut watermark_words = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let word = chars[start].word;
+        let end = chars[start..]
+            .iter()
+            .position(|c| c.word != word)
+            .map_or(chars.len(), |len| start + len);
+        let run = &chars[start..end];
+        // distance from the nearest multiple of 90 degrees so that vertical text isn't flagged
+        let rotation = run[0].rotation.rem_euclid(90.);
+        let signals = [
+            run.iter().all(|c| c.alpha <= filter.max_alpha),
+            rotation.min(90. - rotation) > filter.min_rotation,
+            run.iter()
+                .all(|c| c.font_size >= median * filter.size_ratio),
+        ];
+        if signals.iter().filter(|s| **s).count() >= filter.min_signals {
+            watermark_words.push(word);
+        }
+        start = end;
+    }
+    chars
+        .into_iter()
+        .filter(|c| !watermark_words.contains(&c.word))
+        .collect()
+}
+
+/// Split `chars` into lines, wherever the baseline changes, and each line into words. A word is
+/// a run of characters from the same text-showing operator with no gap or whitespace between them.
+/// Whitespace characters themselves are dropped so there are no empty words.
+pub fn words_by_line(chars: &[PositionedChar]) -> Vec<Vec<Vec<&PositionedChar>>> {
+    let mut lines: Vec<Vec<Vec<&PositionedChar>>> = Vec::new();
+    let mut line: Vec<Vec<&PositionedChar>> = Vec::new();
+    let mut last: Option<&PositionedChar> = None;
+    let mut last_y = None;
+    for c in chars {
+        if last_y.is_some_and(|y: f64| (c.y - y).abs() > c.font_size * 0.5) {
+            lines.push(std::mem::take(&mut line));
+            last = None;
+        }
+        last_y = Some(c.y);
+        if c.char.trim().is_empty() {
+            last = None;
+            continue;
+        }
+        let same_word = last.is_some_and(|last| {
+            last.word == c.word
+                && (c.x - (last.x + last.width)).max(last.x - (c.x + c.width)) <= c.font_size * 0.1
+        });
+        match line.last_mut() {
+            Some(word) if same_word => word.push(c),
+            _ => line.push(vec![c]),
+        }
+        last = Some(c);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+/// A line of text along with where it is on the page
+#[derive(Debug, Clone)]
+pub struct TextLine {
+    /// the words of the line separated by single spaces
+    pub text: String,
+    /// the box covered by the characters of the line as [llx, lly, urx, ury]
+    pub bbox: [f64; 4],
+    /// the baseline of the first character of the line
+    pub baseline_y: f64,
+}
+
+// The box covered by `chars` as [llx, lly, urx, ury]
+fn chars_bbox<'a, I: IntoIterator<Item = &'a PositionedChar>>(chars: I) -> [f64; 4] {
+    let mut bbox = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for c in chars {
+        let (llx, lly, urx, ury) = c.bbox();
+        bbox = [
+            bbox[0].min(llx),
+            bbox[1].min(lly),
+            bbox[2].max(urx),
+            bbox[3].max(ury),
+        ];
+    }
+    bbox
+}
+
+/// Group `chars` into lines the same way as `words_by_line`
+pub fn text_lines(chars: &[PositionedChar]) -> Vec<TextLine> {
+    words_by_line(chars)
+        .into_iter()
+        .map(|line| {
+            let text = line
+                .iter()
+                .map(|word| word.iter().map(|c| c.char.as_str()).collect::<String>())
+                .collect::<Vec<_>>()
+                .join(" ");
+            TextLine {
+                text,
+                bbox: chars_bbox(line.iter().flatten().copied()),
+                baseline_y: line[0][0].y,
+            }
+        })
+        .collect()
+}
+
+/// A word along with where it is on the page
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextWord {
+    pub text: String,
+    /// the box covered by the characters of the word as [llx, lly, urx, ury]
+    pub bbox: [f64; 4],
+}
+
+/// The words of `chars` in reading order, split up the same way as `words_by_line`
+pub fn text_words(chars: &[PositionedChar]) -> Vec<TextWord> {
+    words_by_line(chars)
+        .into_iter()
+        .flatten()
+        .map(|word| TextWord {
+            text: word.iter().map(|c| c.char.as_str()).collect(),
+            bbox: chars_bbox(word.iter().copied()),
+        })
+        .collect()
+}
+
+// Whether `text` is read left to right inside right-to-left text. Numbers are, even when
+// they're written with Arabic-Indic digits.
+fn left_to_right(text: &str) -> bool {
+    !text.chars().any(|c| is_rtl(c) && !c.is_numeric()) && text.chars().any(|c| c.is_alphanumeric())
+}
+
+/// Lay `chars` out on a grid of character cells measured from `right`, so that the rightmost
+/// column of text comes first on each line. Runs of adjacent characters keep their order and
+/// are placed by their right edge, with the gaps between them filled with spaces. Neighbouring
+/// runs of numbers or left-to-right text, like the groups of a phone number, are read left to
+/// right as a unit.
+pub fn rtl_layout(chars: &[PositionedChar], right: f64) -> String {
+    let glyphs: Vec<&PositionedChar> = chars.iter().filter(|c| !c.char.trim().is_empty()).collect();
+    if glyphs.is_empty() {
+        return String::new();
+    }
+    // the size of a grid cell is the average glyph advance
+    let cell = glyphs.iter().map(|c| c.width).sum::<f64>() / glyphs.len() as f64;
+    let cell = if cell > 0. {
+        cell
+    } else {
+        glyphs[0].font_size / 2.
+    };
+
+    // split the characters into runs, each of which is placed on the grid as a unit
+    let mut runs: Vec<(f64, f64, f64, String)> = Vec::new();
+    let mut last: Option<&PositionedChar> = None;
+    for c in chars {
+        if c.char.trim().is_empty() {
+            last = None;
+            continue;
+        }
+        let same_run = last.is_some_and(|last| {
+            last.word == c.word
+                && (c.y - last.y).abs() <= c.font_size * 0.5
+                && (c.x - (last.x + last.width)).max(last.x - (c.x + c.width)) <= c.font_size * 0.1
+        });
+        match runs.last_mut() {
+            Some((_, llx, urx, text)) if same_run => {
+                *llx = llx.min(c.x);
+                *urx = urx.max(c.x + c.width);
+                *text += &c.char;
+            }
+            _ => runs.push((c.y, c.x, c.x + c.width, c.char.clone())),
+        }
+        last = Some(c);
+    }
+
+    // group the runs into lines from the top of the page down
+    runs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut lines: Vec<Vec<(f64, f64, String)>> = Vec::new();
+    let mut line_y = f64::NAN;
+    for (y, llx, urx, text) in runs {
+        if lines.is_empty() || (y - line_y).abs() > cell {
+            lines.push(Vec::new());
+            line_y = y;
+        }
+        lines.last_mut().unwrap().push((llx, urx, text));
+    }
+
+    let mut s = String::new();
+    for (i, mut line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            s.push('\n');
+        }
+        line.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        // join left-to-right runs that are a word space or so apart, keeping runs that are
+        // further apart in their own columns
+        let mut units: Vec<(f64, f64, String)> = Vec::new();
+        for (llx, urx, text) in line {
+            match units.last_mut() {
+                Some(unit)
+                    if unit.0 - urx <= cell * 2.
+                        && left_to_right(&unit.2)
+                        && left_to_right(&text) =>
+                {
+                    unit.0 = llx;
+                    unit.2 = format!("{} {}", text, unit.2);
+                }
+                _ => units.push((llx, urx, text)),
+            }
+        }
+        let mut column = 0;
+        for (_, urx, text) in units {
+            let start = ((right - urx) / cell).round().max(0.) as usize;
+            if start > column {
+                s.extend(std::iter::repeat_n(' ', start - column));
+                column = start;
+            } else if column > 0 {
+                // keep runs that would overlap apart
+                s.push(' ');
+                column += 1;
+            }
+            column += text.chars().count();
+            s += &text;
+        }
+    }
+    s
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => result += "&amp;",
+            '<' => result += "&lt;",
+            '>' => result += "&gt;",
+            '"' => result += "&quot;",
+            '\'' => result += "&apos;",
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Keeps floating point noise out of the coordinate attributes
+fn round_coord(v: f64) -> f64 {
+    (v * 1000.).round() / 1000.
+}
+
+/// A run of characters making up one ALTO `<String>`
+struct AltoString {
+    content: String,
+    llx: f64,
+    lly: f64,
+    urx: f64,
+    ury: f64,
+}
+
+impl AltoString {
+    fn new(word: &[&PositionedChar]) -> AltoString {
+        let mut s = AltoString {
+            content: String::new(),
+            llx: f64::INFINITY,
+            lly: f64::INFINITY,
+            urx: f64::NEG_INFINITY,
+            ury: f64::NEG_INFINITY,
+        };
+        for c in word {
+            let (llx, lly, urx, ury) = c.bbox();
+            s.content += &c.char;
+            s.llx = s.llx.min(llx);
+            s.lly = s.lly.min(lly);
+            s.urx = s.urx.max(urx);
+            s.ury = s.ury.max(ury);
+        }
+        s
+    }
+}
+
+// ALTO has no unit for points, so positions are written in 1/1200 of an inch
+fn alto_units(points: f64) -> f64 {
+    round_coord(points * 1200. / 72.)
+}
+
+/// Writes the text of each page as ALTO XML, with a `<TextLine>` per line
+/// and a `<String>` per word. Positions are measured from the top left of the page in
+/// 1/1200 of an inch (the `inch1200` measurement unit), so 1pt is 16.667 units.
+///
+/// **The document is only closed by `finish`, or when the output is dropped.** Call `finish`
+/// after the last page to find out whether writing the closing tags failed; dropping the
+/// output without calling it closes the document and ignores any error.
+pub struct AltoOutput<'a> {
+    file: &'a mut dyn std::io::Write,
+    chars: PositionedOutput,
+    media_box: MediaBox,
+    started: bool,
+    finished: bool,
+    line_id: usize,
+    string_id: usize,
+}
+
+impl<'a> AltoOutput<'a> {
+    pub fn new(file: &mut dyn std::io::Write) -> AltoOutput<'_> {
+        AltoOutput {
+            file,
+            chars: PositionedOutput::new(),
+            media_box: MediaBox {
+                llx: 0.,
+                lly: 0.,
+                urx: 0.,
+                ury: 0.,
+            },
+            started: false,
+            finished: false,
+            line_id: 0,
+            string_id: 0,
+        }
+    }
+
+    fn write_header(&mut self) -> Result<(), OutputError> {
+        self.started = true;
+        writeln!(self.file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            self.file,
+            "<alto xmlns=\"http://www.loc.gov/standards/alto/ns-v4#\">"
+        )?;
+        writeln!(
+            self.file,
+            "<Description><MeasurementUnit>inch1200</MeasurementUnit></Description>"
+        )?;
+        writeln!(self.file, "<Layout>")?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, strings: &[AltoString]) -> Result<(), OutputError> {
+        if strings.is_empty() {
+            return Ok(());
+        }
+        let llx = strings.iter().map(|s| s.llx).fold(f64::INFINITY, f64::min);
+        let lly = strings.iter().map(|s| s.lly).fold(f64::INFINITY, f64::min);
+        let urx = strings
+            .iter()
+            .map(|s| s.urx)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let ury = strings
+            .iter()
+            .map(|s| s.ury)
+            .fold(f64::NEG_INFINITY, f64::max);
+        self.line_id += 1;
+        writeln!(
+            self.file,
+            "<TextLine ID=\"line{}\" HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\">",
+            self.line_id,
+            alto_units(llx - self.media_box.llx),
+            alto_units(self.media_box.ury - ury),
+            alto_units(urx - llx),
+            alto_units(ury - lly)
+        )?;
+        for (i, s) in strings.iter().enumerate() {
+            if i > 0 {
+                writeln!(self.file, "<SP/>")?;
+            }
+            self.string_id += 1;
+            writeln!(
+                self.file,
+                "<String ID=\"string{}\" CONTENT=\"{}\" HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"/>",
+                self.string_id,
+                escape_xml(&s.content),
+                alto_units(s.llx - self.media_box.llx),
+                alto_units(self.media_box.ury - s.ury),
+                alto_units(s.urx - s.llx),
+                alto_units(s.ury - s.lly)
+            )?;
+        }
+        writeln!(self.file, "</TextLine>")?;
+        Ok(())
+    }
+}
+
+impl<'a> OutputDev for AltoOutput<'a> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        if !self.started {
+            self.write_header()?;
+        }
+        self.media_box = *media_box;
+        writeln!(
+            self.file,
+            "<Page ID=\"page{}\" PHYSICAL_IMG_NR=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\">",
+            page_num,
+            page_num,
+            alto_units(media_box.urx - media_box.llx),
+            alto_units(media_box.ury - media_box.lly)
+        )?;
+        writeln!(
+            self.file,
+            "<PrintSpace>\n<TextBlock ID=\"block{}\">",
+            page_num
+        )?;
+        self.chars.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        let chars = std::mem::take(&mut self.chars.chars);
+        for line in words_by_line(&chars) {
+            let strings: Vec<AltoString> = line.iter().map(|word| AltoString::new(word)).collect();
+            self.write_line(&strings)?;
+        }
+        writeln!(self.file, "</TextBlock>\n</PrintSpace>\n</Page>")?;
+        self.chars.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character(trm, width, spacing, font_size, char)
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.chars.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.chars.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.chars.end_line()
+    }
+    fn fill_alpha(&mut self, alpha: f64) -> Result<(), OutputError> {
+        self.chars.fill_alpha(alpha)
+    }
+    /// Close the `<Layout>` and `<alto>` elements. Calling it again does nothing.
+    fn finish(&mut self) -> Result<(), OutputError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if !self.started {
+            self.write_header()?;
+        }
+        writeln!(self.file, "</Layout>\n</alto>")?;
+        Ok(())
+    }
+}
+
+impl Drop for AltoOutput<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::warn!("couldn't close the ALTO document: {}", e);
+        }
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            c if (c as u32) < 0x20 => result += &format!("\\u{:04x}", c as u32),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+// A number as JSON, which has no NaN or infinity so those are written as null
+fn json_number(n: f64) -> String {
+    if n.is_finite() {
+        n.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// Writes every character as an object in a JSON array, with its page, position, size and fill color.
+/// Positions and sizes that aren't finite, e.g. from a degenerate matrix, are written as null.
+///
+/// The array is closed by `finish`, or when the output is dropped. Call `finish` after the last
+/// page to find out whether writing the end of the array failed.
+pub struct JSONOutput<'a> {
+    file: &'a mut dyn std::io::Write,
+    chars: PositionedOutput,
+    started: bool,
+    finished: bool,
+}
+
+impl<'a> JSONOutput<'a> {
+    pub fn new(file: &mut dyn std::io::Write) -> JSONOutput<'_> {
+        JSONOutput {
+            file,
+            chars: PositionedOutput::new(),
+            started: false,
+            finished: false,
+        }
+    }
+}
+
+impl<'a> OutputDev for JSONOutput<'a> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        self.chars.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        for c in std::mem::take(&mut self.chars.chars) {
+            write!(self.file, "{}", if self.started { ",\n" } else { "[" })?;
+            self.started = true;
+            write!(
+                self.file,
+                "{{\"page\": {}, \"char\": \"{}\", \"x\": {}, \"y\": {}, \"width\": {}, \"font_size\": {}, \"color\": [{}, {}, {}]}}",
+                c.page_num,
+                escape_json(&c.char),
+                json_number(c.x),
+                json_number(c.y),
+                json_number(c.width),
+                json_number(c.font_size),
+                json_number(c.color[0]),
+                json_number(c.color[1]),
+                json_number(c.color[2])
+            )?;
+        }
+        self.chars.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character(trm, width, spacing, font_size, char)
+    }
+    fn output_character_with_color(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+        rgb: [f64; 3],
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character_with_color(trm, width, spacing, font_size, char, rgb)
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.chars.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.chars.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.chars.end_line()
+    }
+    fn fill_alpha(&mut self, alpha: f64) -> Result<(), OutputError> {
+        self.chars.fill_alpha(alpha)
+    }
+    /// Close the array. Calling it again does nothing.
+    fn finish(&mut self) -> Result<(), OutputError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if !self.started {
+            write!(self.file, "[")?;
+        }
+        writeln!(self.file, "]")?;
+        Ok(())
+    }
+}
+
+impl Drop for JSONOutput<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::warn!("couldn't close the JSON array: {}", e);
+        }
+    }
+}
+
+fn escape_markdown(line: &str) -> String {
+    let mut result = String::new();
+    // these only mean something at the start of a line
+    if line.starts_with(['#', '>', '-', '+']) {
+        result.push('\\');
+    }
+    for c in line.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Writes the text as Markdown, with a blank line between paragraphs. Optionally the document
+/// starts with YAML front matter and each page with a `<!-- page N -->` comment so that
+/// passages can be traced back to the PDF.
+pub struct MarkdownOutput<'a> {
+    file: &'a mut dyn std::io::Write,
+    chars: PositionedOutput,
+    front_matter: Option<String>,
+    page_markers: bool,
+}
+
+impl<'a> MarkdownOutput<'a> {
+    pub fn new(file: &mut dyn std::io::Write) -> MarkdownOutput<'_> {
+        MarkdownOutput {
+            file,
+            chars: PositionedOutput::new(),
+            front_matter: None,
+            page_markers: false,
+        }
+    }
+
+    /// Start the output with YAML front matter holding the title and author from the
+    /// document information dictionary, when they're there, and the page count of `doc`
+    pub fn front_matter(mut self, doc: &Document) -> Self {
+        let mut front_matter = String::from("---\n");
+        for (key, name) in [(&b"Title"[..], "title"), (&b"Author"[..], "author")] {
+            let value = get_info(doc).and_then(|info| maybe_get_obj(doc, info, key));
+            if let Some(Object::String(value, _)) = value {
+                let value = escape_json(&pdf_to_utf8(value));
+                front_matter += &format!("{}: \"{}\"\n", name, value);
+            }
+        }
+        front_matter += &format!("pages: {}\n---\n\n", doc.get_pages().len());
+        self.front_matter = Some(front_matter);
+        self
+    }
+
+    /// Start each page with an HTML comment giving its page number
+    pub fn page_markers(mut self, page_markers: bool) -> Self {
+        self.page_markers = page_markers;
+        self
+    }
+}
+
+impl<'a> OutputDev for MarkdownOutput<'a> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        if let Some(front_matter) = self.front_matter.take() {
+            write!(self.file, "{}", front_matter)?;
+        }
+        if self.page_markers {
+            writeln!(self.file, "<!-- page {} -->\n", page_num)?;
+        }
+        self.chars.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        let chars = std::mem::take(&mut self.chars.chars);
+        let mut last: Option<TextLine> = None;
+        for line in text_lines(&chars) {
+            if let Some(last) = &last {
+                // a gap of more than a line and a half starts a new paragraph
+                let height = last.bbox[3] - last.bbox[1];
+                if (last.baseline_y - line.baseline_y).abs() > height * 1.5 {
+                    writeln!(self.file)?;
+                }
+            }
+            writeln!(self.file, "{}", escape_markdown(&line.text))?;
+            last = Some(line);
+        }
+        if last.is_some() {
+            writeln!(self.file)?;
+        }
+        self.chars.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character(trm, width, spacing, font_size, char)
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.chars.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.chars.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.chars.end_line()
+    }
+}
+
+/// A dump of the text in reading order for debugging layout, where each line, or each word when
+/// built with `words`, is numbered in the order that it's read and given with its bounding box:
+///
+/// `12 [72.0 700.2 310.5 712.0] the text of the line`
+///
+/// The numbers keep counting up across pages.
+pub struct ReadingOrderOutput<'a> {
+    file: &'a mut dyn std::io::Write,
+    chars: PositionedOutput,
+    words: bool,
+    index: usize,
+}
+
+impl<'a> ReadingOrderOutput<'a> {
+    pub fn new(file: &mut dyn std::io::Write, words: bool) -> ReadingOrderOutput<'_> {
+        ReadingOrderOutput {
+            file,
+            chars: PositionedOutput::new(),
+            words,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> OutputDev for ReadingOrderOutput<'a> {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        art_box: Option<ArtBox>,
+    ) -> Result<(), OutputError> {
+        writeln!(self.file, "page {}", page_num)?;
+        self.chars.begin_page(page_num, media_box, art_box)
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        let chars = std::mem::take(&mut self.chars.chars);
+        let items: Vec<(String, [f64; 4])> = if self.words {
+            text_words(&chars)
+                .into_iter()
+                .map(|w| (w.text, w.bbox))
+                .collect()
+        } else {
+            text_lines(&chars)
+                .into_iter()
+                .map(|l| (l.text, l.bbox))
+                .collect()
+        };
+        for (text, bbox) in items {
+            writeln!(
+                self.file,
+                "{} [{:.1} {:.1} {:.1} {:.1}] {}",
+                self.index, bbox[0], bbox[1], bbox[2], bbox[3], text
+            )?;
+            self.index += 1;
+        }
+        self.chars.end_page()
+    }
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        self.chars
+            .output_character(trm, width, spacing, font_size, char)
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        self.chars.begin_word()
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        self.chars.end_word()
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        self.chars.end_line()
+    }
 }
 
 pub fn print_metadata(doc: &Document) {
@@ -1,26 +1,175 @@
-use crate::font::make_font;
+use crate::font::{
+    font_style, make_font, type3_char_proc, CharCode, FontSubstitutes, GlyphResolver, PdfFont,
+};
 use crate::output::OutputDev;
 use crate::{
-    apply_state, as_num, get, get_contents, make_colorspace, maybe_get_obj, pdf_to_utf8, show_text,
-    ColorSpace, GraphicsState, MediaBox, OutputError, Path, PathOp, TextState, Transform2D,
+    apply_state, as_num, get, get_contents, make_colorspace, maybe_get, maybe_get_name,
+    maybe_get_obj, pdf_to_utf8, show_text, to_rgb, ActualText, ColorSpace, GlyphAdvance,
+    GraphicsState, MediaBox, OutputError, Path, PathOp, ShownGlyphs, TextState, Transform,
+    Transform2D,
 };
-use lopdf::content::Content;
-use lopdf::{Dictionary, Document, Object, Stream};
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::rc::Rc;
 
 macro_rules! dlog {
     ($($e:expr),*) => { {$(let _ = $e;)*} }
     //($($t:tt)*) => { println!($($t)*) }
 }
 
+/// How many `q` operators can be nested before we give up on a content stream
+pub const DEFAULT_MAX_GS_DEPTH: usize = 4096;
+
 pub struct Processor<'a> {
     _none: PhantomData<&'a ()>,
+    glyph_resolver: Option<&'a dyn GlyphResolver>,
+    /// the form XObjects that are currently being drawn, so that a form that draws itself is skipped
+    forms: Vec<ObjectId>,
+    /// the resources of the streams that the current form is drawn from, innermost last
+    parent_resources: Vec<&'a Dictionary>,
+    /// the CTM that the form being drawn starts with
+    form_ctm: Transform,
+    /// the intersection of the /BBox of the forms being drawn, in device space
+    form_clip: Option<[f64; 4]>,
+    /// the marked-content identifier of the sequence that the form being drawn is in
+    form_mcid: Option<i64>,
+    max_gs_depth: usize,
+    unknown_operator: Option<&'a mut dyn FnMut(&Operation)>,
+    glyph_advance: GlyphAdvance,
+    /// a Type3 glyph procedure is being run
+    in_char_proc: bool,
+    /// the decoded glyph procedures of Type3 fonts by font and code, `None` when there's none or
+    /// it can't be decoded
+    char_procs: HashMap<(*const Dictionary, CharCode), Option<Rc<DecodedContent>>>,
+    font_substitutes: Option<&'a FontSubstitutes>,
+}
+
+/// The state of a content stream that is part way through being run
+pub(crate) struct StreamState<'a> {
+    font_table: HashMap<Vec<u8>, Rc<dyn PdfFont + 'a>>,
+    gs: GraphicsState<'a>,
+    gs_stack: Vec<GraphicsState<'a>>,
+    /// the indices of the BMC and BDC operations of the open marked-content sequences
+    mc_stack: Vec<usize>,
+    actual_text: Option<ActualText>,
+    glyphs: ShownGlyphs,
+    tlm: Transform,
+    path: Path,
+    /// the index of the next operation to run
+    next: usize,
+}
+
+impl StreamState<'_> {
+    /// The index of the next operation to run
+    pub(crate) fn next(&self) -> usize {
+        self.next
+    }
+}
+
+/// A content stream decoded into operations, with its inline images taken out
+pub(crate) struct DecodedContent {
+    operations: Vec<Operation>,
+    inline_images: Vec<Stream>,
+}
+
+impl DecodedContent {
+    pub(crate) fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub(crate) fn decode(content: Vec<u8>) -> Result<DecodedContent, OutputError> {
+        // lopdf can't parse inline images so we take them out first
+        let (content, inline_images) = split_inline_images(content);
+        Ok(DecodedContent {
+            operations: Content::decode(&content)?.operations,
+            inline_images,
+        })
+    }
 }
 
 impl<'a> Processor<'a> {
     pub fn new() -> Processor<'a> {
-        Processor { _none: PhantomData }
+        Processor {
+            _none: PhantomData,
+            glyph_resolver: None,
+            forms: Vec::new(),
+            parent_resources: Vec::new(),
+            form_ctm: Transform2D::identity(),
+            form_clip: None,
+            form_mcid: None,
+            max_gs_depth: DEFAULT_MAX_GS_DEPTH,
+            unknown_operator: None,
+            glyph_advance: GlyphAdvance::FontWidths,
+            in_char_proc: false,
+            char_procs: HashMap::new(),
+            font_substitutes: None,
+        }
+    }
+
+    pub fn with_glyph_resolver(resolver: &'a dyn GlyphResolver) -> Processor<'a> {
+        Processor {
+            _none: PhantomData,
+            glyph_resolver: Some(resolver),
+            forms: Vec::new(),
+            parent_resources: Vec::new(),
+            form_ctm: Transform2D::identity(),
+            form_clip: None,
+            form_mcid: None,
+            max_gs_depth: DEFAULT_MAX_GS_DEPTH,
+            unknown_operator: None,
+            glyph_advance: GlyphAdvance::FontWidths,
+            in_char_proc: false,
+            char_procs: HashMap::new(),
+            font_substitutes: None,
+        }
+    }
+
+    /// Call `callback` with every operator that the processor doesn't handle
+    pub fn set_unknown_operator(&mut self, callback: &'a mut dyn FnMut(&Operation)) {
+        self.unknown_operator = Some(callback);
+    }
+
+    /// The resources that define the colorspace `name`. Forms sometimes use colorspaces that
+    /// are only defined in the resources of the page (or form) drawing them, so we fall back to those.
+    fn colorspace_resources(
+        &self,
+        doc: &'a Document,
+        name: &[u8],
+        resources: &'a Dictionary,
+    ) -> &'a Dictionary {
+        std::iter::once(resources)
+            .chain(self.parent_resources.iter().rev().copied())
+            .find(|r| {
+                maybe_get_obj(doc, r, b"ColorSpace")
+                    .and_then(|cs| cs.as_dict().ok())
+                    .is_some_and(|cs| cs.has(name))
+            })
+            .unwrap_or(resources)
+    }
+
+    /// Fail content streams that nest `q` deeper than `max_gs_depth` instead of saving
+    /// graphics states until we run out of memory
+    pub fn set_max_gs_depth(&mut self, max_gs_depth: usize) {
+        self.max_gs_depth = max_gs_depth;
+    }
+
+    /// The metrics to use for fonts without widths or an embedded font program
+    pub fn set_font_substitutes(&mut self, substitutes: &'a FontSubstitutes) {
+        self.font_substitutes = Some(substitutes);
+    }
+
+    /// Where the advances of glyphs come from, the font's widths by default
+    pub fn set_glyph_advance(&mut self, glyph_advance: GlyphAdvance) {
+        self.glyph_advance = glyph_advance;
+    }
+
+    /// Draw the following streams like a form XObject placed with `ctm` and clipped to `clip`,
+    /// e.g. for the appearance streams of annotations
+    pub fn set_form_placement(&mut self, ctm: Transform, clip: Option<[f64; 4]>) {
+        self.form_ctm = ctm;
+        self.form_clip = clip;
     }
 
     pub fn process_stream(
@@ -32,46 +181,132 @@ impl<'a> Processor<'a> {
         output: &mut dyn OutputDev,
         page_num: u32,
     ) -> Result<(), OutputError> {
-        let content = Content::decode(&content).unwrap();
-        let mut font_table = HashMap::new();
-        let mut gs: GraphicsState = GraphicsState {
-            ts: TextState {
-                font: None,
-                font_size: std::f64::NAN,
-                character_spacing: 0.,
-                word_spacing: 0.,
-                horizontal_scaling: 1.0,
-                leading: 0.,
-                rise: 0.,
-                tm: Transform2D::identity(),
+        let content = DecodedContent::decode(content)?;
+        self.process_decoded(doc, &content, resources, media_box, output, page_num)
+    }
+
+    /// The decoded glyph procedure that draws `code` in the Type3 font `font`, decoded once per
+    /// processor
+    fn char_proc(
+        &mut self,
+        doc: &'a Document,
+        font: &'a Dictionary,
+        code: CharCode,
+    ) -> Option<Rc<DecodedContent>> {
+        self.char_procs
+            .entry((font as *const Dictionary, code))
+            .or_insert_with(|| {
+                let char_proc = type3_char_proc(doc, font, code)?;
+                match DecodedContent::decode(get_contents(char_proc)) {
+                    Ok(content) => Some(Rc::new(content)),
+                    Err(e) => {
+                        log::warn!("ignoring the Type3 glyph procedure for {}: {}", code, e);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    fn process_decoded(
+        &mut self,
+        doc: &'a Document,
+        content: &DecodedContent,
+        resources: &'a Dictionary,
+        media_box: &MediaBox,
+        output: &mut dyn OutputDev,
+        page_num: u32,
+    ) -> Result<(), OutputError> {
+        let mut state = self.new_state();
+        let operations = content.operations.len();
+        self.run(
+            doc, content, &mut state, operations, resources, media_box, output, page_num,
+        )
+    }
+
+    /// The state that a stream drawn by this processor starts out with
+    pub(crate) fn new_state(&self) -> StreamState<'a> {
+        StreamState {
+            font_table: HashMap::new(),
+            gs: GraphicsState {
+                ts: TextState {
+                    font: None,
+                    font_size: std::f64::NAN,
+                    character_spacing: 0.,
+                    word_spacing: 0.,
+                    horizontal_scaling: 1.0,
+                    leading: 0.,
+                    rise: 0.,
+                    tm: Transform2D::identity(),
+                    type3_font: None,
+                },
+                fill_color: Vec::new(),
+                fill_colorspace: ColorSpace::DeviceGray,
+                stroke_color: Vec::new(),
+                stroke_colorspace: ColorSpace::DeviceGray,
+                line_width: 1.,
+                fill_alpha: 1.,
+                ctm: self.form_ctm,
+                smask: None,
+                form_clip: self.form_clip,
             },
-            fill_color: Vec::new(),
-            fill_colorspace: ColorSpace::DeviceGray,
-            stroke_color: Vec::new(),
-            stroke_colorspace: ColorSpace::DeviceGray,
-            line_width: 1.,
-            ctm: Transform2D::identity(),
-            smask: None,
-        };
-        //let mut ts = &mut gs.ts;
-        let mut gs_stack = Vec::new();
-        let mut mc_stack = Vec::new();
-        // XXX: replace tlm with a point for text start
-        let mut tlm = Transform2D::identity();
-        let mut path = Path::new();
+            gs_stack: Vec::new(),
+            mc_stack: Vec::new(),
+            actual_text: None,
+            glyphs: ShownGlyphs {
+                positioning: self.glyph_advance == GlyphAdvance::Positioning,
+                adjustment: None,
+                type3: Vec::new(),
+            },
+            tlm: Transform2D::identity(),
+            path: Path::new(),
+            next: 0,
+        }
+    }
+
+    /// Run the operations of `content` from where `state` left off, up to operation `end`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn run(
+        &mut self,
+        doc: &'a Document,
+        content: &DecodedContent,
+        state: &mut StreamState<'a>,
+        end: usize,
+        resources: &'a Dictionary,
+        media_box: &MediaBox,
+        output: &mut dyn OutputDev,
+        page_num: u32,
+    ) -> Result<(), OutputError> {
+        let inline_images = &content.inline_images;
+        let StreamState {
+            font_table,
+            gs,
+            gs_stack,
+            mc_stack,
+            actual_text,
+            glyphs,
+            // XXX: replace tlm with a point for text start
+            tlm,
+            path,
+            next,
+        } = state;
         let flip_ctm = Transform2D::row_major(1., 0., 0., -1., 0., media_box.ury - media_box.lly);
         dlog!("MediaBox {:?}", media_box);
-        for operation in &content.operations {
+        let end = end.min(content.operations.len());
+        while *next < end {
+            let i = *next;
+            let operation = &content.operations[i];
+            *next += 1;
             //dlog!("op: {:?}", operation);
 
             match operation.operator.as_ref() {
                 "BT" => {
-                    tlm = Transform2D::identity();
-                    gs.ts.tm = tlm;
+                    *tlm = Transform2D::identity();
+                    gs.ts.tm = *tlm;
                 }
                 "ET" => {
-                    tlm = Transform2D::identity();
-                    gs.ts.tm = tlm;
+                    *tlm = Transform2D::identity();
+                    gs.ts.tm = *tlm;
                 }
                 "cm" => {
                     assert!(operation.operands.len() == 6);
@@ -88,11 +323,13 @@ impl<'a> Processor<'a> {
                 }
                 "CS" => {
                     let name = operation.operands[0].as_name().unwrap();
-                    gs.stroke_colorspace = make_colorspace(doc, name, resources);
+                    let cs_resources = self.colorspace_resources(doc, name, resources);
+                    gs.stroke_colorspace = make_colorspace(doc, name, cs_resources);
                 }
                 "cs" => {
                     let name = operation.operands[0].as_name().unwrap();
-                    gs.fill_colorspace = make_colorspace(doc, name, resources);
+                    let cs_resources = self.colorspace_resources(doc, name, resources);
+                    gs.fill_colorspace = make_colorspace(doc, name, cs_resources);
                 }
                 "SC" | "SCN" => {
                     gs.stroke_color = match gs.stroke_colorspace {
@@ -112,15 +349,75 @@ impl<'a> Processor<'a> {
                         _ => operation.operands.iter().map(|x| as_num(x)).collect(),
                     };
                 }
-                "G" | "g" | "RG" | "rg" | "K" | "k" => {
-                    dlog!("unhandled color operation {:?}", operation);
+                "G" | "g" => {
+                    // the gray level is clamped to 0..1, and a missing one leaves no color
+                    // rather than failing
+                    let color: Vec<f64> = operation
+                        .operands
+                        .first()
+                        .and_then(|o| o.as_float().ok())
+                        .map(|gray| (gray as f64).clamp(0., 1.))
+                        .into_iter()
+                        .collect();
+                    if operation.operator == "G" {
+                        gs.stroke_colorspace = ColorSpace::DeviceGray;
+                        gs.stroke_color = color;
+                    } else {
+                        gs.fill_colorspace = ColorSpace::DeviceGray;
+                        gs.fill_color = color;
+                    }
+                }
+                "RG" | "rg" => {
+                    let color = operation.operands.iter().map(as_num).collect();
+                    if operation.operands.len() != 3 {
+                        log::warn!(
+                            "ignoring {} without 3 operands {:?}",
+                            operation.operator,
+                            operation
+                        );
+                    } else if operation.operator == "RG" {
+                        gs.stroke_colorspace = ColorSpace::DeviceRGB;
+                        gs.stroke_color = color;
+                    } else {
+                        gs.fill_colorspace = ColorSpace::DeviceRGB;
+                        gs.fill_color = color;
+                    }
+                }
+                "K" | "k" => {
+                    let color = operation.operands.iter().map(as_num).collect();
+                    if operation.operands.len() != 4 {
+                        log::warn!(
+                            "ignoring {} without 4 operands {:?}",
+                            operation.operator,
+                            operation
+                        );
+                    } else if operation.operator == "K" {
+                        gs.stroke_colorspace = ColorSpace::DeviceCMYK;
+                        gs.stroke_color = color;
+                    } else {
+                        gs.fill_colorspace = ColorSpace::DeviceCMYK;
+                        gs.fill_color = color;
+                    }
                 }
                 "TJ" => match operation.operands[0] {
                     Object::Array(ref array) => {
-                        for e in array {
+                        for (i, e) in array.iter().enumerate() {
                             match e {
                                 &Object::String(ref s, _) => {
-                                    show_text(&mut gs, s, &tlm, &flip_ctm, output)?;
+                                    glyphs.adjustment = match array.get(i + 1) {
+                                        Some(&Object::Integer(i)) => Some(i as f64),
+                                        Some(&Object::Real(r)) => Some(r as f64),
+                                        _ => None,
+                                    };
+                                    show_text(
+                                        gs,
+                                        s,
+                                        tlm,
+                                        &flip_ctm,
+                                        actual_text.as_mut(),
+                                        glyphs,
+                                        output,
+                                    )?;
                                 }
                                 &Object::Integer(i) => {
                                     let ts = &mut gs.ts;
@@ -154,14 +451,21 @@ impl<'a> Processor<'a> {
                     }
                     _ => {}
                 },
-                "Tj" => match operation.operands[0] {
-                    Object::String(ref s, _) => {
-                        show_text(&mut gs, s, &tlm, &flip_ctm, output)?;
-                    }
-                    _ => {
-                        panic!("unexpected Tj operand {:?}", operation)
+                "Tj" => {
+                    // a malformed stream can leave Tj with the wrong operand, which we skip
+                    let operand = match operation.operands.first() {
+                        Some(&Object::Reference(id)) => doc.get_object(id).ok(),
+                        operand => operand,
+                    };
+                    match operand {
+                        Some(Object::String(s, _)) => {
+                            show_text(gs, s, tlm, &flip_ctm, actual_text.as_mut(), glyphs, output)?;
+                        }
+                        _ => {
+                            log::warn!("ignoring unexpected Tj operand {:?}", operation)
+                        }
                     }
-                },
+                }
                 "Tc" => {
                     gs.ts.character_spacing = as_num(&operation.operands[0]);
                 }
@@ -179,7 +483,14 @@ impl<'a> Processor<'a> {
                     let name = operation.operands[0].as_name().unwrap();
                     let font = font_table
                         .entry(name.to_owned())
-                        .or_insert_with(|| make_font(doc, get::<&Dictionary>(doc, fonts, name)))
+                        .or_insert_with(|| {
+                            make_font(
+                                doc,
+                                get::<&Dictionary>(doc, fonts, name),
+                                self.glyph_resolver,
+                                self.font_substitutes,
+                            )
+                        })
                         .clone();
                     {
                         /*let file = font.get_descriptor().and_then(|desc| desc.get_file());
@@ -191,6 +502,12 @@ impl<'a> Processor<'a> {
                         }*/
                     }
                     gs.ts.font = Some(font);
+                    let font_dict = get::<&Dictionary>(doc, fonts, name);
+                    gs.ts.type3_font = (maybe_get_name(doc, font_dict, b"Subtype")
+                        == Some(b"Type3"))
+                    .then_some(font_dict);
+                    let base_font = maybe_get_name(doc, font_dict, b"BaseFont").unwrap_or(name);
+                    output.set_font(&pdf_to_utf8(base_font), font_style(doc, font_dict))?;
 
                     gs.ts.font_size = as_num(&operation.operands[1]);
                     dlog!(
@@ -205,7 +522,7 @@ impl<'a> Processor<'a> {
                 }
                 "Tm" => {
                     assert!(operation.operands.len() == 6);
-                    tlm = Transform2D::row_major(
+                    *tlm = Transform2D::row_major(
                         as_num(&operation.operands[0]),
                         as_num(&operation.operands[1]),
                         as_num(&operation.operands[2]),
@@ -213,7 +530,7 @@ impl<'a> Processor<'a> {
                         as_num(&operation.operands[4]),
                         as_num(&operation.operands[5]),
                     );
-                    gs.ts.tm = tlm;
+                    gs.ts.tm = *tlm;
                     dlog!("Tm: matrix {:?}", gs.ts.tm);
                     output.end_line()?;
                 }
@@ -227,8 +544,8 @@ impl<'a> Processor<'a> {
                     let ty = as_num(&operation.operands[1]);
                     dlog!("translation: {} {}", tx, ty);
 
-                    tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
-                    gs.ts.tm = tlm;
+                    *tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
+                    gs.ts.tm = *tlm;
                     dlog!("Td matrix {:?}", gs.ts.tm);
                     output.end_line()?;
                 }
@@ -243,8 +560,8 @@ impl<'a> Processor<'a> {
                     dlog!("translation: {} {}", tx, ty);
                     gs.ts.leading = -ty;
 
-                    tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
-                    gs.ts.tm = tlm;
+                    *tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
+                    gs.ts.tm = *tlm;
                     dlog!("TD matrix {:?}", gs.ts.tm);
                     output.end_line()?;
                 }
@@ -253,27 +570,34 @@ impl<'a> Processor<'a> {
                     let tx = 0.0;
                     let ty = -gs.ts.leading;
 
-                    tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
-                    gs.ts.tm = tlm;
+                    *tlm = tlm.pre_transform(&Transform2D::create_translation(tx, ty));
+                    gs.ts.tm = *tlm;
                     dlog!("T* matrix {:?}", gs.ts.tm);
                     output.end_line()?;
                 }
                 "q" => {
+                    if gs_stack.len() >= self.max_gs_depth {
+                        return Err(lopdf::Error::Invalid(format!(
+                            "graphics states nested deeper than {}",
+                            self.max_gs_depth
+                        ))
+                        .into());
+                    }
                     gs_stack.push(gs.clone());
                 }
                 "Q" => {
                     let s = gs_stack.pop();
                     if let Some(s) = s {
-                        gs = s;
+                        *gs = s;
                     } else {
-                        println!("No state to pop");
+                        log::warn!("No state to pop");
                     }
                 }
                 "gs" => {
                     let ext_gstate: &Dictionary = get(doc, resources, b"ExtGState");
                     let name = operation.operands[0].as_name().unwrap();
                     let state: &Dictionary = get(doc, ext_gstate, name);
-                    apply_state(doc, &mut gs, state);
+                    apply_state(doc, gs, state);
                 }
                 "i" => {
                     dlog!(
@@ -329,15 +653,35 @@ impl<'a> Processor<'a> {
                     as_num(&operation.operands[2]),
                     as_num(&operation.operands[3]),
                 )),
-                "s" | "f*" | "B" | "B*" | "b" => {
-                    dlog!("unhandled path op {:?}", operation);
-                }
-                "S" => {
-                    output.stroke(&gs.ctm, &gs.stroke_colorspace, &gs.stroke_color, &path)?;
-                    path.ops.clear();
-                }
-                "F" | "f" => {
-                    output.fill(&gs.ctm, &gs.fill_colorspace, &gs.fill_color, &path)?;
+                "S" | "s" | "F" | "f" | "f*" | "B" | "B*" | "b" | "b*" => {
+                    let op = operation.operator.as_str();
+                    // s, b and b* close the path first
+                    if matches!(op, "s" | "b" | "b*") {
+                        path.ops.push(PathOp::Close);
+                    }
+                    match op {
+                        "F" | "f" | "B" | "b" => {
+                            output.fill(&gs.ctm, &gs.fill_colorspace, &gs.fill_color, path)?
+                        }
+                        "f*" | "B*" | "b*" => output.fill_even_odd(
+                            &gs.ctm,
+                            &gs.fill_colorspace,
+                            &gs.fill_color,
+                            path,
+                        )?,
+                        _ => {}
+                    }
+                    if !matches!(op, "F" | "f" | "f*") {
+                        // the line width is in user space so scale it the same way the path will be
+                        let line_width = gs.line_width * gs.ctm.determinant().abs().sqrt();
+                        output.stroke(
+                            &gs.ctm,
+                            &gs.stroke_colorspace,
+                            &gs.stroke_color,
+                            line_width,
+                            path,
+                        )?;
+                    }
                     path.ops.clear();
                 }
                 "W" | "w*" => {
@@ -348,10 +692,61 @@ impl<'a> Processor<'a> {
                     path.ops.clear();
                 }
                 "BMC" | "BDC" => {
-                    mc_stack.push(operation);
+                    mc_stack.push(i);
+                    // nested replacements are already covered by the outer one
+                    if actual_text.is_none() {
+                        *actual_text = marked_content_properties(doc, resources, operation)
+                            .and_then(|p| maybe_get_obj(doc, p, b"ActualText"))
+                            .and_then(|t| t.as_str().ok())
+                            .map(|t| ActualText {
+                                depth: mc_stack.len(),
+                                text: Some(pdf_to_utf8(t)),
+                            });
+                    }
+                    let mcid = innermost_mcid(doc, resources, &content.operations, mc_stack)
+                        .or(self.form_mcid);
+                    output.marked_content(mcid)?;
                 }
                 "EMC" => {
+                    if actual_text
+                        .as_ref()
+                        .is_some_and(|a| a.depth == mc_stack.len())
+                    {
+                        // the sequence didn't show any glyphs, like an image of text, so the
+                        // replacement goes where the text would have been drawn
+                        if let Some(text) = actual_text.take().and_then(|a| a.text) {
+                            let font_size = if gs.ts.font_size.is_finite() {
+                                gs.ts.font_size
+                            } else {
+                                0.
+                            };
+                            output.begin_word()?;
+                            output.output_character_with_color(
+                                &gs.ts.tm.post_transform(&gs.ctm),
+                                0.,
+                                0.,
+                                font_size,
+                                &text,
+                                to_rgb(&gs.fill_colorspace, &gs.fill_color),
+                            )?;
+                            output.end_word()?;
+                        }
+                    }
                     mc_stack.pop();
+                    let mcid = innermost_mcid(doc, resources, &content.operations, mc_stack)
+                        .or(self.form_mcid);
+                    output.marked_content(mcid)?;
+                }
+                "BI" => {
+                    // the operand is the index that `split_inline_images` gave the image
+                    let image = operation
+                        .operands
+                        .first()
+                        .and_then(|i| i.as_i64().ok())
+                        .and_then(|i| inline_images.get(i as usize));
+                    if let Some(image) = image {
+                        output.image(&gs.ctm, image)?;
+                    }
                 }
                 "Do" => {
                     // `Do` process an entire subdocument, so we do a recursive call to `process_stream`
@@ -359,17 +754,385 @@ impl<'a> Processor<'a> {
                     let xobject: &Dictionary = get(&doc, resources, b"XObject");
                     let name = operation.operands[0].as_name().unwrap();
                     let xf: &Stream = get(&doc, xobject, name);
-                    let resources = maybe_get_obj(&doc, &xf.dict, b"Resources")
+                    if maybe_get_name(doc, &xf.dict, b"Subtype") == Some(b"Image") {
+                        output.image(&gs.ctm, xf)?;
+                        continue;
+                    }
+                    let id = xobject.get(name).ok().and_then(|o| o.as_reference().ok());
+                    if let Some(id) = id {
+                        if self.forms.contains(&id) {
+                            log::warn!("skipping {:?} which is already being drawn", id);
+                            continue;
+                        }
+                        self.forms.push(id);
+                    }
+                    let form_resources = maybe_get_obj(&doc, &xf.dict, b"Resources")
                         .and_then(|n| n.as_dict().ok())
                         .unwrap_or(resources);
                     let contents = get_contents(xf);
-                    self.process_stream(&doc, contents, resources, &media_box, output, page_num)?;
+                    // the form is drawn with the current CTM followed by its /Matrix and clipped to its /BBox
+                    let form_ctm = match maybe_get_obj(doc, &xf.dict, b"Matrix")
+                        .and_then(|m| m.as_array().ok())
+                    {
+                        Some(m) if m.len() == 6 => gs.ctm.pre_transform(&Transform2D::row_major(
+                            as_num(&m[0]),
+                            as_num(&m[1]),
+                            as_num(&m[2]),
+                            as_num(&m[3]),
+                            as_num(&m[4]),
+                            as_num(&m[5]),
+                        )),
+                        _ => gs.ctm,
+                    };
+                    let form_clip = match maybe_get_obj(doc, &xf.dict, b"BBox")
+                        .and_then(|b| b.as_array().ok())
+                    {
+                        Some(b) if b.len() == 4 => {
+                            let bbox = [as_num(&b[0]), as_num(&b[1]), as_num(&b[2]), as_num(&b[3])];
+                            let clip = transform_rect(&form_ctm, bbox);
+                            Some(match gs.form_clip {
+                                Some(outer) => [
+                                    clip[0].max(outer[0]),
+                                    clip[1].max(outer[1]),
+                                    clip[2].min(outer[2]),
+                                    clip[3].min(outer[3]),
+                                ],
+                                None => clip,
+                            })
+                        }
+                        _ => gs.form_clip,
+                    };
+                    // the form's content is part of the sequence it's drawn in
+                    let mcid = innermost_mcid(doc, resources, &content.operations, mc_stack)
+                        .or(self.form_mcid);
+                    let saved = (self.form_ctm, self.form_clip, self.form_mcid);
+                    self.form_ctm = form_ctm;
+                    self.form_clip = form_clip;
+                    self.form_mcid = mcid;
+                    self.parent_resources.push(resources);
+                    let result = self.process_stream(
+                        &doc,
+                        contents,
+                        form_resources,
+                        &media_box,
+                        output,
+                        page_num,
+                    );
+                    self.parent_resources.pop();
+                    (self.form_ctm, self.form_clip, self.form_mcid) = saved;
+                    if id.is_some() {
+                        self.forms.pop();
+                    }
+                    result?;
+                    // marked content in the form leaves the output with the form's identifiers
+                    output.marked_content(mcid)?;
                 }
+                // the glyph metrics at the start of Type3 glyph procedures
+                "d0" | "d1" => {}
                 _ => {
                     dlog!("unknown operation {:?}", operation);
+                    if let Some(callback) = self.unknown_operator.as_mut() {
+                        callback(operation);
+                    }
+                }
+            }
+
+            // Type3 glyphs aren't drawn, but the images drawn by their glyph procedures are given
+            // to the output, e.g. for fonts of scanned glyphs
+            let type3_glyphs = std::mem::take(&mut glyphs.type3);
+            if let (Some(font), false) = (gs.ts.type3_font, self.in_char_proc) {
+                for (code, glyph_ctm) in type3_glyphs {
+                    let Some(char_proc) = self.char_proc(doc, font, code) else {
+                        continue;
+                    };
+                    let char_proc_resources =
+                        maybe_get::<&Dictionary>(doc, font, b"Resources").unwrap_or(resources);
+                    let saved = (self.form_ctm, self.in_char_proc);
+                    (self.form_ctm, self.in_char_proc) = (glyph_ctm, true);
+                    let result = self.process_decoded(
+                        doc,
+                        &char_proc,
+                        char_proc_resources,
+                        media_box,
+                        &mut ImageOutput(&mut *output),
+                        page_num,
+                    );
+                    (self.form_ctm, self.in_char_proc) = saved;
+                    // a broken glyph procedure only loses its images, not the page's text
+                    if let Err(e) = result {
+                        log::warn!("ignoring the Type3 glyph procedure for {}: {}", code, e);
+                    }
                 }
             }
         }
         Ok(())
     }
 }
+
+// The axis aligned bounding box of `rect` after transforming it by `m`
+pub(crate) fn transform_rect(m: &Transform, rect: [f64; 4]) -> [f64; 4] {
+    let corners = [
+        (rect[0], rect[1]),
+        (rect[2], rect[1]),
+        (rect[0], rect[3]),
+        (rect[2], rect[3]),
+    ]
+    .map(|(x, y)| m.transform_point(euclid::point2(x, y)));
+    corners.iter().fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |r, p| [r[0].min(p.x), r[1].min(p.y), r[2].max(p.x), r[3].max(p.y)],
+    )
+}
+
+// Replace each inline image (BI ... ID ... EI) in `content` with "n BI", where n is the index of
+// the image in the returned list. Strings and comments are skipped so that "BI" in them is left alone.
+pub(crate) fn split_inline_images(content: Vec<u8>) -> (Vec<u8>, Vec<Stream>) {
+    if !content.windows(2).any(|w| w == b"BI") {
+        return (content, Vec::new());
+    }
+    let mut result = Vec::with_capacity(content.len());
+    let mut images = Vec::new();
+    let mut i = 0;
+    while i < content.len() {
+        match content[i] {
+            b'%' => {
+                let end = content[i..]
+                    .iter()
+                    .position(|&c| c == b'\r' || c == b'\n')
+                    .map_or(content.len(), |p| i + p);
+                result.extend_from_slice(&content[i..end]);
+                i = end;
+            }
+            b'(' => {
+                let mut depth = 0;
+                let start = i;
+                while i < content.len() {
+                    match content[i] {
+                        b'\\' => i += 1,
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                let end = i.min(content.len());
+                result.extend_from_slice(&content[start..end]);
+            }
+            b'B' if is_token(&content, i, b"BI") => match inline_image(&content, i + 2) {
+                Some((image, end)) => {
+                    result.extend_from_slice(format!(" {} BI ", images.len()).as_bytes());
+                    images.push(image);
+                    i = end;
+                }
+                None => {
+                    // leave the rest of the content as it is rather than lose it
+                    log::warn!("unterminated or malformed inline image");
+                    result.extend_from_slice(&content[i..]);
+                    break;
+                }
+            },
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+    (result, images)
+}
+
+// Whether `token` is at `i` in `content` with whitespace (or the start or end) around it
+fn is_token(content: &[u8], i: usize, token: &[u8]) -> bool {
+    content[i..].starts_with(token)
+        && (i == 0 || content[i - 1].is_ascii_whitespace())
+        && content
+            .get(i + token.len())
+            .is_none_or(|c| c.is_ascii_whitespace())
+}
+
+// Parse the inline image whose dictionary starts at `start`, returning it and the offset of the
+// end of its EI
+fn inline_image(content: &[u8], start: usize) -> Option<(Stream, usize)> {
+    let id = (start..content.len()).find(|&i| is_token(content, i, b"ID"))?;
+    // a single whitespace character separates ID from the data
+    let data_start = id + 3;
+    let ei = (data_start..content.len()).find(|&i| is_token(content, i, b"EI"))?;
+    let data = content[data_start.min(ei - 1)..ei - 1].to_vec();
+    let mut dict_content = b"<<".to_vec();
+    dict_content.extend_from_slice(&content[start..id]);
+    dict_content.extend_from_slice(b">> BI");
+    let dict = Content::decode(&dict_content).ok()?;
+    let dict = dict.operations.first()?.operands.first()?.as_dict().ok()?;
+    Some((Stream::new(expand_inline_image_dict(dict), data), ei + 2))
+}
+
+// Spell out the abbreviated keys and names of an inline image dictionary so that it reads like an
+// image XObject's
+fn expand_inline_image_dict(dict: &Dictionary) -> Dictionary {
+    fn expand_name(name: &[u8]) -> &[u8] {
+        match name {
+            b"AHx" => b"ASCIIHexDecode",
+            b"A85" => b"ASCII85Decode",
+            b"LZW" => b"LZWDecode",
+            b"Fl" => b"FlateDecode",
+            b"RL" => b"RunLengthDecode",
+            b"CCF" => b"CCITTFaxDecode",
+            b"DCT" => b"DCTDecode",
+            b"G" => b"DeviceGray",
+            b"RGB" => b"DeviceRGB",
+            b"CMYK" => b"DeviceCMYK",
+            b"I" => b"Indexed",
+            name => name,
+        }
+    }
+    fn expand(value: &Object) -> Object {
+        match value {
+            Object::Name(name) => Object::Name(expand_name(name).to_vec()),
+            Object::Array(array) => Object::Array(array.iter().map(expand).collect()),
+            value => value.clone(),
+        }
+    }
+    let mut expanded = Dictionary::new();
+    expanded.set("Type", "XObject");
+    expanded.set("Subtype", "Image");
+    for (key, value) in dict.iter() {
+        let key: &[u8] = match &key[..] {
+            b"BPC" => b"BitsPerComponent",
+            b"CS" => b"ColorSpace",
+            b"D" => b"Decode",
+            b"DP" => b"DecodeParms",
+            b"F" => b"Filter",
+            b"H" => b"Height",
+            b"IM" => b"ImageMask",
+            b"I" => b"Interpolate",
+            b"W" => b"Width",
+            key => key,
+        };
+        let value = match key {
+            b"ColorSpace" | b"Filter" => expand(value),
+            _ => value.clone(),
+        };
+        expanded.set(key.to_vec(), value);
+    }
+    expanded
+}
+
+// Drops everything, for running operations whose output isn't wanted
+pub(crate) struct NullOutput;
+
+impl OutputDev for NullOutput {
+    fn begin_page(
+        &mut self,
+        _page_num: u32,
+        _media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        _trm: &Transform,
+        _width: f64,
+        _spacing: f64,
+        _font_size: f64,
+        _char: &str,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+// Only passes on images, for running operations whose text and paths aren't wanted
+struct ImageOutput<'b>(&'b mut dyn OutputDev);
+
+impl OutputDev for ImageOutput<'_> {
+    fn begin_page(
+        &mut self,
+        _page_num: u32,
+        _media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        _trm: &Transform,
+        _width: f64,
+        _spacing: f64,
+        _font_size: f64,
+        _char: &str,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+    fn image(&mut self, ctm: &Transform, image: &Stream) -> Result<(), OutputError> {
+        self.0.image(ctm, image)
+    }
+}
+
+// The properties of a BDC operation. They're either inline or a name in the /Properties resources.
+fn marked_content_properties<'a>(
+    doc: &'a Document,
+    resources: &'a Dictionary,
+    operation: &'a Operation,
+) -> Option<&'a Dictionary> {
+    let properties = match operation.operands.get(1)? {
+        Object::Dictionary(properties) => properties,
+        Object::Name(name) => {
+            let properties = maybe_get_obj(doc, resources, b"Properties")?
+                .as_dict()
+                .ok()?;
+            maybe_get_obj(doc, properties, name)?.as_dict().ok()?
+        }
+        _ => return None,
+    };
+    Some(properties)
+}
+
+fn marked_content_id(doc: &Document, resources: &Dictionary, operation: &Operation) -> Option<i64> {
+    let properties = marked_content_properties(doc, resources, operation)?;
+    maybe_get_obj(doc, properties, b"MCID")?.as_i64().ok()
+}
+
+// The identifier of the innermost of the open sequences, given by the indices of their BMC and
+// BDC operations, that has one
+fn innermost_mcid(
+    doc: &Document,
+    resources: &Dictionary,
+    operations: &[Operation],
+    mc_stack: &[usize],
+) -> Option<i64> {
+    mc_stack
+        .iter()
+        .rev()
+        .find_map(|&op| marked_content_id(doc, resources, &operations[op]))
+}
@@ -1,5 +1,7 @@
 use encoding_rs::UTF_16BE;
 use lopdf::{Dictionary, Document, Object};
+use std::collections::BTreeMap;
+use std::str;
 
 macro_rules! dlog {
     ($($e:expr),*) => { {$(let _ = $e;)*} }
@@ -135,3 +137,488 @@ pub fn maybe_get_obj<'a>(
 ) -> Option<&'a Object> {
     dict.get(key).map(|o| maybe_deref(doc, o)).ok()
 }
+
+/// Walk a name tree rooted at `node` and collect its (key, value) pairs in order.
+/// Values are returned dereferenced.
+pub fn name_tree_entries<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+) -> Vec<(Vec<u8>, &'a Object)> {
+    let mut entries = Vec::new();
+    collect_name_tree(doc, node, &mut entries, 0);
+    entries
+}
+
+fn collect_name_tree<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    entries: &mut Vec<(Vec<u8>, &'a Object)>,
+    depth: usize,
+) {
+    // guard against malformed trees that refer back to themselves
+    if depth > 32 {
+        dlog!("name tree too deep");
+        return;
+    }
+    if let Some(names) = maybe_get_obj(doc, node, b"Names").and_then(|n| n.as_array().ok()) {
+        for pair in names.chunks(2) {
+            if let [key, value] = pair {
+                if let Object::String(key, _) = maybe_deref(doc, key) {
+                    entries.push((key.clone(), maybe_deref(doc, value)));
+                }
+            }
+        }
+    }
+    if let Some(kids) = maybe_get_obj(doc, node, b"Kids").and_then(|n| n.as_array().ok()) {
+        for kid in kids {
+            if let Ok(kid) = maybe_deref(doc, kid).as_dict() {
+                collect_name_tree(doc, kid, entries, depth + 1);
+            }
+        }
+    }
+}
+
+/// The strong direction of `c`: `Some(true)` for right-to-left scripts (Hebrew, Arabic, Syriac,
+/// Thaana, N'Ko, ...), `Some(false)` for other letters and `None` for neutral characters like
+/// digits, punctuation and spaces.
+pub fn direction(c: char) -> Option<bool> {
+    if is_rtl(c) {
+        Some(true)
+    } else if c.is_alphabetic() {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub fn is_rtl(c: char) -> bool {
+    matches!(c as u32,
+        0x0590..=0x08ff
+        | 0xfb1d..=0xfdff
+        | 0xfe70..=0xfeff
+        | 0x10800..=0x10fff
+        | 0x1e800..=0x1efff)
+}
+
+// The decoders below are lenient in the same way pdfium's are: malformed input stops decoding
+// and whatever was decoded so far is returned.
+
+pub fn ascii_hex_decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    let mut high = None;
+    for &c in input {
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            b'>' => break,
+            c if c.is_ascii_whitespace() || c == 0 => continue,
+            _ => {
+                dlog!("bad hex digit {}", c);
+                break;
+            }
+        };
+        match high.take() {
+            Some(h) => output.push(h << 4 | digit),
+            None => high = Some(digit),
+        }
+    }
+    // "If the filter encounters the EOD marker after reading an odd number of hexadecimal digits,
+    // it shall behave as if a 0 (zero) followed the last digit."
+    if let Some(h) = high {
+        output.push(h << 4);
+    }
+    output
+}
+
+pub fn ascii85_decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 4 / 5);
+    let mut group = [0u8; 5];
+    let mut len = 0;
+    for &c in input {
+        match c {
+            b'!'..=b'u' => {
+                group[len] = c - b'!';
+                len += 1;
+                if len == 5 {
+                    let value = group
+                        .iter()
+                        .fold(0u32, |acc, &d| acc.wrapping_mul(85).wrapping_add(d as u32));
+                    output.extend_from_slice(&value.to_be_bytes());
+                    len = 0;
+                }
+            }
+            b'z' if len == 0 => output.extend_from_slice(&[0; 4]),
+            b'~' => break,
+            c if c.is_ascii_whitespace() || c == 0 => {}
+            _ => {
+                dlog!("bad ascii85 character {}", c);
+                break;
+            }
+        }
+    }
+    // a final partial group of n characters is padded with 'u' and produces n - 1 bytes
+    if len > 1 {
+        for d in group.iter_mut().skip(len) {
+            *d = b'u' - b'!';
+        }
+        let value = group
+            .iter()
+            .fold(0u32, |acc, &d| acc.wrapping_mul(85).wrapping_add(d as u32));
+        output.extend_from_slice(&value.to_be_bytes()[..len - 1]);
+    }
+    output
+}
+
+pub fn run_length_decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 2);
+    let mut i = 0;
+    while i < input.len() {
+        let length = input[i] as usize;
+        i += 1;
+        if length < 128 {
+            let end = (i + length + 1).min(input.len());
+            output.extend_from_slice(&input[i..end]);
+            i = end;
+        } else if length > 128 {
+            if let Some(&b) = input.get(i) {
+                output.extend(std::iter::repeat_n(b, 257 - length));
+            }
+            i += 1;
+        } else {
+            // 128 is the EOD marker
+            break;
+        }
+    }
+    output
+}
+
+/// The contextual form of an Arabic presentation form character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoiningForm {
+    Isolated,
+    Final,
+    Initial,
+    Medial,
+}
+
+// The letters in Arabic Presentation Forms-B come in groups of isolated, final, initial and
+// medial forms for dual joining letters and isolated and final forms for right joining letters.
+// These are the first code points of the dual joining groups.
+const DUAL_JOINING_FORMS: &[u32] = &[
+    0xfe89, 0xfe8f, 0xfe95, 0xfe99, 0xfe9d, 0xfea1, 0xfea5, 0xfeb1, 0xfeb5, 0xfeb9, 0xfebd, 0xfec1,
+    0xfec5, 0xfec9, 0xfecd, 0xfed1, 0xfed5, 0xfed9, 0xfedd, 0xfee1, 0xfee5, 0xfee9, 0xfef1,
+];
+
+pub fn joining_form(c: char) -> Option<JoiningForm> {
+    let c = c as u32;
+    if !(0xfe80..=0xfefc).contains(&c) {
+        return None;
+    }
+    if let Some(start) = DUAL_JOINING_FORMS.iter().find(|&&s| c >= s && c < s + 4) {
+        return Some(match c - start {
+            0 => JoiningForm::Isolated,
+            1 => JoiningForm::Final,
+            2 => JoiningForm::Initial,
+            _ => JoiningForm::Medial,
+        });
+    }
+    // the remaining letters, besides the lone hamza at 0xfe80, are right joining pairs that
+    // start on odd code points
+    if c == 0xfe80 || c % 2 == 1 {
+        Some(JoiningForm::Isolated)
+    } else {
+        Some(JoiningForm::Final)
+    }
+}
+
+/// How an Arabic letter in its nominal (base) form joins to its neighbours, as given by
+/// Unicode's ArabicShaping.txt. Letters that don't join have none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoiningType {
+    /// joins to the letters on both sides, like beh
+    Dual,
+    /// only joins to the letter before it, like alef
+    Right,
+    /// makes the letters on both sides join, like tatweel
+    JoinCausing,
+}
+
+pub fn joining_type(c: char) -> Option<JoiningType> {
+    use JoiningType::*;
+    Some(match c as u32 {
+        0x0640 => JoinCausing,
+        0x0620
+        | 0x0626
+        | 0x0628
+        | 0x062a..=0x062e
+        | 0x0633..=0x063f
+        | 0x0641..=0x0647
+        | 0x0649..=0x064a
+        | 0x066e..=0x066f
+        | 0x0678..=0x0687
+        | 0x069a..=0x06bf
+        | 0x06c1..=0x06c2
+        | 0x06cc
+        | 0x06ce
+        | 0x06d0..=0x06d1
+        | 0x06fa..=0x06fc
+        | 0x06ff
+        | 0x0750..=0x0758
+        | 0x075c..=0x076a
+        | 0x076d..=0x0770
+        | 0x0772
+        | 0x0775..=0x0777
+        | 0x077a..=0x077f => Dual,
+        0x0622..=0x0625
+        | 0x0627
+        | 0x0629
+        | 0x062f..=0x0632
+        | 0x0648
+        | 0x0671..=0x0673
+        | 0x0675..=0x0677
+        | 0x0688..=0x0699
+        | 0x06c0
+        | 0x06c3..=0x06cb
+        | 0x06cd
+        | 0x06cf
+        | 0x06d2..=0x06d3
+        | 0x06d5
+        | 0x06ee..=0x06ef
+        | 0x0759..=0x075b
+        | 0x076b..=0x076c
+        | 0x0771
+        | 0x0773..=0x0774
+        | 0x0778..=0x0779 => Right,
+        _ => return None,
+    })
+}
+
+/// Whether a line ending with `last` and a line starting with `first` are the two halves of
+/// one Arabic word: `last` has to join to the following letter (an initial or medial form, a
+/// dual joining letter in its base form, or a tatweel used for justification) and `first` has
+/// to join to the letter before it (a final or medial form, or a dual or right joining letter
+/// in its base form).
+pub fn continues_arabic_word(last: char, first: char) -> bool {
+    let last_joins = match joining_form(last) {
+        Some(form) => matches!(form, JoiningForm::Initial | JoiningForm::Medial),
+        None => matches!(
+            joining_type(last),
+            Some(JoiningType::Dual | JoiningType::JoinCausing)
+        ),
+    };
+    let first_joins = match joining_form(first) {
+        Some(form) => matches!(form, JoiningForm::Final | JoiningForm::Medial),
+        None => joining_type(first).is_some(),
+    };
+    last_joins && first_joins
+}
+
+/// The letters a ligature is made of, for the Latin and Armenian ligatures
+/// and the Arabic lam-alef presentation forms
+fn ligature_components(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{FB00}' => "ff",
+        '\u{FB01}' => "fi",
+        '\u{FB02}' => "fl",
+        '\u{FB03}' => "ffi",
+        '\u{FB04}' => "ffl",
+        '\u{FB05}' => "\u{17F}t",
+        '\u{FB06}' => "st",
+        '\u{0587}' => "\u{565}\u{582}",
+        '\u{FB13}' => "\u{574}\u{576}",
+        '\u{FB14}' => "\u{574}\u{565}",
+        '\u{FB15}' => "\u{574}\u{56B}",
+        '\u{FB16}' => "\u{57E}\u{576}",
+        '\u{FB17}' => "\u{574}\u{56D}",
+        '\u{FEF5}' | '\u{FEF6}' => "\u{644}\u{622}",
+        '\u{FEF7}' | '\u{FEF8}' => "\u{644}\u{623}",
+        '\u{FEF9}' | '\u{FEFA}' => "\u{644}\u{625}",
+        '\u{FEFB}' | '\u{FEFC}' => "\u{644}\u{627}",
+        _ => return None,
+    })
+}
+
+/// Expand the ligatures in `s` into their letters. Unlike NFKC this leaves everything else alone.
+pub fn decompose_ligatures(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match ligature_components(c) {
+            Some(components) => result += components,
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// Parse the `N G obj` header of an indirect object at the start of `input`
+fn object_header(input: &[u8]) -> Option<(u32, u16)> {
+    fn number(input: &[u8]) -> Option<(u64, &[u8])> {
+        let len = input.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 || len > 10 {
+            return None;
+        }
+        let n = str::from_utf8(&input[..len]).ok()?.parse().ok()?;
+        Some((n, &input[len..]))
+    }
+    fn whitespace(input: &[u8]) -> Option<&[u8]> {
+        let len = input.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        if len == 0 {
+            None
+        } else {
+            Some(&input[len..])
+        }
+    }
+    let (id, rest) = number(input)?;
+    let (gen, rest) = number(whitespace(rest)?)?;
+    let rest = whitespace(rest)?;
+    if rest.starts_with(b"obj") {
+        Some((u32::try_from(id).ok()?, u16::try_from(gen).ok()?))
+    } else {
+        None
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// The length of the `open`..`close` group at the start of `input`, including nested groups.
+// Hex strings are skipped so their closing `>` isn't taken for part of a `>>`.
+fn balanced(input: &[u8], open: &[u8], close: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < input.len() {
+        if input[i..].starts_with(open) {
+            depth += 1;
+            i += open.len();
+        } else if input[i..].starts_with(close) {
+            depth -= 1;
+            i += close.len();
+            if depth == 0 {
+                return Some(i);
+            }
+        } else if input[i] == b'<' {
+            i += find(&input[i..], b">")? + 1;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+// The source of the value of `key` in the dictionary `dict`, e.g. `12 0 R` or `[<ab> <cd>]`
+fn dict_value<'a>(dict: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    let mut start = 0;
+    while let Some(at) = find(&dict[start..], key) {
+        let rest = &dict[start + at + key.len()..];
+        start += at + key.len();
+        // make sure we didn't match the start of a longer name
+        if rest.first().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        let rest = &rest[rest.iter().take_while(|c| c.is_ascii_whitespace()).count()..];
+        let len = if rest.starts_with(b"<<") {
+            balanced(rest, b"<<", b">>")?
+        } else if rest.starts_with(b"[") {
+            balanced(rest, b"[", b"]")?
+        } else if rest.starts_with(b"/") {
+            1 + rest[1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .count()
+        } else {
+            rest.iter()
+                .position(|&c| c == b'/' || c == b'>')
+                .unwrap_or(rest.len())
+        };
+        return Some(rest[..len].trim_ascii());
+    }
+    None
+}
+
+// Keep the /Encrypt, /Info and /ID of the trailer dictionary at the start of `input`, replacing
+// those of earlier trailers
+fn trailer_entries(input: &[u8], entries: &mut BTreeMap<&'static str, Vec<u8>>) {
+    let Some(len) = balanced(input, b"<<", b">>") else {
+        return;
+    };
+    for key in ["Encrypt", "Info", "ID"] {
+        if let Some(value) = dict_value(&input[..len], format!("/{}", key).as_bytes()) {
+            entries.insert(key, value.to_vec());
+        }
+    }
+}
+
+/// Rebuild the cross-reference table of a damaged file by scanning it for objects.
+///
+/// The result is `buffer` with a new xref table and trailer appended. The trailer's /Root is the
+/// last object that looks like a catalog, and its /Encrypt, /Info and /ID come from the last
+/// trailer, or cross-reference stream, that has them. Returns `None` if no catalog could be found.
+pub fn rebuild_xref(buffer: &[u8]) -> Option<Vec<u8>> {
+    let mut offsets: BTreeMap<u32, (u16, usize)> = BTreeMap::new();
+    let mut root = None;
+    let mut entries = BTreeMap::new();
+    let mut pos = 0;
+    while pos < buffer.len() {
+        if buffer[pos..].starts_with(b"trailer") {
+            let rest = &buffer[pos + b"trailer".len()..];
+            let skip = rest.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            trailer_entries(&rest[skip..], &mut entries);
+            pos += b"trailer".len();
+            continue;
+        }
+        let at_line_start = pos == 0 || buffer[pos - 1].is_ascii_whitespace();
+        let header = if at_line_start && buffer[pos].is_ascii_digit() {
+            object_header(&buffer[pos..])
+        } else {
+            None
+        };
+        match header {
+            Some((id, gen)) => {
+                // later definitions replace earlier ones, like an incremental update would
+                offsets.insert(id, (gen, pos));
+                let body = &buffer[pos..];
+                let end = find(body, b"endobj").unwrap_or(body.len());
+                if find(&body[..end], b"/Catalog").is_some() {
+                    root = Some((id, gen));
+                }
+                // a cross-reference stream's dictionary doubles as the trailer
+                if let Some(dict) = find(&body[..end], b"<<") {
+                    let dict = &body[dict..end];
+                    let len = balanced(dict, b"<<", b">>").unwrap_or(0);
+                    if dict_value(&dict[..len], b"/Type") == Some(b"/XRef") {
+                        trailer_entries(dict, &mut entries);
+                    }
+                }
+                pos += end.max(1);
+            }
+            None => pos += 1,
+        }
+    }
+    let Some((root_id, root_gen)) = root else {
+        log::warn!("couldn't find a catalog while rebuilding the xref table");
+        return None;
+    };
+    let size = offsets.keys().next_back()? + 1;
+
+    let mut result = buffer.to_vec();
+    let xref_start = result.len() + 1;
+    let mut xref = format!("\nxref\n0 {}\n0000000000 65535 f\r\n", size);
+    for id in 1..size {
+        match offsets.get(&id) {
+            Some((gen, offset)) => xref += &format!("{:010} {:05} n\r\n", offset, gen),
+            None => xref += "0000000000 00000 f\r\n",
+        }
+    }
+    xref += &format!(
+        "trailer\n<< /Size {} /Root {} {} R",
+        size, root_id, root_gen
+    );
+    for (key, value) in &entries {
+        xref += &format!(" /{} {}", key, String::from_utf8_lossy(value));
+    }
+    xref += &format!(" >>\nstartxref\n{}\n%%EOF\n", xref_start);
+    result.extend_from_slice(xref.as_bytes());
+    Some(result)
+}
@@ -146,3 +146,3792 @@ impl ExpectedText<'_> {
         );
     }
 }
+
+// Builds a single page document that shows `content` using the font returned by `make_font` as /F1
+fn single_page_pdf<F>(make_font: F, content: &[u8]) -> Vec<u8>
+where
+    F: FnOnce(&mut lopdf::Document) -> lopdf::Dictionary,
+{
+    pdf_with_pages(make_font, &[content])
+}
+
+// Builds a document with a page for each of `contents`, all sharing the font from `make_font` as /F1
+fn pdf_with_pages<F>(make_font: F, contents: &[&[u8]]) -> Vec<u8>
+where
+    F: FnOnce(&mut lopdf::Document) -> lopdf::Dictionary,
+{
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font = make_font(&mut doc);
+    let font_id = doc.add_object(font);
+    let mut kids: Vec<Object> = Vec::new();
+    for content in contents {
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => font_id },
+            },
+        });
+        kids.push(page_id.into());
+    }
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    let mut buffer = Vec::new();
+    doc.save_to(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn to_unicode_multi_char_destination() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+        1 begincodespacerange <00> <FF> endcodespacerange \
+        3 beginbfchar <01> <006600660069> <63> <0063> <65> <0065> endbfchar \
+        endcmap end end";
+    let mut widths: Vec<Object> = vec![500.into(); 101];
+    widths[0] = 830.into();
+    let make_font = |doc: &mut lopdf::Document| {
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "FirstChar" => 1,
+            "LastChar" => 101,
+            "Widths" => widths,
+            "ToUnicode" => doc.add_object(Stream::new(dictionary! {}, cmap.to_vec())),
+        }
+    };
+    let pdf = single_page_pdf(make_font, b"BT /F1 12 Tf 10 700 Td <016365> Tj ET");
+    let out = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    assert_eq!(out.trim(), "ffice");
+}
+
+fn helvetica(_doc: &mut lopdf::Document) -> lopdf::Dictionary {
+    use lopdf::dictionary;
+
+    dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    }
+}
+
+#[test]
+fn group_baselines_sorts_out_of_order_glyphs() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // "world" is drawn before "hello" on the first line and the second line is drawn last
+    let content = b"BT /F1 12 Tf 100 700 Td (world) Tj -90 0 Td (hello) Tj 0 -20 Td (next) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s).group_baselines(true);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(s, "hello world\nnext");
+}
+
+#[test]
+fn group_baselines_reads_right_to_left_lines_in_logical_order() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let make_font = |doc: &mut lopdf::Document| mapped_font(doc, &['\u{633}', '\u{644}', '1', '2']);
+    // seen is drawn at the right, then the number on the left and lam last, between them
+    let content = b"BT /F1 12 Tf 106 700 Td (A) Tj -26 0 Td (CD) Tj 20 0 Td (B) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s).group_baselines(true);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(s, "\u{633}\u{644} 12");
+}
+
+#[test]
+fn embedded_text_file() {
+    use lopdf::{dictionary, Object, Stream, StringFormat};
+
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    let file_id = doc.add_object(Stream::new(
+        dictionary! { "Type" => "EmbeddedFile", "Subtype" => "text/csv" },
+        b"a,b\n1,2\n".to_vec(),
+    ));
+    let spec_id = doc.add_object(dictionary! {
+        "Type" => "Filespec",
+        "F" => Object::String(b"data.csv".to_vec(), StringFormat::Literal),
+        "UF" => Object::String(b"data.csv".to_vec(), StringFormat::Literal),
+        "EF" => dictionary! { "F" => file_id },
+    });
+    let names = dictionary! {
+        "EmbeddedFiles" => dictionary! {
+            "Names" => vec![Object::String(b"key".to_vec(), StringFormat::Literal), spec_id.into()],
+        },
+    };
+    doc.catalog_mut().unwrap().set("Names", names);
+
+    let files = ara_yaaaay::embedded_files(&doc);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "data.csv");
+    assert_eq!(files[0].data, b"a,b\n1,2\n");
+    assert_eq!(files[0].mime.as_deref(), Some("text/csv"));
+}
+
+// A simple font with every glyph 500 units wide whose ToUnicode maps each code starting at 'A'
+// to the corresponding character of `chars`
+fn mapped_font(doc: &mut lopdf::Document, chars: &[char]) -> lopdf::Dictionary {
+    use lopdf::{dictionary, Object, Stream};
+
+    let mut cmap = format!(
+        "/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+        1 begincodespacerange <00> <FF> endcodespacerange {} beginbfchar ",
+        chars.len()
+    );
+    for (i, c) in chars.iter().enumerate() {
+        cmap += &format!("<{:02X}> <{:04X}> ", b'A' as usize + i, *c as u32);
+    }
+    cmap += "endbfchar endcmap end end";
+    let widths: Vec<Object> = vec![500.into(); chars.len()];
+    dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Mapped",
+        "FirstChar" => 65,
+        "LastChar" => 64 + chars.len() as i64,
+        "Widths" => widths,
+        "ToUnicode" => doc.add_object(Stream::new(dictionary! {}, cmap.into_bytes())),
+    }
+}
+
+// A, B, C and D are the Arabic letters seen, lam, ain and yeh
+fn arabic_font(doc: &mut lopdf::Document) -> lopdf::Dictionary {
+    mapped_font(doc, &['\u{633}', '\u{644}', '\u{639}', '\u{64a}'])
+}
+
+#[test]
+fn rtl_run_word_spacing() {
+    // the second word is drawn to the left of the first one, as right-to-left text is
+    let content = b"BT /F1 12 Tf 300 700 Td (AB) Tj -50 0 Td (CD) Tj ET";
+    let out = ara_yaaaay::extract_text_from_mem(&single_page_pdf(arabic_font, content)).unwrap();
+    assert_eq!(out.trim(), "\u{633}\u{644} \u{639}\u{64a}");
+}
+
+#[test]
+fn page_boxes_inheritance() {
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set(
+        "CropBox",
+        vec![10.into(), 20.into(), 600.into(), 780.into()],
+    );
+    let pages_id = page.get(b"Parent").unwrap().as_reference().unwrap();
+    let pages = doc.get_object_mut(pages_id).unwrap().as_dict_mut().unwrap();
+    pages.set("Rotate", 90);
+
+    let boxes = ara_yaaaay::page_boxes(&doc, 1).unwrap();
+    // the MediaBox and Rotate come from the Pages node
+    assert_eq!(boxes.media_box.urx, 612.);
+    assert_eq!(boxes.media_box.ury, 792.);
+    assert_eq!(boxes.rotate, 90);
+    assert_eq!(boxes.crop_box.llx, 10.);
+    // ArtBox defaults to the CropBox
+    assert_eq!(boxes.art_box.lly, 20.);
+    assert_eq!(boxes.trim_box.urx, 600.);
+    assert!(ara_yaaaay::page_boxes(&doc, 2).is_err());
+}
+
+// Decodes `encoded` with `filter` by attaching it as an embedded file and reading it back
+fn decode_embedded(filter: &[&str], encoded: &[u8]) -> Vec<u8> {
+    use lopdf::{dictionary, Object, Stream, StringFormat};
+
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    let filter: Vec<Object> = filter
+        .iter()
+        .map(|f| Object::Name(f.as_bytes().to_vec()))
+        .collect();
+    let file_id = doc.add_object(Stream::new(
+        dictionary! { "Type" => "EmbeddedFile", "Filter" => filter },
+        encoded.to_vec(),
+    ));
+    let spec_id = doc.add_object(dictionary! {
+        "Type" => "Filespec",
+        "F" => Object::String(b"f".to_vec(), StringFormat::Literal),
+        "EF" => dictionary! { "F" => file_id },
+    });
+    let names = dictionary! {
+        "EmbeddedFiles" => dictionary! {
+            "Names" => vec![Object::String(b"f".to_vec(), StringFormat::Literal), spec_id.into()],
+        },
+    };
+    doc.catalog_mut().unwrap().set("Names", names);
+    ara_yaaaay::embedded_files(&doc).remove(0).data
+}
+
+#[test]
+fn ascii_and_run_length_filters() {
+    assert_eq!(
+        decode_embedded(&["ASCIIHexDecode"], b"48 65 6C6c 6F>"),
+        b"Hello"
+    );
+    // an odd number of digits behaves as if a 0 followed
+    assert_eq!(decode_embedded(&["AHx"], b"6F7>"), b"op");
+    assert_eq!(
+        decode_embedded(&["ASCII85Decode"], b"87cURD]i,\"Ebo80~>"),
+        b"Hello World!"
+    );
+    assert_eq!(decode_embedded(&["A85"], b"z!!~>"), b"\0\0\0\0\0");
+    assert_eq!(
+        decode_embedded(
+            &["RunLengthDecode"],
+            &[2, b'a', b'b', b'c', 253, b'x', 128, b'q']
+        ),
+        b"abcxxxx"
+    );
+    // filters are applied in order
+    assert_eq!(
+        decode_embedded(&["ASCIIHexDecode", "RunLengthDecode"], b"FE 7A 80"),
+        b"zzz"
+    );
+}
+
+#[test]
+fn link_anchor_text() {
+    let content = b"BT /F1 12 Tf 100 700 Td (see ) Tj (click here) Tj ( for more) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    // "see " is 22.68 wide and "click here" is 50.68 wide at 12pt Helvetica
+    let text = ara_yaaaay::anchor_text(&doc, 1, [120., 695., 174., 715.]).unwrap();
+    assert_eq!(text, "click here");
+}
+
+// A Type0 font using Identity-H where the CIDs 1, 2 and 3 map to A, B and C
+fn cid_font(doc: &mut lopdf::Document, descendant: lopdf::Dictionary) -> lopdf::Dictionary {
+    use lopdf::{dictionary, Stream};
+
+    let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+        1 begincodespacerange <0000> <FFFF> endcodespacerange \
+        3 beginbfchar <0001> <0041> <0002> <0042> <0003> <0043> endbfchar \
+        endcmap end end";
+    let mut descendant = descendant;
+    descendant.set("Type", "Font");
+    descendant.set("Subtype", "CIDFontType2");
+    descendant.set("BaseFont", "CID");
+    descendant.set(
+        "FontDescriptor",
+        dictionary! { "Type" => "FontDescriptor", "FontName" => "CID" },
+    );
+    dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "CID",
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![doc.add_object(descendant).into()],
+        "ToUnicode" => doc.add_object(Stream::new(dictionary! {}, cmap.to_vec())),
+    }
+}
+
+#[test]
+fn cid_font_vertical_defaults() {
+    use lopdf::dictionary;
+
+    let mut doc = lopdf::Document::with_version("1.5");
+    let font = cid_font(
+        &mut doc,
+        dictionary! { "DW2" => vec![900.into(), (-1100).into()] },
+    );
+    assert_eq!(
+        ara_yaaaay::font_vertical_default(&doc, &font),
+        Some([900., -1100.])
+    );
+    let font = cid_font(&mut doc, dictionary! {});
+    assert_eq!(
+        ara_yaaaay::font_vertical_default(&doc, &font),
+        Some([880., -1000.])
+    );
+    let font = helvetica(&mut doc);
+    assert_eq!(ara_yaaaay::font_vertical_default(&doc, &font), None);
+}
+
+#[test]
+fn cid_widths_with_indirect_inner_array() {
+    use lopdf::{dictionary, Object};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let inner = doc.add_object(vec![Object::Integer(600), Object::Integer(700)]);
+        let descendant = dictionary! {
+            "W" => vec![1.into(), inner.into(), 3.into(), 3.into(), 500.into()],
+        };
+        cid_font(doc, descendant)
+    };
+    let content = b"BT /F1 10 Tf 100 700 Td <000100020003> Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let widths: Vec<_> = chars.iter().map(|c| (c.char.as_str(), c.width)).collect();
+    assert_eq!(widths, vec![("A", 6.), ("B", 7.), ("C", 5.)]);
+}
+
+#[test]
+fn join_arabic_soft_wraps() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // "salam" split after the initial seen and medial lam, continuing with a final alef
+    let make_font = |doc: &mut lopdf::Document| {
+        mapped_font(
+            doc,
+            &['\u{feb3}', '\u{fee0}', '\u{fe8e}', '\u{fee1}', '\u{fe8d}'],
+        )
+    };
+    let content = b"BT /F1 12 Tf 300 700 Td (AB) Tj 0 -20 Td (CD) Tj 0 -20 Td (E) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let extract = |join| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s).join_arabic_wraps(join);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        s.trim().to_owned()
+    };
+    assert_eq!(
+        extract(false),
+        "\u{feb3}\u{fee0}\n\u{fe8e}\u{fee1}\n\u{fe8d}"
+    );
+    // the isolated meem ends the word so the last line is kept separate
+    assert_eq!(extract(true), "\u{feb3}\u{fee0}\u{fe8e}\u{fee1}\n\u{fe8d}");
+}
+
+#[test]
+fn join_arabic_soft_wraps_of_base_letters() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // seen, lam, alef, meem and hamza without presentation forms
+    let make_font = |doc: &mut lopdf::Document| {
+        mapped_font(
+            doc,
+            &['\u{633}', '\u{644}', '\u{627}', '\u{645}', '\u{621}'],
+        )
+    };
+    let extract = |content: &[u8]| {
+        let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s).join_arabic_wraps(true);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        s.trim().to_owned()
+    };
+    // lam joins to the alef after it, but meem can't join to a hamza
+    assert_eq!(
+        extract(b"BT /F1 12 Tf 300 700 Td (AB) Tj 0 -20 Td (CD) Tj 0 -20 Td (E) Tj ET"),
+        "\u{633}\u{644}\u{627}\u{645}\n\u{621}"
+    );
+    // alef doesn't join to the letter after it
+    assert_eq!(
+        extract(b"BT /F1 12 Tf 300 700 Td (ABC) Tj 0 -20 Td (D) Tj ET"),
+        "\u{633}\u{644}\u{627}\n\u{645}"
+    );
+}
+
+#[test]
+fn stroke_reports_transformed_line_width() {
+    use ara_yaaaay::output::OutputDev;
+    use ara_yaaaay::{ColorSpace, MediaBox, OutputError, Path, Transform};
+
+    #[derive(Default)]
+    struct Strokes(Vec<f64>);
+    impl OutputDev for Strokes {
+        fn begin_page(
+            &mut self,
+            _: u32,
+            _: &MediaBox,
+            _: Option<(f64, f64, f64, f64)>,
+        ) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_page(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn output_character(
+            &mut self,
+            _: &Transform,
+            _: f64,
+            _: f64,
+            _: f64,
+            _: &str,
+        ) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn begin_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_line(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn stroke(
+            &mut self,
+            _: &Transform,
+            _: &ColorSpace,
+            _: &[f64],
+            line_width: f64,
+            _: &Path,
+        ) -> Result<(), OutputError> {
+            self.0.push(line_width);
+            Ok(())
+        }
+    }
+
+    let content = b"3 w 0 0 m 10 10 l S q 2 0 0 2 0 0 cm 0 0 m 10 10 l S Q";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut strokes = Strokes::default();
+    ara_yaaaay::output_doc(&doc, &mut strokes).unwrap();
+    assert_eq!(strokes.0, vec![3., 6.]);
+}
+
+#[test]
+fn highlighted_text_over_two_lines() {
+    use lopdf::{dictionary, Object};
+
+    let content =
+        b"BT /F1 12 Tf 100 700 Td (see ) Tj (click here) Tj ( for more) Tj 0 -20 Td (next) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let quad = |llx: f64, lly: f64, urx: f64, ury: f64| -> Vec<Object> {
+        [llx, ury, urx, ury, llx, lly, urx, lly]
+            .iter()
+            .map(|&v| Object::Real(v as f32))
+            .collect()
+    };
+    let mut quad_points = quad(120., 695., 174., 715.);
+    quad_points.extend(quad(95., 675., 130., 695.));
+    let highlight = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Highlight",
+        "Rect" => vec![95.into(), 675.into(), 174.into(), 715.into()],
+        "QuadPoints" => quad_points,
+    });
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Annots", vec![highlight.into()]);
+
+    let highlights = ara_yaaaay::highlighted_text(&doc, 1).unwrap();
+    assert_eq!(highlights.len(), 1);
+    assert_eq!(highlights[0].0, "click here\nnext");
+    assert_eq!(highlights[0].1, [95., 675., 174., 715.]);
+}
+
+#[test]
+fn glyph_resolver_recovers_unmapped_glyphs() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use ara_yaaaay::{Glyph, GlyphResolver, NoGlyphResolver};
+
+    struct MockResolver;
+    impl GlyphResolver for MockResolver {
+        fn resolve(&self, glyph: &Glyph) -> Option<String> {
+            assert_eq!(glyph.base_font, "CID");
+            (glyph.code == 4).then(|| "Z".to_owned())
+        }
+    }
+
+    let make_font = |doc: &mut lopdf::Document| cid_font(doc, lopdf::Dictionary::new());
+    // CID 4 isn't in the ToUnicode map
+    let content = b"BT /F1 10 Tf 100 700 Td <00010004> Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let extract = |resolver: &dyn GlyphResolver| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s);
+            ara_yaaaay::output_doc_with_glyph_resolver(&doc, &mut output, resolver).unwrap();
+        }
+        s.trim().to_owned()
+    };
+    assert_eq!(extract(&MockResolver), "AZ");
+    assert_eq!(extract(&NoGlyphResolver), "A");
+}
+
+#[test]
+fn glyph_resolver_for_simple_and_type3_fonts() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use ara_yaaaay::{Glyph, GlyphResolver, NoGlyphResolver};
+    use lopdf::{dictionary, Object, Stream};
+
+    struct MockResolver;
+    impl GlyphResolver for MockResolver {
+        fn resolve(&self, glyph: &Glyph) -> Option<String> {
+            match glyph.font_file {
+                // Type3 fonts hand over the glyph procedure
+                Some(proc) if proc.ends_with(b"re f") => Some("Q".to_owned()),
+                None if glyph.code == u32::from(b'B') => Some("Z".to_owned()),
+                _ => None,
+            }
+        }
+    }
+    let extract = |doc: &lopdf::Document, resolver: &dyn GlyphResolver| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s);
+            ara_yaaaay::output_doc_with_glyph_resolver(doc, &mut output, resolver).unwrap();
+        }
+        s.trim().to_owned()
+    };
+
+    // B is missing from the ToUnicode map and the font has no /Encoding to fall back to
+    let font = |doc: &mut lopdf::Document| mapped_font(doc, &['x']);
+    let content = b"BT /F1 12 Tf 100 700 Td (AB) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(font, content)).unwrap();
+    assert_eq!(extract(&doc, &MockResolver), "xZ");
+    assert_eq!(extract(&doc, &NoGlyphResolver), "xB");
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let glyph = doc.add_object(Stream::new(
+            dictionary! {},
+            b"100 0 0 0 0 100 d1 0 0 100 100 re f".to_vec(),
+        ));
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type3",
+            "FontBBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "FontMatrix" => vec![0.01.into(), 0.into(), 0.into(), 0.01.into(), 0.into(), 0.into()],
+            "CharProcs" => dictionary! { "g1" => glyph },
+            "Encoding" => dictionary! {
+                "Type" => "Encoding",
+                "Differences" => vec![97.into(), Object::Name(b"g1".to_vec())],
+            },
+            "FirstChar" => 97,
+            "LastChar" => 97,
+            "Widths" => vec![100.into()],
+        }
+    };
+    let content = b"BT /F1 12 Tf 200 500 Td (a) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    assert_eq!(extract(&doc, &MockResolver), "Q");
+    assert_eq!(extract(&doc, &NoGlyphResolver), "a");
+}
+
+#[test]
+fn page_inside_object_stream() {
+    // lopdf doesn't write object streams so the file is put together by hand: the page and its
+    // font live in object stream 5 and a cross-reference stream points into it
+    let page = b"<</Type/Page/Parent 2 0 R/Contents 4 0 R/Resources<</Font<</F1 6 0 R>>>>>>";
+    let font = b"<</Type/Font/Subtype/Type1/BaseFont/Helvetica>>";
+    let header = format!("3 0 6 {} ", page.len() + 1);
+    let mut obj_stm = header.clone().into_bytes();
+    obj_stm.extend_from_slice(page);
+    obj_stm.push(b' ');
+    obj_stm.extend_from_slice(font);
+
+    let mut pdf = b"%PDF-1.5\n".to_vec();
+    let mut offsets = vec![0; 8];
+    fn add(pdf: &mut Vec<u8>, offsets: &mut [usize], id: usize, dict: &str, stream: Option<&[u8]>) {
+        offsets[id] = pdf.len();
+        pdf.extend_from_slice(format!("{} 0 obj\n{}", id, dict).as_bytes());
+        if let Some(stream) = stream {
+            pdf.extend_from_slice(b"\nstream\n");
+            pdf.extend_from_slice(stream);
+            pdf.extend_from_slice(b"\nendstream");
+        }
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    add(
+        &mut pdf,
+        &mut offsets,
+        1,
+        "<</Type/Catalog/Pages 2 0 R>>",
+        None,
+    );
+    add(
+        &mut pdf,
+        &mut offsets,
+        2,
+        "<</Type/Pages/Kids[3 0 R]/Count 1/MediaBox[0 0 612 792]>>",
+        None,
+    );
+    let content = b"BT /F1 12 Tf 100 700 Td (Hello) Tj ET";
+    add(
+        &mut pdf,
+        &mut offsets,
+        4,
+        &format!("<</Length {}>>", content.len()),
+        Some(content),
+    );
+    add(
+        &mut pdf,
+        &mut offsets,
+        5,
+        &format!(
+            "<</Type/ObjStm/N 2/First {}/Length {}>>",
+            header.len(),
+            obj_stm.len()
+        ),
+        Some(&obj_stm),
+    );
+    let xref_offset = pdf.len();
+    let mut xref = Vec::new();
+    for (id, offset) in offsets.iter().enumerate() {
+        let (kind, field2, field3): (u8, u32, u16) = match id {
+            0 => (0, 0, 0xffff),
+            3 => (2, 5, 0),
+            6 => (2, 5, 1),
+            7 => (1, xref_offset as u32, 0),
+            _ => (1, *offset as u32, 0),
+        };
+        xref.push(kind);
+        xref.extend_from_slice(&field2.to_be_bytes());
+        xref.extend_from_slice(&field3.to_be_bytes());
+    }
+    add(
+        &mut pdf,
+        &mut offsets,
+        7,
+        &format!(
+            "<</Type/XRef/Size 8/W[1 4 2]/Root 1 0 R/Length {}>>",
+            xref.len()
+        ),
+        Some(&xref),
+    );
+    pdf.extend_from_slice(format!("startxref\n{}\n%%EOF\n", xref_offset).as_bytes());
+
+    let out = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    assert_eq!(out.trim(), "Hello");
+}
+
+#[test]
+fn output_each_page_separately() {
+    use ara_yaaaay::output::{ConvertToFmt, JSONOutput, OutputDev, PlainTextOutput};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<String>>);
+    impl std::fmt::Write for Shared {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0.borrow_mut().push_str(s);
+            Ok(())
+        }
+    }
+    impl ConvertToFmt for Shared {
+        type Writer = Shared;
+        fn convert(self) -> Self::Writer {
+            self
+        }
+    }
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (one) Tj ET",
+        b"BT /F1 12 Tf 100 650 Td (two) Tj ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let mut outputs = Vec::new();
+    ara_yaaaay::output_doc_per_page(&doc, |page_num| {
+        let s = Shared::default();
+        outputs.push((page_num, s.clone()));
+        Box::new(PlainTextOutput::new(s)) as Box<dyn OutputDev>
+    })
+    .unwrap();
+    let outputs: Vec<_> = outputs
+        .iter()
+        .map(|(n, s)| (*n, s.0.borrow().trim().to_owned()))
+        .collect();
+    assert_eq!(outputs, vec![(1, "one".to_owned()), (2, "two".to_owned())]);
+
+    // each device is finished after its page, which closes the JSON arrays
+    let mut files = vec![Vec::new(), Vec::new()];
+    let mut next_file = files.iter_mut();
+    ara_yaaaay::output_doc_per_page(&doc, |_| {
+        Box::new(JSONOutput::new(next_file.next().unwrap())) as Box<dyn OutputDev>
+    })
+    .unwrap();
+    for file in &files {
+        let chars: serde_json::Value = serde_json::from_slice(file).unwrap();
+        assert_eq!(chars.as_array().unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn differences_with_out_of_range_codes() {
+    use lopdf::{dictionary, Object};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let differences: Vec<Object> = vec![
+            300.into(),
+            Object::Name(b"a".to_vec()),
+            Object::Name(b"b".to_vec()),
+            65.into(),
+            Object::Name(b"B".to_vec()),
+        ];
+        let encoding = doc.add_object(dictionary! {
+            "Type" => "Encoding",
+            "BaseEncoding" => "WinAnsiEncoding",
+            "Differences" => differences,
+        });
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => encoding,
+        }
+    };
+    let content = b"BT /F1 12 Tf 100 700 Td (Ac) Tj ET";
+    let out = ara_yaaaay::extract_text_from_mem(&single_page_pdf(make_font, content)).unwrap();
+    assert_eq!(out.trim(), "Bc");
+}
+
+#[test]
+fn alto_output_for_a_short_line() {
+    use ara_yaaaay::output::{AltoOutput, OutputDev};
+
+    let pdf = single_page_pdf(helvetica, b"BT /F1 12 Tf 100 700 Td (Hi there) Tj ET");
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let mut xml = Vec::new();
+    {
+        let mut output = AltoOutput::new(&mut xml);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        output.finish().unwrap();
+    }
+    let xml = String::from_utf8(xml).unwrap();
+
+    assert!(xml.starts_with("<?xml"));
+    assert!(xml.trim_end().ends_with("</Layout>\n</alto>"));
+    assert_eq!(xml.matches("</alto>").count(), 1);
+    assert!(xml.contains("<MeasurementUnit>inch1200</MeasurementUnit>"));
+    // positions are in 1/1200 of an inch, 50/3 to the point
+    assert!(
+        xml.contains("<Page ID=\"page1\" PHYSICAL_IMG_NR=\"1\" WIDTH=\"10200\" HEIGHT=\"13200\">")
+    );
+    assert_eq!(xml.matches("<TextLine ").count(), 1);
+    assert_eq!(xml.matches("</TextLine>").count(), 1);
+    assert_eq!(xml.matches("<SP/>").count(), 1);
+    // "Hi" is 722 + 222 units wide in Helvetica and the top of the line is 700 + 12 from the bottom,
+    // so it's 11.328pt wide and 80pt from the top
+    assert!(xml.contains(
+        "CONTENT=\"Hi\" HPOS=\"1666.667\" VPOS=\"1333.333\" WIDTH=\"188.8\" HEIGHT=\"200\""
+    ));
+    assert!(xml.contains("CONTENT=\"there\" HPOS=\""));
+
+    // dropping the output closes the document too
+    let mut dropped = Vec::new();
+    {
+        let mut output = AltoOutput::new(&mut dropped);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(String::from_utf8(dropped).unwrap(), xml);
+}
+
+#[test]
+fn self_referential_descendant_fonts() {
+    let make_font = |doc: &mut lopdf::Document| {
+        let mut font = cid_font(doc, lopdf::Dictionary::new());
+        // the font is added right after this returns so this is its own id
+        let font_id = (doc.max_id + 1, 0);
+        font.set("DescendantFonts", vec![font_id.into()]);
+        font
+    };
+    let content = b"BT /F1 10 Tf 100 700 Td <000100020003> Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let text: Vec<_> = chars.iter().map(|c| (c.char.as_str(), c.width)).collect();
+    // the default width of 1000 is used for every glyph
+    assert_eq!(text, vec![("A", 10.), ("B", 10.), ("C", 10.)]);
+}
+
+#[test]
+fn rtl_layout_two_columns() {
+    // each line has a run in the right column at 500 and one in the left column at 100
+    let content = b"BT /F1 12 Tf 500 700 Td (AB) Tj -400 0 Td (CD) Tj \
+        400 -20 Td (CD) Tj -400 0 Td (AB) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(arabic_font, content)).unwrap();
+    let text = ara_yaaaay::extract_text_rtl_layout(&doc, 1).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2);
+    let (ab, cd) = ("\u{633}\u{644}", "\u{639}\u{64a}");
+    assert_eq!(
+        lines[0].split_whitespace().collect::<Vec<_>>(),
+        vec![ab, cd]
+    );
+    assert_eq!(
+        lines[1].split_whitespace().collect::<Vec<_>>(),
+        vec![cd, ab]
+    );
+    // the columns line up on the grid
+    assert_eq!(lines[0].find(cd), lines[1].find(ab));
+    assert_eq!(lines[0].find(ab), lines[1].find(cd));
+}
+
+#[test]
+fn recover_missing_xref() {
+    let pdf = single_page_pdf(helvetica, b"BT /F1 12 Tf 100 700 Td (recovered) Tj ET");
+    // cut the file off right before the xref table, which also drops the trailer and startxref
+    let xref = pdf.windows(5).rposition(|w| w == b"xref\n").unwrap();
+    let damaged = &pdf[..xref];
+    assert!(lopdf::Document::load_mem(damaged).is_err());
+
+    let out = ara_yaaaay::extract_text_from_mem(damaged).unwrap();
+    assert_eq!(out.trim(), "recovered");
+}
+
+#[test]
+fn recover_keeps_trailer_entries() {
+    use lopdf::{dictionary, Object};
+
+    let pdf = single_page_pdf(helvetica, b"BT /F1 12 Tf 100 700 Td (recovered) Tj ET");
+    let mut doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let info = doc.add_object(dictionary! { "Title" => Object::string_literal("Kept") });
+    doc.trailer.set("Info", info);
+    let id = Object::String(vec![0xab, 0x3e], lopdf::StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![id.clone(), id]);
+    let mut pdf = Vec::new();
+    doc.save_to(&mut pdf).unwrap();
+    // point startxref past the end of the file
+    let startxref = pdf.windows(10).rposition(|w| w == b"startxref\n").unwrap();
+    pdf.truncate(startxref);
+    pdf.extend_from_slice(b"startxref\n999999\n%%EOF\n");
+    assert!(lopdf::Document::load_mem(&pdf).is_err());
+
+    let recovered = ara_yaaaay::load_with_recovery(&pdf).unwrap();
+    let info = recovered
+        .trailer
+        .get(b"Info")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let title = recovered
+        .get_dictionary(info)
+        .unwrap()
+        .get(b"Title")
+        .unwrap();
+    assert_eq!(title.as_str().unwrap(), b"Kept");
+    let ids = recovered.trailer.get(b"ID").unwrap().as_array().unwrap();
+    assert_eq!(ids[1].as_str().unwrap(), [0xab, 0x3e]);
+}
+
+#[test]
+fn red_text_reports_its_color() {
+    use ara_yaaaay::output::{JSONOutput, OutputDev};
+
+    let content = b"BT /F1 12 Tf 100 700 Td (ab) Tj 1 0 0 rg (cd) Tj 0 0 1 0 k (e) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| (c.char.as_str(), c.color)).collect();
+    let (black, red, yellow) = ([0., 0., 0.], [1., 0., 0.], [1., 1., 0.]);
+    assert_eq!(
+        colors,
+        vec![
+            ("a", black),
+            ("b", black),
+            ("c", red),
+            ("d", red),
+            ("e", yellow)
+        ]
+    );
+
+    let mut json = Vec::new();
+    {
+        let mut output = JSONOutput::new(&mut json);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        output.finish().unwrap();
+    }
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with('['));
+    assert!(json.trim_end().ends_with(']'));
+    assert_eq!(json.matches("\"page\": 1").count(), 5);
+    assert!(json.contains("\"char\": \"c\", \"x\": 113.344, \"y\": 700, \"width\": 6, \"font_size\": 12, \"color\": [1, 0, 0]"));
+
+    // a matrix that overflows and no call to finish
+    let huge = format!("1{}.0", "0".repeat(200));
+    let content =
+        format!("{huge} 0 0 {huge} 0 0 cm {huge} 0 0 {huge} 0 0 cm BT /F1 12 Tf (a) Tj ET");
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content.as_bytes())).unwrap();
+    let mut json = Vec::new();
+    ara_yaaaay::output_doc(&doc, &mut JSONOutput::new(&mut json)).unwrap();
+    let chars: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(chars[0]["char"], "a");
+    assert!(chars[0]["x"].is_null());
+    assert!(chars[0]["font_size"].is_null());
+}
+
+#[test]
+fn type3_font_matrix_placement() {
+    use lopdf::{dictionary, Object};
+
+    let chars = |font_matrix: [f64; 4], width: i64| {
+        let make_font = |_doc: &mut lopdf::Document| {
+            let font_matrix: Vec<Object> = vec![
+                font_matrix[0].into(),
+                font_matrix[1].into(),
+                font_matrix[2].into(),
+                font_matrix[3].into(),
+                0.into(),
+                0.into(),
+            ];
+            dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type3",
+                "FontBBox" => vec![0.into(), 0.into(), width.into(), width.into()],
+                "FontMatrix" => font_matrix,
+                "CharProcs" => dictionary! {},
+                "Encoding" => "WinAnsiEncoding",
+                "FirstChar" => 97,
+                "LastChar" => 98,
+                "Widths" => vec![width.into(), width.into()],
+            }
+        };
+        let content = b"BT /F1 12 Tf 100 700 Td (ab) Tj ET";
+        let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+        ara_yaaaay::positioned_chars(&doc, 1)
+            .unwrap()
+            .iter()
+            .map(|c| {
+                // FontMatrix entries are stored as f32
+                let round = |v: f64| (v * 1000.).round() / 1000.;
+                (round(c.x), round(c.width), round(c.font_size))
+            })
+            .collect::<Vec<_>>()
+    };
+    let default = chars([0.001, 0., 0., 0.001], 500);
+    assert_eq!(default, vec![(100., 6., 12.), (106., 6., 12.)]);
+    // the same glyphs described in a glyph space with 100 units per em end up in the same place
+    assert_eq!(chars([0.01, 0., 0., 0.01], 50), default);
+    // while doubling the FontMatrix doubles the advance
+    assert_eq!(
+        chars([0.002, 0., 0., 0.002], 500),
+        vec![(100., 12., 12.), (112., 12., 12.)]
+    );
+}
+
+#[test]
+fn bidi_isolates_around_arabic_run() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // A and B are H and i, C and D are the Arabic letters seen and lam
+    let make_font = |doc: &mut lopdf::Document| mapped_font(doc, &['H', 'i', '\u{633}', '\u{644}']);
+    let content = b"BT /F1 12 Tf 100 700 Td (AB) Tj 20 0 Td (CD) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s).bidi_isolates(true);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(
+        s.trim(),
+        "\u{2066}Hi\u{2069} \u{2067}\u{633}\u{644}\u{2069}"
+    );
+}
+
+#[test]
+fn tee_to_text_and_counter() {
+    use ara_yaaaay::output::{OutputDev, PlainTextOutput, TeeOutput};
+    use ara_yaaaay::{MediaBox, OutputError, Transform};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Counter(Rc<Cell<usize>>);
+    impl OutputDev for Counter {
+        fn begin_page(
+            &mut self,
+            _: u32,
+            _: &MediaBox,
+            _: Option<(f64, f64, f64, f64)>,
+        ) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_page(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn output_character(
+            &mut self,
+            _: &Transform,
+            _: f64,
+            _: f64,
+            _: f64,
+            _: &str,
+        ) -> Result<(), OutputError> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+        fn begin_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_line(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+    }
+
+    let content = b"BT /F1 12 Tf 100 700 Td (hello) Tj 0 -14 Td (world) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let count = Rc::new(Cell::new(0));
+    let mut s = String::new();
+    {
+        let mut output = TeeOutput::new(vec![
+            Box::new(PlainTextOutput::new(&mut s)),
+            Box::new(Counter(count.clone())),
+        ]);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(s.trim(), "hello\nworld");
+    assert_eq!(count.get(), 10);
+}
+
+#[test]
+fn contents_stream_or_array() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"BT /F1 12 Tf\n100 700 Td (same) Tj ET";
+    let single = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+
+    let mut split = single.clone();
+    let first = split.add_object(Stream::new(dictionary! {}, b"BT /F1 12 Tf".to_vec()));
+    let hex: String = b"100 700 Td (same) Tj ET"
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    let second = split.add_object(Stream::new(
+        dictionary! { "Filter" => "ASCIIHexDecode" },
+        hex.into_bytes(),
+    ));
+    let page_id = split.get_pages()[&1];
+    let page = split
+        .get_object_mut(page_id)
+        .unwrap()
+        .as_dict_mut()
+        .unwrap();
+    page.set("Contents", vec![Object::from(first), Object::from(second)]);
+
+    let single_bytes = ara_yaaaay::page_content_bytes(&single, 1).unwrap();
+    assert_eq!(single_bytes, content.to_vec());
+    assert_eq!(
+        ara_yaaaay::page_content_bytes(&split, 1).unwrap(),
+        single_bytes
+    );
+    let text = |doc: &lopdf::Document| chars_text(&ara_yaaaay::positioned_chars(doc, 1).unwrap());
+    assert_eq!(text(&split), "same");
+    assert_eq!(text(&single), "same");
+}
+
+#[test]
+fn missing_or_broken_contents() {
+    use lopdf::Object;
+
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    let with_contents = |contents: Option<Object>| {
+        let mut doc = doc.clone();
+        let page_id = doc.get_pages()[&1];
+        let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+        match contents {
+            Some(contents) => page.set("Contents", contents),
+            None => {
+                page.remove(b"Contents");
+            }
+        }
+        doc
+    };
+    // only a page without /Contents is empty
+    let empty = with_contents(None);
+    assert_eq!(ara_yaaaay::page_content_bytes(&empty, 1).unwrap(), b"");
+    assert!(ara_yaaaay::positioned_chars(&empty, 1).unwrap().is_empty());
+    for broken in [
+        Object::Reference((999, 0)),
+        Object::Integer(1),
+        vec![Object::Reference((999, 0))].into(),
+        vec![Object::Integer(1)].into(),
+    ] {
+        let doc = with_contents(Some(broken.clone()));
+        assert!(
+            ara_yaaaay::page_content_bytes(&doc, 1).is_err(),
+            "{:?}",
+            broken
+        );
+        assert!(
+            ara_yaaaay::positioned_chars(&doc, 1).is_err(),
+            "{:?}",
+            broken
+        );
+    }
+}
+
+fn chars_text(chars: &[ara_yaaaay::output::PositionedChar]) -> String {
+    chars.iter().map(|c| c.char.as_str()).collect()
+}
+
+#[test]
+fn unknown_operator_callback() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let content = b"BT /F1 12 Tf 100 700 Td (hi) Tj ET 1 2 xyz";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut unknown = Vec::new();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        let mut callback = |op: &lopdf::content::Operation| {
+            unknown.push((op.operator.clone(), op.operands.clone()))
+        };
+        ara_yaaaay::output_doc_with_unknown_operator(&doc, &mut output, &mut callback).unwrap();
+    }
+    assert_eq!(s.trim(), "hi");
+    assert_eq!(
+        unknown,
+        vec![(
+            "xyz".to_owned(),
+            vec![lopdf::Object::Integer(1), lopdf::Object::Integer(2)]
+        )]
+    );
+}
+
+#[test]
+fn words_on_two_lines() {
+    let content = b"BT /F1 12 Tf 100 700 Td (hello world) Tj () Tj \
+        0 -14 Td (second) Tj ( ) Tj (line) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let words = ara_yaaaay::extract_words(&doc, 1).unwrap();
+    assert_eq!(words, vec![vec!["hello", "world"], vec!["second", "line"]]);
+}
+
+#[test]
+fn decompose_ligatures_option() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // A is the fi ligature and B is the isolated lam-alef
+    let make_font = |doc: &mut lopdf::Document| mapped_font(doc, &['\u{FB01}', '\u{FEFB}']);
+    let content = b"BT /F1 12 Tf 100 700 Td (A) Tj 20 0 Td (B) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let text = |decompose: bool| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s).decompose_ligatures(decompose);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        s.trim().to_owned()
+    };
+    assert_eq!(text(false), "\u{FB01} \u{FEFB}");
+    assert_eq!(text(true), "fi \u{644}\u{627}");
+}
+
+#[test]
+fn rotated_transparent_watermark_is_removed() {
+    use ara_yaaaay::output::WatermarkFilter;
+    use lopdf::dictionary;
+
+    let content = b"BT /F1 12 Tf 100 700 Td (Hello) Tj ET \
+        q /GS1 gs 0.7071 0.7071 -0.7071 0.7071 200 300 cm \
+        BT /F1 72 Tf 0 0 Td (DRAFT) Tj ET Q \
+        BT /F1 12 Tf 100 686 Td (world) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set(
+        "ExtGState",
+        dictionary! { "GS1" => dictionary! { "Type" => "ExtGState", "ca" => 0.3 } },
+    );
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let draft = chars.iter().find(|c| c.char == "D").unwrap();
+    assert!((draft.rotation - 45.).abs() < 0.01);
+    assert!((draft.alpha - 0.3).abs() < 0.01);
+    assert_eq!(chars.iter().find(|c| c.char == "H").unwrap().alpha, 1.);
+
+    let filter = WatermarkFilter::default();
+    let text = ara_yaaaay::extract_text_without_watermarks(&doc, 1, &filter).unwrap();
+    assert_eq!(text, "Hello\nworld");
+    // the watermark is still there when nothing counts as one
+    let filter = WatermarkFilter {
+        min_signals: 4,
+        ..filter
+    };
+    let text = ara_yaaaay::extract_text_without_watermarks(&doc, 1, &filter).unwrap();
+    // the rotated letters each end up on their own line
+    let letters: String = text.chars().filter(|c| c.is_alphabetic()).collect();
+    assert_eq!(letters, "HelloDRAFTworld");
+}
+
+#[test]
+fn rotate_is_normalized() {
+    let pdf = single_page_pdf(helvetica, b"");
+    let rotate = |value: lopdf::Object| {
+        let mut doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let page_id = doc.get_pages()[&1];
+        let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+        page.set("Rotate", value);
+        ara_yaaaay::page_boxes(&doc, 1).unwrap().rotate
+    };
+    assert_eq!(rotate((-90).into()), 270);
+    assert_eq!(rotate(450.into()), 90);
+    assert_eq!(rotate((-360).into()), 0);
+    // not a multiple of 90
+    assert_eq!(rotate(45.into()), 0);
+}
+
+#[test]
+fn by_pages_results_keeps_going_after_a_bad_page() {
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (one) Tj ET",
+        b"BT /F1 12 Tf 100 650 Td (two) Tj ET",
+        b"BT /F1 12 Tf 100 600 Td (three) Tj ET",
+    ];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    // page 2 gets a MediaBox that's missing a coordinate
+    let page_id = doc.get_pages()[&2];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("MediaBox", vec![0.into(), 0.into(), 612.into()]);
+    let mut pdf = Vec::new();
+    doc.save_to(&mut pdf).unwrap();
+    let results = ara_yaaaay::extract_text_from_mem_by_pages_results(&pdf).unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().trim(), "one");
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap().trim(), "three");
+    // while the old interface stops at the bad page
+    assert_eq!(
+        ara_yaaaay::extract_text_from_mem_by_pages(&pdf)
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn malformed_tj_operand_is_skipped() {
+    let content = b"BT /F1 12 Tf 100 700 Td 5 Tj (still) Tj Tj /Name Tj ( here) Tj ET";
+    let out = ara_yaaaay::extract_text_from_mem(&single_page_pdf(helvetica, content)).unwrap();
+    assert_eq!(out.trim(), "still here");
+}
+
+#[test]
+fn utf8_bom_precedes_content() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (one) Tj ET",
+        b"BT /F1 12 Tf 100 650 Td (two) Tj ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let mut file: Vec<u8> = Vec::new();
+    {
+        let mut output = PlainTextOutput::new(&mut file as &mut dyn std::io::Write).utf8_bom(true);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(&file[..3], b"\xEF\xBB\xBF");
+    let text = String::from_utf8(file[3..].to_vec()).unwrap();
+    // only the start of the output gets one
+    assert!(!text.contains('\u{FEFF}'));
+    assert!(text.contains("one") && text.contains("two"));
+}
+
+#[test]
+fn named_destinations_from_tree_and_dictionary() {
+    use lopdf::{dictionary, Object};
+
+    let pages: &[&[u8]] = &[b"", b"", b""];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let page_ids = doc.get_pages();
+    let dest = |page: u32| -> Object { vec![page_ids[&page].into(), "Fit".into()].into() };
+    // the tree keeps its names in a kid and uses both the array and dictionary forms
+    let leaf = doc.add_object(dictionary! {
+        "Limits" => vec![Object::string_literal("chapter1"), Object::string_literal("chapter2")],
+        "Names" => vec![
+            Object::string_literal("chapter1"),
+            dest(2),
+            Object::string_literal("chapter2"),
+            dictionary! { "D" => dest(3) }.into(),
+        ],
+    });
+    let catalog = doc.catalog_mut().unwrap();
+    catalog.set(
+        "Names",
+        dictionary! { "Dests" => dictionary! { "Kids" => vec![leaf.into()] } },
+    );
+    catalog.set("Dests", dictionary! { "intro" => dest(1) });
+
+    let dests = ara_yaaaay::named_destinations(&doc);
+    assert_eq!(dests.len(), 3);
+    assert_eq!(dests["intro"], 1);
+    assert_eq!(dests["chapter1"], 2);
+    assert_eq!(dests["chapter2"], 3);
+}
+
+#[test]
+fn font_unicode_map_with_partial_to_unicode() {
+    let mut doc = lopdf::Document::with_version("1.5");
+    // the ToUnicode map only covers A and B, the rest comes from the encoding
+    let mut font = mapped_font(&mut doc, &['\u{633}', '\u{644}']);
+    font.set("Encoding", "WinAnsiEncoding");
+    let map = ara_yaaaay::font_unicode_map(&doc, &font).unwrap();
+    assert_eq!(map[&(b'A' as u32)], "\u{633}");
+    assert_eq!(map[&(b'B' as u32)], "\u{644}");
+    assert_eq!(map[&(b'C' as u32)], "C");
+    assert_eq!(map[&0x80], "\u{20ac}");
+
+    // a CID font has nothing but its ToUnicode map
+    let mut font = cid_font(&mut doc, lopdf::dictionary! {});
+    let map = ara_yaaaay::font_unicode_map(&doc, &font).unwrap();
+    assert_eq!(map.len(), 3);
+    assert_eq!(map[&2], "B");
+    font.remove(b"ToUnicode");
+    assert_eq!(ara_yaaaay::font_unicode_map(&doc, &font), None);
+}
+
+#[test]
+fn form_uses_colorspace_from_page_resources() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"/Fm0 Do")).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_dictionary(page_id).unwrap();
+    let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+    let fonts = resources.get(b"Font").unwrap().clone();
+    // the form has its own resources but CS0 is only defined by the page
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => dictionary! { "Font" => fonts },
+        },
+        b"/CS0 cs 1 0 0 sc BT /F1 12 Tf 100 700 Td (Hi) Tj ET".to_vec(),
+    ));
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => "DeviceRGB" });
+    resources.set("XObject", dictionary! { "Fm0" => Object::Reference(form) });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "Hi");
+    assert!(chars.iter().all(|c| c.color == [1., 0., 0.]));
+}
+
+#[test]
+fn buffered_output_matches_unbuffered() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // lets us look at what has reached the writer while the BufWriter is still alive
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (first line) Tj 0 -14 Td (second line) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (page two) Tj ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let mut unbuffered = String::new();
+    ara_yaaaay::output_doc(&doc, &mut PlainTextOutput::new(&mut unbuffered)).unwrap();
+    assert!(unbuffered.contains("second line"));
+
+    let shared = Shared::default();
+    let writer = std::io::BufWriter::with_capacity(1 << 20, shared.clone());
+    let mut output = PlainTextOutput::new(writer);
+    ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    // the end of the last page flushed everything even though the buffer is far from full
+    let bytes = shared.0.borrow().clone();
+    assert_eq!(String::from_utf8(bytes).unwrap(), unbuffered);
+}
+
+#[test]
+fn unbalanced_q_is_capped() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let mut content = b"q ".repeat(ara_yaaaay::DEFAULT_MAX_GS_DEPTH + 1);
+    content.extend_from_slice(b"BT /F1 12 Tf 100 700 Td (deep) Tj ET");
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, &content)).unwrap();
+    let mut text = String::new();
+    let result = ara_yaaaay::output_doc(&doc, &mut PlainTextOutput::new(&mut text));
+    assert!(matches!(
+        result,
+        Err(ara_yaaaay::OutputError::PdfError(lopdf::Error::Invalid(_)))
+    ));
+
+    // a lower cap triggers sooner, and stray Qs are only warned about
+    let content = b"Q Q q q q BT /F1 12 Tf 100 700 Td (deep) Tj ET Q Q Q Q";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut text = String::new();
+    let mut output = PlainTextOutput::new(&mut text);
+    assert!(ara_yaaaay::output_doc_with_max_gs_depth(&doc, &mut output, 2).is_err());
+    let mut text = String::new();
+    let mut output = PlainTextOutput::new(&mut text);
+    ara_yaaaay::output_doc_with_max_gs_depth(&doc, &mut output, 3).unwrap();
+    assert_eq!(text.trim(), "deep");
+}
+
+#[test]
+fn preview_stops_mid_page() {
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (first line) Tj 0 -14 Td (second line) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (page two) Tj ET",
+    ];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    // processing page 2 would fail, so getting a preview shows we never got there
+    let page_id = doc.get_pages()[&2];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("MediaBox", vec![0.into(), 0.into(), 612.into()]);
+
+    assert_eq!(ara_yaaaay::extract_preview(&doc, 5).unwrap(), "first");
+    // whitespace doesn't count
+    assert_eq!(
+        ara_yaaaay::extract_preview(&doc, 11).unwrap(),
+        "first line\nse"
+    );
+    assert!(ara_yaaaay::extract_preview(&doc, 100).is_err());
+}
+
+#[test]
+fn calrgb_with_indirect_parameters() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"/CS0 cs 1 0 0 sc BT /F1 12 Tf 100 700 Td (Hi) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let params = doc.add_object(dictionary! {
+        "WhitePoint" => vec![0.9505.into(), 1.into(), 1.089.into()],
+    });
+    let calrgb = doc.add_object(Object::Name(b"CalRGB".to_vec()));
+    let cs = doc.add_object(vec![Object::Reference(calrgb), Object::Reference(params)]);
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => cs });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "Hi");
+    assert!(chars.iter().all(|c| c.color == [1., 0., 0.]));
+}
+
+#[test]
+fn text_by_structure_element() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"/H1 <</MCID 0>> BDC BT /F1 12 Tf 100 700 Td (Introduction) Tj ET EMC \
+        /P <</MCID 1>> BDC BT /F1 12 Tf 100 680 Td (Some text) Tj ET EMC \
+        BT /F1 12 Tf 100 100 Td (footer) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let heading = doc.add_object(dictionary! { "S" => "H1", "K" => 0 });
+    // a custom type given through the role map and a marked-content reference
+    let para = doc.add_object(dictionary! {
+        "S" => "Para",
+        "K" => dictionary! { "Type" => "MCR", "MCID" => 1 },
+    });
+    let document = doc.add_object(dictionary! {
+        "S" => "Document",
+        "Pg" => page_id,
+        "K" => vec![Object::Reference(heading), Object::Reference(para)],
+    });
+    let root = doc.add_object(dictionary! {
+        "Type" => "StructTreeRoot",
+        "K" => document,
+        "RoleMap" => dictionary! { "Para" => "P" },
+    });
+    doc.catalog_mut().unwrap().set("StructTreeRoot", root);
+
+    let elements = ara_yaaaay::extract_by_structure(&doc).unwrap();
+    let elements: Vec<_> = elements
+        .iter()
+        .map(|(s, text)| (s.as_str(), text.as_str()))
+        .collect();
+    assert_eq!(
+        elements,
+        vec![
+            ("H1", "Introduction"),
+            ("P", "Some text"),
+            ("Content", "footer")
+        ]
+    );
+}
+
+#[test]
+fn text_of_structure_role() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"/H1 <</MCID 0>> BDC BT /F1 12 Tf 100 700 Td (Title) Tj ET EMC \
+        /H2 <</MCID 1>> BDC BT /F1 12 Tf 100 680 Td (Background) Tj ET EMC \
+        /P <</MCID 2>> BDC BT /F1 12 Tf 100 660 Td (Some text) Tj ET EMC \
+        /H2 <</MCID 3>> BDC BT /F1 12 Tf 100 640 Td (Results) Tj ET EMC";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let title = doc.add_object(dictionary! { "S" => "H1", "K" => 0 });
+    let background = doc.add_object(dictionary! { "S" => "H2", "K" => 1 });
+    let para = doc.add_object(dictionary! { "S" => "P", "K" => 2 });
+    // a custom type that the role map turns into an H2
+    let results = doc.add_object(dictionary! { "S" => "Subhead", "K" => 3 });
+    let document = doc.add_object(dictionary! {
+        "S" => "Document",
+        "Pg" => page_id,
+        "K" => vec![
+            Object::Reference(title),
+            Object::Reference(background),
+            Object::Reference(para),
+            Object::Reference(results),
+        ],
+    });
+    let root = doc.add_object(dictionary! {
+        "Type" => "StructTreeRoot",
+        "K" => document,
+        "RoleMap" => dictionary! { "Subhead" => "H2" },
+    });
+    doc.catalog_mut().unwrap().set("StructTreeRoot", root);
+
+    assert_eq!(
+        ara_yaaaay::extract_structure_role(&doc, "H2").unwrap(),
+        vec!["Background", "Results"]
+    );
+    assert_eq!(
+        ara_yaaaay::extract_structure_role(&doc, "Subhead").unwrap(),
+        vec!["Results"]
+    );
+    // the text of an element includes that of its descendants
+    assert_eq!(
+        ara_yaaaay::extract_structure_role(&doc, "Document")
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn mac_roman_matches_unicode_mapping() {
+    use lopdf::dictionary;
+
+    // Apple's ROMAN.TXT for 0x80 to 0xFF, except that PDF's MacRomanEncoding encodes 0xCA as
+    // space instead of no-break space and keeps the currency sign at 0xDB where Mac OS 8.5
+    // put the euro
+    #[rustfmt::skip]
+    let high: [u32; 128] = [
+        // 0x80
+        0x00C4, 0x00C5, 0x00C7, 0x00C9, 0x00D1, 0x00D6, 0x00DC, 0x00E1,
+        0x00E0, 0x00E2, 0x00E4, 0x00E3, 0x00E5, 0x00E7, 0x00E9, 0x00E8,
+        // 0x90
+        0x00EA, 0x00EB, 0x00ED, 0x00EC, 0x00EE, 0x00EF, 0x00F1, 0x00F3,
+        0x00F2, 0x00F4, 0x00F6, 0x00F5, 0x00FA, 0x00F9, 0x00FB, 0x00FC,
+        // 0xA0
+        0x2020, 0x00B0, 0x00A2, 0x00A3, 0x00A7, 0x2022, 0x00B6, 0x00DF,
+        0x00AE, 0x00A9, 0x2122, 0x00B4, 0x00A8, 0x2260, 0x00C6, 0x00D8,
+        // 0xB0
+        0x221E, 0x00B1, 0x2264, 0x2265, 0x00A5, 0x00B5, 0x2202, 0x2211,
+        0x220F, 0x03C0, 0x222B, 0x00AA, 0x00BA, 0x03A9, 0x00E6, 0x00F8,
+        // 0xC0
+        0x00BF, 0x00A1, 0x00AC, 0x221A, 0x0192, 0x2248, 0x2206, 0x00AB,
+        0x00BB, 0x2026, 0x0020, 0x00C0, 0x00C3, 0x00D5, 0x0152, 0x0153,
+        // 0xD0
+        0x2013, 0x2014, 0x201C, 0x201D, 0x2018, 0x2019, 0x00F7, 0x25CA,
+        0x00FF, 0x0178, 0x2044, 0x00A4, 0x2039, 0x203A, 0xFB01, 0xFB02,
+        // 0xE0
+        0x2021, 0x00B7, 0x201A, 0x201E, 0x2030, 0x00C2, 0x00CA, 0x00C1,
+        0x00CB, 0x00C8, 0x00CD, 0x00CE, 0x00CF, 0x00CC, 0x00D3, 0x00D4,
+        // 0xF0
+        0xF8FF, 0x00D2, 0x00DA, 0x00DB, 0x00D9, 0x0131, 0x02C6, 0x02DC,
+        0x00AF, 0x02D8, 0x02D9, 0x02DA, 0x00B8, 0x02DD, 0x02DB, 0x02C7,
+    ];
+    let doc = lopdf::Document::with_version("1.5");
+    let font = dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Times-Roman",
+        "Encoding" => "MacRomanEncoding",
+    };
+    let map = ara_yaaaay::font_unicode_map(&doc, &font).unwrap();
+    for code in 0..=255u32 {
+        let expected = match code {
+            0x20..=0x7E => char::from_u32(code),
+            0x80..=0xFF => char::from_u32(high[code as usize - 0x80]),
+            _ => None,
+        };
+        let actual = map.get(&code).map(|s| s.chars().collect::<Vec<_>>());
+        assert_eq!(actual, expected.map(|c| vec![c]), "code {:#04x}", code);
+    }
+}
+
+#[test]
+fn lines_with_bounding_boxes() {
+    let content = b"BT /F1 12 Tf 100 700 Td (first line) Tj 0 -14 Td (second) Tj ( line) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let lines = ara_yaaaay::extract_lines(&doc, 1).unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].text, "first line");
+    assert_eq!(lines[1].text, "second line");
+    assert_eq!(lines[0].baseline_y, 700.);
+    assert_eq!(lines[1].baseline_y, 686.);
+    assert_eq!(lines[0].bbox[0], 100.);
+    // the first line is above the second one without overlapping it
+    assert!(lines[0].bbox[1] >= lines[1].bbox[3]);
+}
+
+#[test]
+fn cid_without_widths_uses_default_width() {
+    use lopdf::dictionary;
+
+    let content = b"BT /F1 10 Tf 100 700 Td <00010002> Tj ET";
+    let make_font = |doc: &mut lopdf::Document| cid_font(doc, dictionary! {});
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let widths: Vec<_> = chars.iter().map(|c| c.width).collect();
+    // there's no /W or /DW so every glyph is 1000 units wide
+    assert_eq!(widths, vec![10., 10.]);
+}
+
+#[test]
+fn separation_with_icc_based_alternate() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"/CS0 cs 1 scn BT /F1 12 Tf 100 700 Td (a) Tj ET \
+        0.5 scn BT /F1 12 Tf 110 700 Td (b) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    // the profile itself isn't looked at, only its number of components
+    let icc = doc.add_object(Stream::new(dictionary! { "N" => 3 }, b"profile".to_vec()));
+    let tint_transform = dictionary! {
+        "FunctionType" => 2,
+        "Domain" => vec![0.into(), 1.into()],
+        "C0" => vec![1.into(), 1.into(), 1.into()],
+        "C1" => vec![1.into(), 0.into(), 0.into()],
+        "N" => 1,
+    };
+    let separation: Vec<Object> = vec![
+        "Separation".into(),
+        "Spot Red".into(),
+        vec!["ICCBased".into(), icc.into()].into(),
+        tint_transform.into(),
+    ];
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => separation });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    assert_eq!(colors, vec![[1., 0., 0.], [1., 0.5, 0.5]]);
+}
+
+#[test]
+fn markdown_front_matter_and_page_markers() {
+    use ara_yaaaay::output::MarkdownOutput;
+    use lopdf::{dictionary, Object};
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (# not a heading) Tj 0 -14 Td (same paragraph) Tj \
+            0 -40 Td (next paragraph) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (page two) Tj ET",
+    ];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let info = doc.add_object(dictionary! {
+        "Title" => Object::string_literal("A \"quoted\" title"),
+        "Author" => Object::string_literal("Someone"),
+    });
+    doc.trailer.set("Info", info);
+
+    let mut md = Vec::new();
+    {
+        let mut output = MarkdownOutput::new(&mut md)
+            .front_matter(&doc)
+            .page_markers(true);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let md = String::from_utf8(md).unwrap();
+    assert_eq!(
+        md,
+        "---\ntitle: \"A \\\"quoted\\\" title\"\nauthor: \"Someone\"\npages: 2\n---\n\n\
+        <!-- page 1 -->\n\n\\# not a heading\nsame paragraph\n\nnext paragraph\n\n\
+        <!-- page 2 -->\n\npage two\n\n"
+    );
+}
+
+#[test]
+fn corrupt_to_unicode_falls_back_to_encoding() {
+    use lopdf::{dictionary, Stream};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        // a bfchar destination has to be a hex string
+        let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+            1 begincodespacerange <00> <FF> endcodespacerange \
+            1 beginbfchar <41> /Oops endbfchar endcmap end end";
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => "WinAnsiEncoding",
+            "ToUnicode" => doc.add_object(Stream::new(dictionary! {}, cmap.to_vec())),
+        }
+    };
+    let content = b"BT /F1 12 Tf 100 700 Td (Hello) Tj ET";
+    let out = ara_yaaaay::extract_text_from_mem(&single_page_pdf(make_font, content)).unwrap();
+    assert_eq!(out.trim(), "Hello");
+}
+
+#[test]
+fn corrupt_encoding_cmap_falls_back_to_identity() {
+    use lopdf::{dictionary, Stream};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        // the CID offset of a cidrange has to be an integer
+        let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+            1 begincodespacerange <0000> <FFFF> endcodespacerange \
+            1 begincidrange <0000> <FFFF> /Oops endcidrange endcmap end end";
+        let mut font = cid_font(doc, dictionary! {});
+        let encoding = doc.add_object(Stream::new(dictionary! {}, cmap.to_vec()));
+        font.set("Encoding", encoding);
+        font
+    };
+    let content = b"BT /F1 10 Tf 100 700 Td <000100020003> Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "ABC");
+}
+
+#[test]
+fn chars_iterator_matches_plain_text() {
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (first page) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (second) Tj 0 -14 Td (page) Tj ET",
+    ];
+    let pdf = pdf_with_pages(helvetica, pages);
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let chars: Vec<_> = ara_yaaaay::chars(&doc).collect::<Result<_, _>>().unwrap();
+    let text: String = chars.iter().map(|c| c.text.as_str()).collect();
+    let expected = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    let without_whitespace = |s: &str| s.split_whitespace().collect::<String>();
+    assert_eq!(without_whitespace(&text), without_whitespace(&expected));
+    assert_eq!(chars[0].page, 1);
+    assert_eq!(chars.last().unwrap().page, 2);
+    assert_eq!(chars[0].bbox[0], 100.);
+    // stopping early doesn't need the rest of the document
+    assert_eq!(ara_yaaaay::chars(&doc).take(5).count(), 5);
+}
+
+#[test]
+fn svg_of_rotated_page() {
+    use ara_yaaaay::output::SVGOutput;
+
+    let content = b"0 0 m 100 0 l 100 50 l f";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Rotate", 90);
+    let mut svg = Vec::new();
+    ara_yaaaay::output_doc(&doc, &mut SVGOutput::new(&mut svg)).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    // the page is 612 wide and 792 high before it's turned
+    assert!(svg.contains("<svg width=\"792\" height=\"612\""));
+    assert!(svg.contains("viewBox='0 0 792 612'"));
+    // the bottom left corner of the page ends up at the top left
+    assert!(svg.contains("<g transform='matrix(0, 1, 1, 0, 0, 0)'>"));
+}
+
+#[test]
+fn non_embedded_font_uses_substitute_metrics() {
+    use ara_yaaaay::output::PositionedOutput;
+    use ara_yaaaay::{FontSubstitutes, OutputOptions};
+    use lopdf::dictionary;
+
+    let arial = |_: &mut lopdf::Document| {
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Arial",
+            "Encoding" => "WinAnsiEncoding",
+        }
+    };
+    let pdf = single_page_pdf(arial, b"BT /F1 10 Tf 100 700 Td (Hi) Tj ET");
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let chars: Vec<_> = ara_yaaaay::chars(&doc).collect::<Result<_, _>>().unwrap();
+    let widths: Vec<f64> = chars.iter().map(|c| c.bbox[2] - c.bbox[0]).collect();
+    // Helvetica's H and i
+    assert!((widths[0] - 7.22).abs() < 1e-6, "{:?}", widths);
+    assert!((widths[1] - 2.22).abs() < 1e-6, "{:?}", widths);
+
+    let mut substitutes = FontSubstitutes::new();
+    assert!(!substitutes.insert("Typewriter", "Comic Sans"));
+    assert!(substitutes.insert("Typewriter", "Courier"));
+    let typewriter = |_: &mut lopdf::Document| {
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "ABCDEF+Typewriter",
+        }
+    };
+    let pdf = single_page_pdf(typewriter, b"BT /F1 10 Tf 100 700 Td (Hi) Tj ET");
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let widths = |options: OutputOptions| {
+        let mut output = PositionedOutput::new();
+        ara_yaaaay::output_doc_with_options(&doc, &mut output, options).unwrap();
+        output.chars.iter().map(|c| c.width).collect::<Vec<_>>()
+    };
+    let courier = widths(OutputOptions::new().font_substitutes(&substitutes));
+    assert!(
+        courier.iter().all(|w| (w - 6.).abs() < 1e-6),
+        "{:?}",
+        courier
+    );
+    // the substitutes only apply where they're given
+    let helvetica = widths(OutputOptions::new());
+    assert!((helvetica[0] - 7.22).abs() < 1e-6, "{:?}", helvetica);
+}
+
+#[test]
+fn form_text_outside_bbox_is_clipped() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"q 1 0 0 1 100 500 cm /Fm0 Do Q";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Matrix" => vec![1.into(), 0.into(), 0.into(), 1.into(), 50.into(), 0.into()],
+        },
+        b"BT /F1 12 Tf 10 10 Td (in) Tj 200 0 Td (out) Tj ET".to_vec(),
+    ));
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("XObject", dictionary! { "Fm0" => Object::Reference(form) });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "in");
+    // the page's CTM and the form's /Matrix both apply
+    assert_eq!(chars[0].x, 160.);
+}
+
+#[test]
+fn text_in_rects_across_pages() {
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (skipped) Tj 0 -100 Td (end of one) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (start of two) Tj 0 -100 Td (skipped) Tj ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    // the rects don't need to be in page order
+    let rects = [(2, [90., 690., 300., 720.]), (1, [90., 590., 300., 620.])];
+    let text = ara_yaaaay::extract_text_in_rects(&doc, &rects).unwrap();
+    assert_eq!(text, "end of one\nstart of two");
+}
+
+#[test]
+fn horizontally_scaled_text_spacing() {
+    // the kern between the words is a small space once the text is condensed to half its width
+    let content = b"BT /F1 12 Tf 50 Tz 100 700 Td [(hello) -300 (world)] TJ ET";
+    let pdf = single_page_pdf(helvetica, content);
+    assert_eq!(
+        ara_yaaaay::extract_text_from_mem(&pdf).unwrap().trim(),
+        "hello world"
+    );
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    // Helvetica's h is 556 units wide
+    assert!((chars[0].width - 0.556 * 12. * 0.5).abs() < 1e-9);
+    assert_eq!(chars[0].font_size, 12.);
+    assert_eq!(ara_yaaaay::output::chars_to_text(&chars), "hello world");
+}
+
+#[test]
+fn stream_text_without_a_page() {
+    let content = b"BT /F1 12 Tf 100 700 Td (from a stream) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page = doc.get_dictionary(doc.get_pages()[&1]).unwrap();
+    let stream_id = page.get(b"Contents").unwrap().as_reference().unwrap();
+    let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+    let media_box = ara_yaaaay::MediaBox {
+        llx: 0.,
+        lly: 0.,
+        urx: 612.,
+        ury: 792.,
+    };
+    let text = ara_yaaaay::extract_stream_text(&doc, stream_id, resources, media_box).unwrap();
+    assert_eq!(text.trim(), "from a stream");
+}
+
+#[test]
+fn indentation_from_tab_stops() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let content = b"BT /F1 12 Tf 72 700 Td (one) Tj 18 -14 Td (two) Tj 18 -14 Td (three) Tj \
+        -36 -14 Td (four) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    for group_baselines in [false, true] {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s)
+                .group_baselines(group_baselines)
+                .infer_indentation(18., 2);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        assert_eq!(s.trim_matches('\n'), "one\n  two\n    three\nfour");
+    }
+}
+
+#[test]
+fn indentation_of_right_to_left_lines() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // the glyphs are drawn from right to left and the lines end at 300, 282 and 264, so
+    // they're indented from the right margin
+    let content =
+        b"BT /F1 12 Tf 294 700 Td (A) Tj -6 0 Td (B) Tj -12 -20 Td (C) Tj -6 0 Td (D) Tj \
+        -12 -20 Td (A) Tj -6 0 Td (B) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(arabic_font, content)).unwrap();
+    for group_baselines in [false, true] {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s)
+                .group_baselines(group_baselines)
+                .infer_indentation(18., 2);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        assert_eq!(
+            s.trim_matches('\n'),
+            "\u{633}\u{644}\n  \u{639}\u{64a}\n    \u{633}\u{644}"
+        );
+    }
+}
+
+#[test]
+fn actual_text_replaces_glyphs() {
+    let content = b"BT /F1 12 Tf 100 700 Td /Span << /ActualText (fi) >> BDC (AB) Tj EMC \
+        (C) Tj /Span << /ActualText <FEFF0066006C> >> BDC [(A) -20 (B)] TJ EMC ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(
+        |doc| mapped_font(doc, &['#', '%', 'x']),
+        content,
+    ))
+    .unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "fixfl");
+    assert_eq!(chars[0].x, 100.);
+}
+
+#[test]
+fn actual_text_goes_to_the_first_glyph_drawn() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"q 1 0 0 1 100 700 cm /Fm Do Q";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(
+        |doc| mapped_font(doc, &['#', '%']),
+        content,
+    ))
+    .unwrap();
+    let page_id = doc.get_pages()[&1];
+    let resources = doc
+        .get_dictionary(page_id)
+        .unwrap()
+        .get(b"Resources")
+        .unwrap()
+        .clone();
+    // the first two glyphs start to the left of the form's BBox and are clipped away
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+            "Resources" => resources,
+        },
+        b"BT /F1 12 Tf -10 5 Td /Span << /ActualText (fi) >> BDC (ABA) Tj EMC ET".to_vec(),
+    ));
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("XObject", dictionary! { "Fm" => Object::Reference(form) });
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "fi");
+}
+
+#[test]
+fn marked_content_around_forms() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"/P << /MCID 0 >> BDC BT /F1 12 Tf 100 700 Td (A) Tj ET /Fm Do \
+        BT /F1 12 Tf 100 600 Td (C) Tj ET EMC";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let resources = doc
+        .get_dictionary(page_id)
+        .unwrap()
+        .get(b"Resources")
+        .unwrap()
+        .clone();
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => resources,
+        },
+        b"/Span << /MCID 5 >> BDC BT /F1 12 Tf 100 680 Td (B) Tj ET EMC \
+            BT /F1 12 Tf 100 660 Td (D) Tj ET"
+            .to_vec(),
+    ));
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("XObject", dictionary! { "Fm" => Object::Reference(form) });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let mcids: Vec<_> = chars.iter().map(|c| (c.char.as_str(), c.mcid)).collect();
+    // the form is drawn inside of sequence 0, which carries on after the form's own sequence
+    // and after the form
+    assert_eq!(
+        mcids,
+        [
+            ("A", Some(0)),
+            ("B", Some(5)),
+            ("D", Some(0)),
+            ("C", Some(0))
+        ]
+    );
+}
+
+#[test]
+fn form_fields_in_tab_order() {
+    use lopdf::{dictionary, Object};
+
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    let parent = doc.add_object(dictionary! { "T" => Object::string_literal("address") });
+    // a 2x2 grid listed column by column, with the right column slightly lower
+    let mut annots = Vec::new();
+    for (name, x, y) in [
+        ("a", 100, 700),
+        ("c", 100, 650),
+        ("b", 300, 695),
+        ("d", 300, 645),
+    ] {
+        annots.push(Object::Reference(doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal(name),
+            "V" => Object::string_literal(name.to_uppercase()),
+            "Parent" => parent,
+            "Rect" => vec![x.into(), y.into(), (x + 150).into(), (y + 20).into()],
+        })));
+    }
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Annots", annots);
+    page.set("Tabs", "R");
+    let names = |doc: &lopdf::Document| -> Vec<String> {
+        let fields = ara_yaaaay::form_fields(doc, 1).unwrap();
+        fields.into_iter().map(|f| f.name).collect()
+    };
+    assert_eq!(
+        names(&doc),
+        ["address.a", "address.b", "address.c", "address.d"]
+    );
+    let fields = ara_yaaaay::form_fields(&doc, 1).unwrap();
+    assert_eq!(fields[1].value.as_deref(), Some("B"));
+
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Tabs", "C");
+    assert_eq!(
+        names(&doc),
+        ["address.a", "address.c", "address.b", "address.d"]
+    );
+}
+
+#[test]
+fn filled_form_as_flat_text() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"BT /F1 12 Tf 72 700 Td (Application) Tj ET \
+        BT /F1 10 Tf 72 660 Td (Name:) Tj ET \
+        BT /F1 10 Tf 72 630 Td (City:) Tj ET \
+        BT /F1 10 Tf 72 600 Td (Notes:) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    // the widgets sit slightly above and below the baselines of their labels, and the empty one
+    // leaves no text. The unlabelled row at the bottom has its rects given from the upper right
+    // corner.
+    let mut annots = Vec::new();
+    for (name, value, rect) in [
+        ("city", "Cairo", [150, 625, 350, 641]),
+        ("name", "Muhamad Tarek", [150, 656, 350, 672]),
+        ("notes", "", [150, 597, 350, 613]),
+        ("zip", "11511", [140, 576, 72, 560]),
+        ("country", "Egypt", [350, 576, 150, 560]),
+    ] {
+        annots.push(Object::Reference(doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal(name),
+            "V" => Object::string_literal(value),
+            "Rect" => rect.map(Object::from).to_vec(),
+        })));
+    }
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Annots", annots);
+
+    assert_eq!(
+        ara_yaaaay::flatten_forms_text(&doc).unwrap(),
+        "Application\nName: Muhamad Tarek\nCity: Cairo\nNotes:\n11511 Egypt"
+    );
+}
+
+#[test]
+fn rotated_text_modes() {
+    use ara_yaaaay::output::{PlainTextOutput, RotatedText};
+
+    let content = b"BT /F1 12 Tf 100 700 Td (body) Tj ET \
+        BT /F1 8 Tf 0 1 -1 0 50 600 Tm (side label) Tj ET \
+        BT /F1 12 Tf 100 680 Td (text) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let text = |mode| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s).rotated_text(mode);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        s.split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+    assert_eq!(text(RotatedText::Include), "body side label text");
+    assert_eq!(text(RotatedText::Skip), "body text");
+    assert_eq!(text(RotatedText::Separate), "body text side label");
+}
+
+#[test]
+fn grid_line_segments() {
+    // a 2x2 grid of stroked lines, a cell border drawn with re and a curve that is skipped
+    let content = b"1 0 0 1 100 500 cm 0 0 m 200 0 l 0 100 m 200 100 l \
+        0 0 m 0 100 l 200 0 m 200 100 l S 10 10 20 20 re f 0 0 m 50 50 50 50 100 0 c S";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let segments = ara_yaaaay::extract_lines_geometry(&doc, 1).unwrap();
+    assert_eq!(segments.len(), 8);
+    assert_eq!(segments.iter().filter(|s| s.is_horizontal()).count(), 4);
+    assert_eq!(segments.iter().filter(|s| s.is_vertical()).count(), 4);
+    let first = segments[0];
+    assert_eq!(
+        (first.x0, first.y0, first.x1, first.y1),
+        (100., 500., 300., 500.)
+    );
+    let re = segments[4];
+    assert_eq!((re.x0, re.y0, re.x1, re.y1), (110., 510., 130., 510.));
+}
+
+// Collects the images drawn
+#[derive(Default)]
+struct Images(Vec<(ara_yaaaay::Transform, lopdf::Stream)>);
+impl ara_yaaaay::output::OutputDev for Images {
+    fn begin_page(
+        &mut self,
+        _: u32,
+        _: &ara_yaaaay::MediaBox,
+        _: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn end_page(&mut self) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn output_character(
+        &mut self,
+        _: &ara_yaaaay::Transform,
+        _: f64,
+        _: f64,
+        _: f64,
+        _: &str,
+    ) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn begin_word(&mut self) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn end_word(&mut self) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn end_line(&mut self) -> Result<(), ara_yaaaay::OutputError> {
+        Ok(())
+    }
+    fn image(
+        &mut self,
+        ctm: &ara_yaaaay::Transform,
+        image: &lopdf::Stream,
+    ) -> Result<(), ara_yaaaay::OutputError> {
+        self.0.push((*ctm, image.clone()));
+        Ok(())
+    }
+}
+
+#[test]
+fn text_after_an_unterminated_inline_image() {
+    let content = b"BT /F1 12 Tf 100 700 Td (before) Tj ET BI /W 1 /H 1 /CS /G /BPC 8 ID 00 \
+        BT /F1 12 Tf 100 600 Td (after) Tj ET";
+    let pdf = single_page_pdf(helvetica, content);
+    let text = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    assert_eq!(
+        text.split_whitespace().collect::<Vec<_>>(),
+        ["before", "after"]
+    );
+}
+
+#[test]
+fn inline_image_keys_are_expanded() {
+    let content = b"BT /F1 12 Tf 100 700 Td (a BI b) Tj ET q 20 0 0 10 50 60 cm \
+        BI /W 2 /H 2 /CS /G /BPC 8 /F [/AHx /RL] /IM false /I true /D [1 0] ID 00FF00FF> EI Q \
+        BT /F1 12 Tf 100 600 Td (after) Tj ET";
+    let pdf = single_page_pdf(helvetica, content);
+    let text = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    assert_eq!(
+        text.split_whitespace().collect::<Vec<_>>(),
+        ["a", "BI", "b", "after"]
+    );
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let mut images = Images::default();
+    ara_yaaaay::output_doc(&doc, &mut images).unwrap();
+    assert_eq!(images.0.len(), 1);
+    let (ctm, image) = &images.0[0];
+    assert_eq!((ctm.m11, ctm.m22, ctm.m31, ctm.m32), (20., 10., 50., 60.));
+    let dict = &image.dict;
+    assert_eq!(dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Image");
+    assert_eq!(dict.get(b"Width").unwrap().as_i64().unwrap(), 2);
+    assert_eq!(dict.get(b"Height").unwrap().as_i64().unwrap(), 2);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 8);
+    assert_eq!(
+        dict.get(b"ColorSpace").unwrap().as_name().unwrap(),
+        b"DeviceGray"
+    );
+    let filters: Vec<_> = dict
+        .get(b"Filter")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f.as_name().unwrap())
+        .collect();
+    assert_eq!(filters, [&b"ASCIIHexDecode"[..], b"RunLengthDecode"]);
+    assert!(!dict.get(b"ImageMask").unwrap().as_bool().unwrap());
+    assert!(dict.get(b"Interpolate").unwrap().as_bool().unwrap());
+    assert!(dict.has(b"Decode"));
+    assert_eq!(image.content, b"00FF00FF>");
+}
+
+#[test]
+fn pages_in_reverse() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (one) Tj ET",
+        b"BT /F1 12 Tf 100 650 Td (two) Tj ET",
+        b"BT /F1 12 Tf 100 600 Td (three) Tj ET",
+    ];
+    let pdf = pdf_with_pages(helvetica, pages);
+    let by_page = ara_yaaaay::extract_text_from_mem_by_pages_reversed(&pdf).unwrap();
+    let by_page: Vec<_> = by_page.iter().map(|p| p.trim()).collect();
+    assert_eq!(by_page, ["three", "two", "one"]);
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        ara_yaaaay::output_doc_reversed(&doc, &mut output).unwrap();
+    }
+    assert_eq!(
+        s.split_whitespace().collect::<Vec<_>>(),
+        ["three", "two", "one"]
+    );
+}
+
+#[test]
+fn html_marks_bold_fonts() {
+    use ara_yaaaay::output::HTMLOutput;
+    use lopdf::dictionary;
+
+    let html = |base_font: &'static str| {
+        let font = move |_: &mut lopdf::Document| {
+            dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => base_font,
+            }
+        };
+        let content = b"BT /F1 12 Tf 100 700 Td (word) Tj ET";
+        let doc = lopdf::Document::load_mem(&single_page_pdf(font, content)).unwrap();
+        let mut html = Vec::new();
+        ara_yaaaay::output_doc(&doc, &mut HTMLOutput::new(&mut html)).unwrap();
+        String::from_utf8(html).unwrap()
+    };
+    let bold = html("Helvetica-Bold");
+    assert!(bold.contains("<b>word</b>"), "{}", bold);
+    assert!(!bold.contains("<i>"));
+    assert!(html("Helvetica-BoldOblique").contains("<b><i>word</i></b>"));
+    assert!(!html("Helvetica").contains("<b>"));
+}
+
+#[test]
+fn page_in_two_pieces() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let content = b"BT /F1 12 Tf 100 700 Td (first) Tj 0 -14 Td (second) Tj \
+        0 -14 Td (third) Tj 0 -14 Td (fourth) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut full = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut full);
+        ara_yaaaay::output_doc_page(&doc, &mut output, 1).unwrap();
+    }
+    let mut pieces = String::new();
+    {
+        // the same device carries on with the second piece
+        let mut output = PlainTextOutput::new(&mut pieces);
+        let next = ara_yaaaay::output_doc_page_operations(&doc, &mut output, 1, 0, 6).unwrap();
+        assert_eq!(next, Some(6));
+        let next = ara_yaaaay::output_doc_page_operations(&doc, &mut output, 1, 6, 6).unwrap();
+        assert_eq!(next, None);
+    }
+    assert_eq!(pieces, full);
+}
+
+#[test]
+fn page_through_a_resumable_handle() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    // the font, text matrix and saved state are set up in the first piece and used by later ones
+    let content = b"q BT /F1 12 Tf 100 700 Td (first) Tj 0 -14 Td (second) Tj ET Q \
+        BT /F1 12 Tf 100 600 Td (third) Tj 0 -14 Td (fourth) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut full = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut full);
+        ara_yaaaay::output_doc_page(&doc, &mut output, 1).unwrap();
+    }
+    let mut pieces = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut pieces);
+        let mut page = ara_yaaaay::PageOperations::new(&doc, 1).unwrap();
+        let mut calls = 0;
+        while page.output_next(&mut output, 3).unwrap().is_some() {
+            calls += 1;
+        }
+        assert_eq!(calls, (page.operation_count() - 1) / 3);
+        assert_eq!(page.position(), page.operation_count());
+    }
+    assert_eq!(pieces, full);
+}
+
+#[test]
+fn primary_script_ties_go_to_the_first_script() {
+    use ara_yaaaay::Script;
+
+    // A and B are Arabic letters and C and D are Latin ones
+    let font = |doc: &mut lopdf::Document| mapped_font(doc, &['\u{633}', '\u{644}', 'P', 'D']);
+    for (content, expected) in [
+        (
+            &b"BT /F1 12 Tf 300 700 Td (AB) Tj 0 -14 Td (CD) Tj ET"[..],
+            Script::Arabic,
+        ),
+        (
+            &b"BT /F1 12 Tf 300 700 Td (CD) Tj 0 -14 Td (AB) Tj ET"[..],
+            Script::Latin,
+        ),
+    ] {
+        let doc = lopdf::Document::load_mem(&single_page_pdf(font, content)).unwrap();
+        for _ in 0..10 {
+            assert_eq!(ara_yaaaay::detect_primary_script(&doc).unwrap(), expected);
+        }
+    }
+}
+
+#[test]
+fn primary_script_of_arabic_document() {
+    use ara_yaaaay::Script;
+
+    // A, B and C are Arabic letters and D and E are Latin ones
+    let font =
+        |doc: &mut lopdf::Document| mapped_font(doc, &['\u{633}', '\u{644}', '\u{639}', 'P', 'D']);
+    let content = b"BT /F1 12 Tf 300 700 Td (DE) Tj 0 -14 Td (ABCCBA) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(font, content)).unwrap();
+    assert_eq!(
+        ara_yaaaay::detect_primary_script(&doc).unwrap(),
+        Script::Arabic
+    );
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, b"")).unwrap();
+    assert_eq!(
+        ara_yaaaay::detect_primary_script(&doc).unwrap(),
+        Script::Unknown
+    );
+}
+
+#[test]
+fn figure_alt_text_in_structure() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"/P <</MCID 0>> BDC BT /F1 12 Tf 100 700 Td (Results) Tj ET EMC \
+        /Figure <</MCID 1>> BDC 100 400 200 200 re f EMC";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let para = doc.add_object(dictionary! { "S" => "P", "K" => 0 });
+    let figure = doc.add_object(dictionary! {
+        "S" => "Figure",
+        "K" => 1,
+        "Alt" => Object::string_literal("A bar chart of sales by year"),
+    });
+    let root = doc.add_object(dictionary! {
+        "Type" => "StructTreeRoot",
+        "K" => dictionary! {
+            "S" => "Document",
+            "Pg" => page_id,
+            "K" => vec![Object::Reference(para), Object::Reference(figure)],
+        },
+    });
+    doc.catalog_mut().unwrap().set("StructTreeRoot", root);
+
+    let elements = ara_yaaaay::extract_by_structure(&doc).unwrap();
+    let elements: Vec<_> = elements
+        .iter()
+        .map(|(s, text)| (s.as_str(), text.as_str()))
+        .collect();
+    assert_eq!(
+        elements,
+        [("P", "Results"), ("Figure", "A bar chart of sales by year")]
+    );
+}
+
+#[test]
+fn diff_with_one_word_changed() {
+    use ara_yaaaay::DiffOp;
+
+    let old = b"BT /F1 12 Tf 100 700 Td (the quick fox) Tj 0 -14 Td (jumps) Tj ET";
+    let new = b"BT /F1 12 Tf 100 700 Td (the slow fox) Tj 0 -14 Td (jumps) Tj ET";
+    let doc_a = lopdf::Document::load_mem(&single_page_pdf(helvetica, old)).unwrap();
+    let doc_b = lopdf::Document::load_mem(&single_page_pdf(helvetica, new)).unwrap();
+    let diff = ara_yaaaay::text_diff(&doc_a, &doc_b, 1).unwrap();
+    let ops: Vec<_> = diff
+        .iter()
+        .map(|op| match op {
+            DiffOp::Equal(w) => format!("={}", w.text),
+            DiffOp::Delete(w) => format!("-{}", w.text),
+            DiffOp::Insert(w) => format!("+{}", w.text),
+        })
+        .collect();
+    assert_eq!(ops, ["=the", "-quick", "+slow", "=fox", "=jumps"]);
+    // the inserted word is where it's drawn on the new page
+    match &diff[2] {
+        DiffOp::Insert(w) => assert!(w.bbox[0] > 100. && w.bbox[1] >= 700.),
+        op => panic!("unexpected {:?}", op),
+    }
+}
+
+#[test]
+fn annotation_text_between_paragraphs() {
+    use ara_yaaaay::AnnotationOrder;
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"BT /F1 12 Tf 100 700 Td (first) Tj ET BT /F1 12 Tf 100 600 Td (second) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let resources = doc
+        .get_dictionary(page_id)
+        .unwrap()
+        .get(b"Resources")
+        .unwrap()
+        .clone();
+    let appearance = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 200.into(), 20.into()],
+            "Resources" => resources,
+        },
+        b"BT /F1 12 Tf 2 5 Td (stamp) Tj ET".to_vec(),
+    ));
+    let annot = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "FreeText",
+        "Rect" => vec![100.into(), 640.into(), 300.into(), 660.into()],
+        "AP" => dictionary! { "N" => appearance },
+    });
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    page.set("Annots", vec![Object::Reference(annot)]);
+
+    let text = |order| ara_yaaaay::extract_text_with_annotations(&doc, 1, order).unwrap();
+    assert_eq!(text(AnnotationOrder::Interleave), "first\nstamp\nsecond");
+    assert_eq!(text(AnnotationOrder::Append), "first\nsecond\nstamp");
+}
+
+#[test]
+fn glyph_advance_from_positioning() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use ara_yaaaay::GlyphAdvance;
+
+    // the font says that glyphs are 500 wide but the document moves 600 after every glyph
+    // as if they had no width
+    let content = b"BT /F1 10 Tf 100 700 Td [(A) -600 (B) -600 (C) -1600 (D)] TJ ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(
+        |doc| mapped_font(doc, &['A', 'B', 'C', 'D']),
+        content,
+    ))
+    .unwrap();
+    let text = |advance| {
+        let mut s = String::new();
+        {
+            let mut output = PlainTextOutput::new(&mut s);
+            ara_yaaaay::output_doc_with_glyph_advance(&doc, &mut output, advance).unwrap();
+        }
+        s.trim().to_string()
+    };
+    assert_eq!(text(GlyphAdvance::FontWidths), "A B C D");
+    assert_eq!(text(GlyphAdvance::Positioning), "ABC D");
+}
+
+#[test]
+fn glyph_advance_without_adjustments() {
+    use ara_yaaaay::output::PositionedOutput;
+    use ara_yaaaay::GlyphAdvance;
+
+    // glyphs that aren't followed by an adjustment still advance by the font's widths, and each
+    // glyph is output inside of the word of its own string
+    let content = b"BT /F1 10 Tf 100 700 Td (AB) Tj [(CD) -600 (A)] TJ ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(
+        |doc| mapped_font(doc, &['A', 'B', 'C', 'D']),
+        content,
+    ))
+    .unwrap();
+    let mut output = PositionedOutput::new();
+    ara_yaaaay::output_doc_with_glyph_advance(&doc, &mut output, GlyphAdvance::Positioning)
+        .unwrap();
+    let chars: Vec<_> = output
+        .chars
+        .iter()
+        .map(|c| (c.char.as_str(), c.x, c.width, c.word))
+        .collect();
+    let w = chars[0].2;
+    assert_eq!(
+        chars,
+        [
+            ("A", 100., w, chars[0].3),
+            ("B", 100. + w, w, chars[0].3),
+            ("C", 100. + 2. * w, w, chars[2].3),
+            ("D", 100. + 3. * w, 6., chars[2].3),
+            ("A", 100. + 3. * w + 6., w, chars[4].3),
+        ]
+    );
+    assert!(chars[0].3 != chars[2].3 && chars[2].3 != chars[4].3);
+}
+
+#[test]
+fn type3_glyph_images() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let image = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 1,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0],
+        ));
+        // a glyph space with 100 units per em where the glyph is a scan filling the em
+        let glyph = doc.add_object(Stream::new(
+            dictionary! {},
+            b"100 0 0 0 0 100 d1 q 100 0 0 100 0 0 cm /Scan Do Q".to_vec(),
+        ));
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type3",
+            "FontBBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "FontMatrix" => vec![0.01.into(), 0.into(), 0.into(), 0.01.into(), 0.into(), 0.into()],
+            "CharProcs" => dictionary! { "x" => glyph },
+            "Encoding" => dictionary! {
+                "Type" => "Encoding",
+                "Differences" => vec![97.into(), Object::Name(b"x".to_vec())],
+            },
+            "Resources" => dictionary! { "XObject" => dictionary! { "Scan" => image } },
+            "FirstChar" => 97,
+            "LastChar" => 97,
+            "Widths" => vec![100.into()],
+        }
+    };
+    let content = b"BT /F1 12 Tf 200 500 Td (aa) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let mut images = Images::default();
+    ara_yaaaay::output_doc(&doc, &mut images).unwrap();
+    // FontMatrix entries are stored as f32
+    let round = |v: f64| (v * 1000.).round() / 1000.;
+    let placements: Vec<_> = images
+        .0
+        .iter()
+        .map(|(ctm, _)| {
+            (
+                round(ctm.m11),
+                round(ctm.m22),
+                round(ctm.m31),
+                round(ctm.m32),
+            )
+        })
+        .collect();
+    assert_eq!(placements, [(12., 12., 200., 500.), (12., 12., 212., 500.)]);
+}
+
+#[test]
+fn broken_type3_glyph_keeps_text() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use lopdf::{dictionary, Object, Stream};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let image = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 1,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0],
+        ));
+        let good = doc.add_object(Stream::new(
+            dictionary! {},
+            b"100 0 0 0 0 100 d1 q /Scan Do Q".to_vec(),
+        ));
+        // nests q deeper than the processor allows
+        let broken = doc.add_object(Stream::new(dictionary! {}, b"q ".repeat(5000)));
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type3",
+            "FontBBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "FontMatrix" => vec![0.01.into(), 0.into(), 0.into(), 0.01.into(), 0.into(), 0.into()],
+            "CharProcs" => dictionary! { "x" => good, "y" => broken },
+            "Encoding" => dictionary! {
+                "Type" => "Encoding",
+                "Differences" => vec![97.into(), Object::Name(b"x".to_vec()), Object::Name(b"y".to_vec())],
+            },
+            "Resources" => dictionary! { "XObject" => dictionary! { "Scan" => image } },
+            "FirstChar" => 97,
+            "LastChar" => 98,
+            "Widths" => vec![100.into(), 100.into()],
+        }
+    };
+    let content = b"BT /F1 12 Tf 200 500 Td (abab) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let mut s = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    assert_eq!(s.trim(), "xyxy");
+    let mut images = Images::default();
+    ara_yaaaay::output_doc(&doc, &mut images).unwrap();
+    assert_eq!(images.0.len(), 2);
+}
+
+#[test]
+fn validate_broken_document() {
+    use ara_yaaaay::ValidationIssue;
+    use lopdf::{dictionary, Object};
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (fine) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (fine) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (fine) Tj ET",
+    ];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    assert_eq!(ara_yaaaay::validate(&doc), []);
+
+    let page_ids = doc.get_pages();
+    let pages_id = doc
+        .get_dictionary(page_ids[&1])
+        .unwrap()
+        .get(b"Parent")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    doc.get_object_mut(pages_id)
+        .unwrap()
+        .as_dict_mut()
+        .unwrap()
+        .remove(b"MediaBox");
+    for page in [2, 3] {
+        let page = doc
+            .get_object_mut(page_ids[&page])
+            .unwrap()
+            .as_dict_mut()
+            .unwrap();
+        page.set("MediaBox", vec![0.into(), 0.into(), 612.into(), 792.into()]);
+    }
+    let widthless = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "TrueType",
+        "BaseFont" => "Frutiger",
+    });
+    let identity = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "KozMin",
+        "Encoding" => "Identity-H",
+    });
+    let page = doc
+        .get_object_mut(page_ids[&2])
+        .unwrap()
+        .as_dict_mut()
+        .unwrap();
+    page.set(
+        "Resources",
+        dictionary! { "Font" => dictionary! { "F1" => widthless, "F2" => identity } },
+    );
+    let missing = doc.new_object_id();
+    let page = doc
+        .get_object_mut(page_ids[&3])
+        .unwrap()
+        .as_dict_mut()
+        .unwrap();
+    page.remove(b"Resources");
+    page.set("Contents", vec![Object::Reference(missing)]);
+
+    let issues = ara_yaaaay::validate(&doc);
+    assert_eq!(issues.len(), 5, "{:?}", issues);
+    assert_eq!(issues[0], ValidationIssue::MissingMediaBox { page: 1 });
+    assert_eq!(
+        issues[1],
+        ValidationIssue::FontWithoutWidths {
+            page: 2,
+            font: "Frutiger".into()
+        }
+    );
+    assert_eq!(
+        issues[2],
+        ValidationIssue::FontWithoutUnicode {
+            page: 2,
+            font: "KozMin".into()
+        }
+    );
+    assert!(matches!(
+        issues[3],
+        ValidationIssue::BrokenContent { page: 3, .. }
+    ));
+    assert_eq!(issues[4], ValidationIssue::MissingResources { page: 3 });
+    assert_eq!(
+        issues.iter().map(|i| i.page()).collect::<Vec<_>>(),
+        [Some(1), Some(2), Some(2), Some(3), Some(3)]
+    );
+}
+
+#[test]
+fn progress_after_every_page() {
+    use ara_yaaaay::output::PlainTextOutput;
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (one) Tj ET",
+        b"BT /F1 12 Tf 100 650 Td (two) Tj ET",
+        b"BT /F1 12 Tf 100 600 Td (three) Tj ET",
+    ];
+    let pdf = pdf_with_pages(helvetica, pages);
+    let mut calls = Vec::new();
+    let text = ara_yaaaay::extract_text_from_mem_by_pages_with_progress(&pdf, |page, total| {
+        calls.push((page, total))
+    })
+    .unwrap();
+    assert_eq!(text.len(), 3);
+    assert_eq!(calls, [(1, 3), (2, 3), (3, 3)]);
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let mut s = String::new();
+    let mut count = 0;
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        ara_yaaaay::output_doc_with_progress(&doc, &mut output, |_, _| count += 1).unwrap();
+    }
+    assert_eq!(count, 3);
+}
+
+#[test]
+fn combined_output_options() {
+    use ara_yaaaay::output::PlainTextOutput;
+    use ara_yaaaay::{GlyphAdvance, OutputOptions};
+
+    // the second page only reads as one word when glyphs are positioned by the adjustments
+    let pages: &[&[u8]] = &[
+        b"BT /F1 10 Tf 100 700 Td (AB) Tj ET",
+        b"BT /F1 10 Tf 100 700 Td [(C) -600 (D)] TJ ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(
+        |doc| mapped_font(doc, &['A', 'B', 'C', 'D']),
+        pages,
+    ))
+    .unwrap();
+    let mut s = String::new();
+    let mut calls = Vec::new();
+    let mut unknown = 0;
+    {
+        let mut output = PlainTextOutput::new(&mut s);
+        let mut progress = |done, total| calls.push((done, total));
+        let mut count_unknown = |_: &lopdf::content::Operation| unknown += 1;
+        let options = OutputOptions::new()
+            .reversed(true)
+            .glyph_advance(GlyphAdvance::Positioning)
+            .progress(&mut progress)
+            .unknown_operator(&mut count_unknown);
+        ara_yaaaay::output_doc_with_options(&doc, &mut output, options).unwrap();
+    }
+    let (cd, ab) = (s.find("CD").unwrap(), s.find("AB").unwrap());
+    assert!(cd < ab, "{:?}", s);
+    assert_eq!(calls, [(1, 2), (2, 2)]);
+    assert_eq!(unknown, 0);
+}
+
+#[test]
+fn code_zero_in_to_unicode() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let simple = |doc: &mut lopdf::Document| {
+        let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+            1 begincodespacerange <00> <FF> endcodespacerange \
+            2 beginbfchar <00> <0041> <01> <0042> endbfchar endcmap end end";
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Zero",
+            "FirstChar" => 0,
+            "LastChar" => 1,
+            "Widths" => vec![500.into(), 500.into()],
+            "ToUnicode" => doc.add_object(Stream::new(dictionary! {}, cmap.to_vec())),
+        }
+    };
+    let type3 = |doc: &mut lopdf::Document| {
+        let mut font = simple(doc);
+        font.set("Subtype", "Type3");
+        font.set(
+            "FontBBox",
+            vec![0.into(), 0.into(), 1000.into(), 1000.into()],
+        );
+        font.set(
+            "FontMatrix",
+            vec![
+                0.001.into(),
+                0.into(),
+                0.into(),
+                0.001.into(),
+                0.into(),
+                0.into(),
+            ],
+        );
+        font.set("CharProcs", dictionary! {});
+        font.set(
+            "Encoding",
+            dictionary! { "Differences" => vec![0.into(), Object::Name(b"a".to_vec())] },
+        );
+        font
+    };
+    let cid = |doc: &mut lopdf::Document| {
+        let mut font = cid_font(doc, dictionary! {});
+        let cmap = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap \
+            1 begincodespacerange <0000> <FFFF> endcodespacerange \
+            2 beginbfchar <0000> <0041> <0001> <0042> endbfchar endcmap end end";
+        font.set(
+            "ToUnicode",
+            doc.add_object(Stream::new(dictionary! {}, cmap.to_vec())),
+        );
+        font
+    };
+    let text = |pdf: Vec<u8>| {
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let chars = chars_text(&ara_yaaaay::positioned_chars(&doc, 1).unwrap());
+        assert_eq!(
+            ara_yaaaay::extract_text_from_mem(&pdf).unwrap().trim(),
+            chars
+        );
+        chars
+    };
+    let content = b"BT /F1 12 Tf 100 700 Td <000100> Tj ET";
+    assert_eq!(text(single_page_pdf(simple, content)), "ABA");
+    assert_eq!(text(single_page_pdf(type3, content)), "ABA");
+    let content = b"BT /F1 12 Tf 100 700 Td <000000010000> Tj ET";
+    assert_eq!(text(single_page_pdf(cid, content)), "ABA");
+}
+
+#[test]
+fn reading_order_dump() {
+    use ara_yaaaay::output::ReadingOrderOutput;
+
+    let pages: &[&[u8]] = &[
+        b"BT /F1 12 Tf 100 700 Td (first line) Tj 0 -20 Td (second) Tj ET",
+        b"BT /F1 12 Tf 100 700 Td (next page) Tj ET",
+    ];
+    let doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let dump = |words| {
+        let mut out = Vec::new();
+        {
+            let mut output = ReadingOrderOutput::new(&mut out, words);
+            ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    };
+    let lines = dump(false);
+    let lines: Vec<&str> = lines.lines().collect();
+    assert_eq!(lines[0], "page 1");
+    assert!(lines[1].starts_with("0 [100.0 "), "{}", lines[1]);
+    assert!(lines[1].ends_with("] first line"));
+    assert!(lines[2].starts_with("1 [100.0 "));
+    assert!(lines[2].ends_with("] second"));
+    assert_eq!(lines[3], "page 2");
+    assert!(lines[4].starts_with("2 ["));
+    assert!(lines[4].ends_with("] next page"));
+
+    let words = dump(true);
+    let numbers: Vec<usize> = words
+        .lines()
+        .filter(|l| !l.starts_with("page"))
+        .map(|l| l.split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(numbers, [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn rotated_form_matrix() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"q 1 0 0 1 300 400 cm /Fm0 Do Q";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let (cos, sin) = (45f64.to_radians().cos(), 45f64.to_radians().sin());
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Matrix" => vec![cos.into(), sin.into(), (-sin).into(), cos.into(), 0.into(), 0.into()],
+        },
+        b"BT /F1 12 Tf 10 0 Td (ab) Tj ET".to_vec(),
+    ));
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("XObject", dictionary! { "Fm0" => Object::Reference(form) });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    assert_eq!(chars_text(&chars), "ab");
+    // the Matrix entries are stored as f32
+    let round = |v: f64| (v * 100.).round() / 100.;
+    for c in &chars {
+        assert_eq!(round(c.rotation), 45.);
+        assert_eq!(round(c.font_size), 12.);
+    }
+    assert_eq!((round(chars[0].x), round(chars[0].y)), (307.07, 407.07));
+    // the second glyph follows the first along the rotated baseline
+    let advance = 0.556 * 12.;
+    assert_eq!(
+        (round(chars[1].x), round(chars[1].y)),
+        (
+            round(300. + (10. + advance) * cos),
+            round(400. + (10. + advance) * sin)
+        )
+    );
+}
+
+#[test]
+fn bbox_of_rotated_characters() {
+    // H is 722 units wide in Helvetica and is drawn turned a quarter counterclockwise
+    let content = b"BT /F1 12 Tf 0 1 -1 0 300 400 Tm (H) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let round = |v: f64| (v * 1000.).round() / 1000.;
+    let (llx, lly, urx, ury) = chars[0].bbox();
+    assert_eq!(
+        (round(llx), round(lly), round(urx), round(ury)),
+        (288., 400., 300., 408.664)
+    );
+    assert!(chars[0].inside(&[285., 400., 300., 410.]));
+    let records = ara_yaaaay::extract_records(&doc).unwrap();
+    assert_eq!(round(records[0].height), 8.664);
+}
+
+#[test]
+fn records_of_a_page() {
+    let content = b"BT /F1 12 Tf 100 700 Td (Hi) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let records = ara_yaaaay::extract_records(&doc).unwrap();
+    assert_eq!(records.len(), 2);
+    let text: String = records.iter().map(|r| r.text.as_str()).collect();
+    assert_eq!(text, "Hi");
+    assert_eq!(records[0].page, 1);
+    assert_eq!(records[0].font_name, "Helvetica");
+    assert_eq!(
+        (records[0].x, records[0].y, records[0].font_size),
+        (100., 700., 12.)
+    );
+    assert_eq!(records[0].height, 12.);
+    assert!(records[1].x > records[0].x);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn records_serialize_to_json() {
+    let content = b"BT /F1 12 Tf 100 700 Td (Hi) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let records = ara_yaaaay::extract_records(&doc).unwrap();
+    let json = serde_json::to_value(&records).unwrap();
+    assert_eq!(
+        json[1],
+        serde_json::json!({
+            "page": 1,
+            "text": "i",
+            "x": records[1].x,
+            "y": 700.0,
+            "width": records[1].width,
+            "height": 12.0,
+            "font_name": "Helvetica",
+            "font_size": 12.0,
+        })
+    );
+}
+
+#[test]
+fn gray_color_operators() {
+    let content = b"BT /F1 12 Tf 100 700 Td 0.5 g (a) Tj 2 g (b) Tj -1 g (c) Tj g (d) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    // out of range levels are clamped and a missing one draws black
+    assert_eq!(
+        colors,
+        [[0.5, 0.5, 0.5], [1., 1., 1.], [0., 0., 0.], [0., 0., 0.]]
+    );
+}
+
+#[test]
+fn rgb_fill_in_svg() {
+    use ara_yaaaay::output::SVGOutput;
+
+    let content =
+        b"1 0 0 rg 0 0 10 10 re f 0 0.5 1 rg 0.2 g 20 20 10 10 re f 1 1 rg 40 40 10 10 re f";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut svg = Vec::new();
+    {
+        let mut output = SVGOutput::new(&mut svg);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let svg = String::from_utf8(svg).unwrap();
+    let fills: Vec<_> = svg
+        .match_indices("fill='")
+        .map(|(i, _)| &svg[i + 6..i + svg[i..].find(")'").unwrap() + 1])
+        .collect();
+    // the malformed rg is ignored, leaving the gray
+    assert_eq!(
+        fills,
+        ["rgb(255, 0, 0)", "rgb(51, 51, 51)", "rgb(51, 51, 51)"]
+    );
+}
+
+#[test]
+fn rtl_layout_phone_number() {
+    // A and B are seen and lam, C and D ain and yeh, E to I are the digits 0, 5, 1, 2 and 3
+    let make_font = |doc: &mut lopdf::Document| {
+        mapped_font(
+            doc,
+            &[
+                '\u{633}', '\u{644}', '\u{639}', '\u{64a}', '0', '5', '1', '2', '3',
+            ],
+        )
+    };
+    // the right column has a word followed by a phone number drawn as two groups, from left
+    // to right, and the left column has another word
+    let content = b"BT /F1 12 Tf 470 700 Td (AB) Tj -70 0 Td (EFF) Tj 24 0 Td (GHI) Tj \
+        -324 0 Td (CD) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(make_font, content)).unwrap();
+    let text = ara_yaaaay::extract_text_rtl_layout(&doc, 1).unwrap();
+    let (ab, cd) = ("\u{633}\u{644}", "\u{639}\u{64a}");
+    assert_eq!(
+        text.split_whitespace().collect::<Vec<_>>(),
+        [ab, "055", "123", cd]
+    );
+    // the groups of the number stay together and the left column is still apart from them
+    assert!(text.contains("055 123"), "{:?}", text);
+    assert!(text.contains("123 "));
+}
+
+#[test]
+fn confidence_from_widths() {
+    let confidence = |content: &[u8]| {
+        let pdf = single_page_pdf(|doc| mapped_font(doc, &['A', 'B', 'C', 'D']), content);
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        ara_yaaaay::text_layer_confidence(&doc, 1).unwrap()
+    };
+    // kerned glyphs that follow on from the widths of the font
+    assert_eq!(
+        confidence(b"BT /F1 10 Tf 100 700 Td [(A) 20 (B) -10 (C) 20 (D)] TJ ET"),
+        1.
+    );
+    // the font says the glyphs are 500 wide but they were placed as if they were 300 wide
+    assert_eq!(
+        confidence(b"BT /F1 10 Tf 100 700 Td [(A) 200 (B) 200 (C) 200 (D)] TJ ET"),
+        0.
+    );
+    // a space between words doesn't count against the page
+    assert_eq!(
+        confidence(b"BT /F1 10 Tf 100 700 Td [(AB) -3000 (CD)] TJ ET"),
+        1.
+    );
+}
+
+#[test]
+fn cmyk_fill_in_svg() {
+    use ara_yaaaay::output::SVGOutput;
+
+    let content = b"0 1 1 0 k 0 0 10 10 re f 0 0 0 1 k 20 20 10 10 re f 1 0 0 k 40 40 10 10 re f";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut svg = Vec::new();
+    {
+        let mut output = SVGOutput::new(&mut svg);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let svg = String::from_utf8(svg).unwrap();
+    let fills: Vec<_> = svg
+        .match_indices("fill='")
+        .map(|(i, _)| &svg[i + 6..i + svg[i..].find(")'").unwrap() + 1])
+        .collect();
+    // the k with three operands is ignored, leaving the black
+    assert_eq!(fills, ["rgb(255, 0, 0)", "rgb(0, 0, 0)", "rgb(0, 0, 0)"]);
+}
+
+#[test]
+fn colorspace_to_rgb() {
+    use ara_yaaaay::ColorSpace;
+    assert_eq!(ColorSpace::DeviceGray.to_rgb(&[0.25]), (0.25, 0.25, 0.25));
+    assert_eq!(ColorSpace::DeviceRGB.to_rgb(&[1., 0.5, 0.]), (1., 0.5, 0.));
+    assert_eq!(
+        ColorSpace::DeviceCMYK.to_rgb(&[0., 1., 1., 0.]),
+        (1., 0., 0.)
+    );
+    assert_eq!(
+        ColorSpace::DeviceCMYK.to_rgb(&[0.5, 0., 0., 0.5]),
+        (0., 0.5, 0.5)
+    );
+    // the wrong number of components and patterns come out black
+    assert_eq!(ColorSpace::DeviceRGB.to_rgb(&[1.]), (0., 0., 0.));
+    assert_eq!(ColorSpace::Pattern.to_rgb(&[]), (0., 0., 0.));
+}
+
+// A one page linearized file: the linearization dictionary and first-page xref come first and
+// the trailing startxref points back at that xref, whose /Prev is the main xref. `skew` is
+// added to the offsets in the first-page xref to damage it.
+fn linearized_pdf(content: &[u8], skew: usize) -> Vec<u8> {
+    let objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 3 0 R >>".to_vec(),
+        b"<< /Type /Pages /Kids [4 0 R] /Count 1 /MediaBox [0 0 612 792] >>".to_vec(),
+        b"<< /Type /Page /Parent 3 0 R /Contents 6 0 R /Resources << /Font << /F1 5 0 R >> >> >>"
+            .to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec(),
+        [
+            format!("<< /Length {} >>\nstream\n", content.len()).as_bytes(),
+            content,
+            b"\nendstream",
+        ]
+        .concat(),
+        // a hint stream that doesn't decode
+        b"<< /Length 4 /Filter /FlateDecode >>\nstream\njunk\nendstream".to_vec(),
+    ];
+    let mut pdf = b"%PDF-1.5\n".to_vec();
+    let first_obj = pdf.len();
+    pdf.extend_from_slice(
+        b"1 0 obj\n<< /Linearized 1 /L 0 /H [0 0] /O 4 /E 0 /N 1 /T 0 >>\nendobj\n",
+    );
+    // the first-page xref has fixed width offsets so we can lay out the objects after it
+    let xref_pos = pdf.len();
+    let xref_len = "xref\n0 8\n".len() + 8 * 20;
+    let mut offsets = vec![first_obj];
+    let trailer_placeholder = format!("trailer\n<< /Size 8 /Root 2 0 R /Prev {:010} >>\n", 0);
+    let mut body = Vec::new();
+    let body_start = xref_pos + xref_len + trailer_placeholder.len();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(body_start + body.len());
+        body.extend_from_slice(format!("{} 0 obj\n", i + 2).as_bytes());
+        body.extend_from_slice(object);
+        body.extend_from_slice(b"\nendobj\n");
+    }
+    let main_xref = body_start + body.len();
+    pdf.extend_from_slice(b"xref\n0 8\n0000000000 65535 f\r\n");
+    for (i, offset) in offsets.iter().enumerate() {
+        let offset = if i == 0 { *offset } else { offset + skew };
+        pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size 8 /Root 2 0 R /Prev {:010} >>\n",
+            main_xref
+        )
+        .as_bytes(),
+    );
+    pdf.extend_from_slice(&body);
+    pdf.extend_from_slice(b"xref\n0 1\n0000000000 65535 f\r\ntrailer\n<< /Size 8 >>\n");
+    pdf.extend_from_slice(format!("startxref\n{}\n%%EOF\n", xref_pos).as_bytes());
+    pdf
+}
+
+#[test]
+fn linearized_matches_plain() {
+    let content = b"BT /F1 12 Tf 100 700 Td (web optimized) Tj ET";
+    let plain = ara_yaaaay::extract_text_from_mem(&single_page_pdf(helvetica, content)).unwrap();
+    assert_eq!(plain.trim(), "web optimized");
+
+    let linearized = ara_yaaaay::extract_text_from_mem(&linearized_pdf(content, 0)).unwrap();
+    assert_eq!(linearized, plain);
+    // with the first-page xref pointing into the middle of the objects we parse the whole file
+    let damaged = linearized_pdf(content, 3);
+    assert!(lopdf::Document::load_mem(&damaged).is_ok());
+    let out = ara_yaaaay::extract_text_from_mem(&damaged).unwrap();
+    assert_eq!(out, plain);
+}
+
+#[test]
+fn paragraphs_from_lines() {
+    let content = b"BT /F1 18 Tf 72 720 Td (Heading) Tj \
+        /F1 12 Tf 0 -30 Td (first line) Tj 0 -14 Td (second line) Tj 0 -14 Td (third line) Tj \
+        0 -40 Td (after a gap) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let paragraphs = ara_yaaaay::extract_paragraphs(&doc).unwrap();
+    assert_eq!(
+        paragraphs,
+        [
+            "Heading",
+            "first line second line third line",
+            "after a gap"
+        ]
+    );
+}
+
+#[test]
+fn separation_tint_transforms() {
+    use ara_yaaaay::{ColorSpace, MediaBox, OutputError, Path, Transform};
+    use lopdf::{dictionary, Object, Stream};
+
+    struct Tints(Vec<Vec<f64>>);
+    impl ara_yaaaay::output::OutputDev for Tints {
+        fn begin_page(
+            &mut self,
+            _: u32,
+            _: &MediaBox,
+            _: Option<(f64, f64, f64, f64)>,
+        ) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_page(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn output_character(
+            &mut self,
+            _: &Transform,
+            _: f64,
+            _: f64,
+            _: f64,
+            _: &str,
+        ) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn begin_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_word(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn end_line(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+        fn fill(
+            &mut self,
+            _: &Transform,
+            colorspace: &ColorSpace,
+            color: &[f64],
+            _: &Path,
+        ) -> Result<(), OutputError> {
+            if let ColorSpace::Separation(separation) = colorspace {
+                self.0.push(separation.tint_to_alternate(color));
+            }
+            Ok(())
+        }
+    }
+
+    let content = b"/CS0 cs 0.5 scn 0 0 10 10 re f /CS1 cs 0.5 scn 0 0 10 10 re f \
+        0.2 scn 0 0 10 10 re f";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let type2 = dictionary! {
+        "FunctionType" => 2,
+        "Domain" => vec![0.into(), 1.into()],
+        "C0" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        "C1" => vec![0.into(), 1.into(), 0.75.into(), 0.into()],
+        "N" => 1,
+    };
+    let type4 = doc.add_object(Stream::new(
+        dictionary! {
+            "FunctionType" => 4,
+            "Domain" => vec![0.into(), 1.into()],
+            "Range" => vec![0.into(), 1.into(), 0.into(), 1.into(), 0.into(), 1.into()],
+        },
+        b"{ dup 0.4 gt { 2 div } { pop 0 } ifelse dup dup 1 exch sub }".to_vec(),
+    ));
+    let cmyk: Vec<Object> = vec![
+        "Separation".into(),
+        "Spot".into(),
+        "DeviceCMYK".into(),
+        type2.into(),
+    ];
+    let rgb: Vec<Object> = vec![
+        "Separation".into(),
+        "Other".into(),
+        "DeviceRGB".into(),
+        type4.into(),
+    ];
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => cmyk, "CS1" => rgb });
+
+    let mut tints = Tints(Vec::new());
+    ara_yaaaay::output_doc(&doc, &mut tints).unwrap();
+    assert_eq!(
+        tints.0,
+        [
+            vec![0., 0.5, 0.375, 0.],
+            vec![0.25, 0.25, 0.75],
+            vec![0., 0., 1.]
+        ]
+    );
+}
+
+#[test]
+fn postscript_function_squares() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"/CS0 cs 0.5 scn BT /F1 12 Tf 100 700 Td (a) Tj ET \
+        3 scn BT /F1 12 Tf 110 700 Td (b) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let square = doc.add_object(Stream::new(
+        dictionary! {
+            "FunctionType" => 4,
+            "Domain" => vec![0.into(), 2.into()],
+            "Range" => vec![0.into(), 1.into()],
+        },
+        b"{dup mul}".to_vec(),
+    ));
+    let separation: Vec<Object> = vec![
+        "Separation".into(),
+        "Spot".into(),
+        "DeviceGray".into(),
+        square.into(),
+    ];
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => separation });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    // 3 is clamped to the domain and its square of 4 to the range
+    assert_eq!(colors, vec![[0.25, 0.25, 0.25], [1., 1., 1.]]);
+}
+
+#[test]
+fn postscript_function_out_of_range_operands() {
+    use lopdf::{dictionary, Object, Stream};
+
+    let content = b"/CS0 cs 0.5 scn BT /F1 12 Tf 100 700 Td (a) Tj ET \
+        /CS1 cs 0.5 scn BT /F1 12 Tf 110 700 Td (b) Tj ET \
+        /CS2 cs 0.5 scn BT /F1 12 Tf 120 700 Td (c) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let function = |doc: &mut lopdf::Document, program: &[u8]| {
+        doc.add_object(Stream::new(
+            dictionary! {
+                "FunctionType" => 4,
+                "Domain" => vec![0.into(), 1.into()],
+                "Range" => vec![0.into(), 1.into()],
+            },
+            program.to_vec(),
+        ))
+    };
+    // shifts past the width of the integer give 0, as does the shift of -2147483648
+    let shifts = function(
+        &mut doc,
+        b"{ pop 1 100 bitshift 1 -2147483648 bitshift add 1 2 bitshift 8 div add }",
+    );
+    let index = function(&mut doc, b"{ 1e30 index }");
+    // a calculator function has to be a stream
+    let not_a_stream = dictionary! {
+        "FunctionType" => 4,
+        "Domain" => vec![0.into(), 1.into()],
+        "Range" => vec![0.into(), 1.into()],
+    };
+    let separation = |function: Object| -> Object {
+        vec![
+            "Separation".into(),
+            "Spot".into(),
+            "DeviceGray".into(),
+            function,
+        ]
+        .into()
+    };
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set(
+        "ColorSpace",
+        dictionary! {
+            "CS0" => separation(shifts.into()),
+            "CS1" => separation(index.into()),
+            "CS2" => separation(not_a_stream.into()),
+        },
+    );
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    // functions that can't be evaluated draw black
+    assert_eq!(colors, [[0.5, 0.5, 0.5], [0., 0., 0.], [0., 0., 0.]]);
+}
+
+#[test]
+fn ligature_glyph_names_in_differences() {
+    use lopdf::{dictionary, Object};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let differences: Vec<Object> = vec![
+            65.into(),
+            Object::Name(b"f_f_i".to_vec()),
+            Object::Name(b"T_h".to_vec()),
+            Object::Name(b"uni00660069".to_vec()),
+            Object::Name(b"a.sc".to_vec()),
+        ];
+        let encoding = doc.add_object(dictionary! {
+            "Type" => "Encoding",
+            "Differences" => differences,
+        });
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => encoding,
+        }
+    };
+    let content = b"BT /F1 12 Tf 100 700 Td (ABCDE) Tj ET";
+    let out = ara_yaaaay::extract_text_from_mem(&single_page_pdf(make_font, content)).unwrap();
+    assert_eq!(out.trim(), "ffiThfiaE");
+}
+
+#[test]
+fn supplementary_plane_glyph_names() {
+    use lopdf::{dictionary, Object};
+
+    let make_font = |doc: &mut lopdf::Document| {
+        let differences: Vec<Object> = vec![
+            65.into(),
+            Object::Name(b"u1D400".to_vec()),
+            Object::Name(b"u1F600".to_vec()),
+            Object::Name(b"f_i".to_vec()),
+        ];
+        let encoding = doc.add_object(dictionary! {
+            "Type" => "Encoding",
+            "BaseEncoding" => "WinAnsiEncoding",
+            "Differences" => differences,
+        });
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => encoding,
+        }
+    };
+    let content = b"BT /F1 12 Tf 100 700 Td (ABCD) Tj ET";
+    let pdf = single_page_pdf(make_font, content);
+    let out = ara_yaaaay::extract_text_from_mem(&pdf).unwrap();
+    assert_eq!(out.trim(), "\u{1D400}\u{1F600}fiD");
+    // each code is still a single character
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let chars: Vec<_> = chars.iter().map(|c| c.char.as_str()).collect();
+    assert_eq!(chars, ["\u{1D400}", "\u{1F600}", "fi", "D"]);
+}
+
+#[test]
+fn outline_with_destination_views() {
+    use ara_yaaaay::{DestinationView, OutlineItem};
+    use lopdf::{dictionary, Object};
+
+    let pages: &[&[u8]] = &[b"", b""];
+    let mut doc = lopdf::Document::load_mem(&pdf_with_pages(helvetica, pages)).unwrap();
+    let page_ids = doc.get_pages();
+    let outlines = doc.new_object_id();
+    let (intro, section, detail) = (
+        doc.new_object_id(),
+        doc.new_object_id(),
+        doc.new_object_id(),
+    );
+    let xyz: Vec<Object> = vec![
+        page_ids[&1].into(),
+        "XYZ".into(),
+        72.into(),
+        400.5.into(),
+        Object::Null,
+    ];
+    doc.objects.insert(
+        intro,
+        dictionary! {
+            "Title" => Object::string_literal("Intro"),
+            "Parent" => outlines,
+            "Next" => section,
+            "Dest" => xyz,
+        }
+        .into(),
+    );
+    doc.objects.insert(
+        section,
+        dictionary! {
+            "Title" => Object::string_literal("Section"),
+            "Parent" => outlines,
+            "Prev" => intro,
+            "First" => detail,
+            "Last" => detail,
+            "A" => dictionary! { "S" => "GoTo", "D" => Object::string_literal("sec") },
+        }
+        .into(),
+    );
+    let fit_h: Vec<Object> = vec![page_ids[&2].into(), "FitH".into(), 500.into()];
+    doc.objects.insert(
+        detail,
+        dictionary! {
+            "Title" => Object::string_literal("Detail"),
+            "Parent" => section,
+            "Dest" => fit_h,
+        }
+        .into(),
+    );
+    doc.objects.insert(
+        outlines,
+        dictionary! { "Type" => "Outlines", "First" => intro, "Last" => section }.into(),
+    );
+    let sec: Vec<Object> = vec![page_ids[&2].into(), "Fit".into()];
+    let catalog = doc.catalog_mut().unwrap();
+    catalog.set("Outlines", outlines);
+    catalog.set(
+        "Names",
+        dictionary! { "Dests" => dictionary! {
+            "Names" => vec![Object::string_literal("sec"), sec.into()],
+        } },
+    );
+
+    let item = |title: &str, level, page, view| OutlineItem {
+        title: title.to_owned(),
+        level,
+        page: Some(page),
+        view: Some(view),
+    };
+    assert_eq!(
+        ara_yaaaay::outline(&doc),
+        [
+            item(
+                "Intro",
+                0,
+                1,
+                DestinationView::XYZ {
+                    left: Some(72.),
+                    top: Some(400.5),
+                    zoom: None
+                }
+            ),
+            item("Section", 0, 2, DestinationView::Fit),
+            item("Detail", 1, 2, DestinationView::FitH { top: Some(500.) }),
+        ]
+    );
+}
+
+#[test]
+fn stitching_function_tints() {
+    use lopdf::{dictionary, Object};
+
+    let content = b"/CS0 cs 0.25 scn BT /F1 12 Tf 100 700 Td (a) Tj ET \
+        0.875 scn BT /F1 12 Tf 110 700 Td (b) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let ramp = |c0: Object, c1: Object| {
+        dictionary! {
+            "FunctionType" => 2,
+            "Domain" => vec![0.into(), 1.into()],
+            "C0" => vec![c0],
+            "C1" => vec![c1],
+            "N" => 1,
+        }
+    };
+    // white to black over each half, with the second half reversed
+    let stitched = dictionary! {
+        "FunctionType" => 3,
+        "Domain" => vec![0.into(), 1.into()],
+        "Functions" => vec![ramp(1.into(), 0.into()).into(), ramp(1.into(), 0.into()).into()],
+        "Bounds" => vec![0.5.into()],
+        "Encode" => vec![0.into(), 1.into(), 1.into(), 0.into()],
+    };
+    let separation: Vec<Object> = vec![
+        "Separation".into(),
+        "Spot".into(),
+        "DeviceGray".into(),
+        stitched.into(),
+    ];
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => separation });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    assert_eq!(colors, vec![[0.5, 0.5, 0.5], [0.75, 0.75, 0.75]]);
+}
+
+#[test]
+fn numbers_written_as_strings() {
+    let content = b"BT /F1 (12) Tf (100) ( 700 ) Td (hi) Tj ET";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let chars: Vec<_> = chars
+        .iter()
+        .map(|c| (c.char.as_str(), c.font_size, c.x.round(), c.y.round()))
+        .collect();
+    assert_eq!(chars, [("h", 12., 100., 700.), ("i", 12., 107., 700.)]);
+}
+
+#[test]
+fn indexed_rgb_palette() {
+    use lopdf::{dictionary, Object, StringFormat};
+
+    let content = b"/CS0 cs 0 scn BT /F1 12 Tf 100 700 Td (a) Tj ET \
+        2 scn BT /F1 12 Tf 110 700 Td (b) Tj ET \
+        7 scn BT /F1 12 Tf 120 700 Td (c) Tj ET";
+    let mut doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let palette = vec![255, 0, 0, 0, 255, 0, 0, 0, 255];
+    let indexed: Vec<Object> = vec![
+        "Indexed".into(),
+        "DeviceRGB".into(),
+        2.into(),
+        Object::String(palette, StringFormat::Hexadecimal),
+    ];
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_object_mut(page_id).unwrap().as_dict_mut().unwrap();
+    let resources = page.get_mut(b"Resources").unwrap().as_dict_mut().unwrap();
+    resources.set("ColorSpace", dictionary! { "CS0" => indexed });
+
+    let chars = ara_yaaaay::positioned_chars(&doc, 1).unwrap();
+    let colors: Vec<_> = chars.iter().map(|c| c.color).collect();
+    // indices past hival use the last entry
+    assert_eq!(colors, vec![[1., 0., 0.], [0., 0., 1.], [0., 0., 1.]]);
+}
+
+#[test]
+fn svg_of_red_rectangle() {
+    use ara_yaaaay::output::SVGOutput;
+
+    let content = b"1 0 0 rg 10 20 30 40 re f 0 0 1 rg 0 0 m 10 0 l 10 10 l h f*";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut svg = Vec::new();
+    {
+        let mut output = SVGOutput::new(&mut svg);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let expected = concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n",
+        r#"<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd">"#,
+        r#"<svg width="612" height="792" xmlns="http://www.w3.org/2000/svg" version="1.1" viewBox='0 0 612 792'>"#,
+        "\n<g transform='matrix(1, 0, 0, -1, 0, 792)'>\n",
+        "<g transform='matrix(1, 0, 0, 1, 0, 0)'>",
+        "<path d='M10 20 L40 20 L40 60 L10 60 Z' fill='rgb(255, 0, 0)' /></g>\n",
+        "<g transform='matrix(1, 0, 0, 1, 0, 0)'>",
+        "<path d='M0 0 L10 0 L10 10 Z' fill='rgb(0, 0, 255)' fill-rule='evenodd' /></g>\n",
+        "</g>\n</svg>",
+    );
+    assert_eq!(String::from_utf8(svg).unwrap(), expected);
+}
+
+#[test]
+fn even_odd_fills_through_a_tee() {
+    use ara_yaaaay::output::{SVGOutput, TeeOutput};
+
+    let content = b"0 0 m 10 0 l 10 10 l B* 20 0 m 30 0 l 30 10 l b*";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+    {
+        let mut output = TeeOutput::new(vec![
+            Box::new(SVGOutput::new(&mut first)),
+            Box::new(SVGOutput::new(&mut second)),
+        ]);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    for svg in [first, second] {
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(
+            svg.contains("<path d='M0 0 L10 0 L10 10' fill='rgb(0, 0, 0)' fill-rule='evenodd' />"),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(
+                "<path d='M20 0 L30 0 L30 10 Z' fill='rgb(0, 0, 0)' fill-rule='evenodd' />"
+            ),
+            "{}",
+            svg
+        );
+    }
+}
+
+#[test]
+fn svg_of_stroked_line() {
+    use ara_yaaaay::output::SVGOutput;
+
+    // the width is in user space, which the group's transform scales like the path
+    let content = b"2 0 0 2 0 0 cm 3 w 0 1 0 RG 10 20 m 30 40 l S";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut svg = Vec::new();
+    {
+        let mut output = SVGOutput::new(&mut svg);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(
+        svg.contains(
+            "<g transform='matrix(2, 0, 0, 2, 0, 0)'>\
+             <path d='M10 20 L30 40' fill='none' stroke='rgb(0, 255, 0)' stroke-width='3' /></g>\n"
+        ),
+        "{}",
+        svg
+    );
+}
+
+#[test]
+fn svg_of_closed_and_filled_strokes() {
+    use ara_yaaaay::output::SVGOutput;
+
+    // s closes and strokes, b closes, fills and strokes, and neither leaves its path behind for
+    // the S after them. A width of 0 still draws a hairline.
+    let content = b"1 0 0 RG 0 w 0 0 m 10 0 l 10 10 l s \
+        0 0 1 rg 20 0 m 30 0 l 30 10 l b 40 0 m 50 0 l S";
+    let doc = lopdf::Document::load_mem(&single_page_pdf(helvetica, content)).unwrap();
+    let mut svg = Vec::new();
+    {
+        let mut output = SVGOutput::new(&mut svg);
+        ara_yaaaay::output_doc(&doc, &mut output).unwrap();
+    }
+    let svg = String::from_utf8(svg).unwrap();
+    let paths: Vec<&str> = svg
+        .lines()
+        .filter_map(|line| line.split_once("<path ").map(|(_, path)| path))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "d='M0 0 L10 0 L10 10 Z' fill='none' stroke='rgb(255, 0, 0)' stroke-width='0.5' /></g>",
+            "d='M20 0 L30 0 L30 10 Z' fill='rgb(0, 0, 255)' /></g>",
+            "d='M20 0 L30 0 L30 10 Z' fill='none' stroke='rgb(255, 0, 0)' stroke-width='0.5' /></g>",
+            "d='M40 0 L50 0' fill='none' stroke='rgb(255, 0, 0)' stroke-width='0.5' /></g>",
+        ]
+    );
+}